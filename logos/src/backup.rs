@@ -0,0 +1,599 @@
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::driver::{self, Driver, SST_EXTENSION};
+use crate::env::{FileSystem, WritableFile};
+use crate::handle::Db;
+use crate::manifest::MANIFEST;
+use crate::options::BackupOptions;
+use crate::value_log::VALUE_LOG_EXTENSION;
+use crate::Error;
+
+const SHARED: &str = "shared";
+const PRIVATE: &str = "private";
+const META: &str = "meta";
+const TMP_EXTENSION: &str = "tmp";
+const NEXT_ID: &str = "NEXT_ID";
+/// Files are checksummed and copied this many bytes at a time.
+const CHUNK_SIZE: u64 = 1 << 20;
+
+/// A repository holding any number of backups of a database.
+///
+/// Each backup starts as a checkpoint written into `private/{id}`. Its
+/// SSTables and value logs, which never change once written, then move to
+/// `shared/` under a name that includes their checksum and size, so a file
+/// already there from an earlier backup is kept only once. A table or value
+/// log with the same name and size as a shared file is taken to be that file
+/// and not read again. The manifest and WAL segments stay private to the
+/// backup. A backup exists once its metadata is written to `meta/{id}`;
+/// anything a crash left behind before that is removed when the repository is
+/// next opened. Ids are never reused, even those of deleted backups.
+///
+/// Checkpoints hard-link tables where they can, so on the same device as the
+/// database a backup shares its tables' storage rather than copying it.
+pub struct BackupEngine {
+    dir: PathBuf,
+    fs: Arc<dyn FileSystem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub id: u64,
+    pub timestamp: SystemTime,
+    /// Number of files restoring the backup writes.
+    pub files: usize,
+    /// Total size of those files, in bytes, shared ones included.
+    pub size: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct BackupMeta {
+    id: u64,
+    /// Seconds since the unix epoch.
+    timestamp: u64,
+    files: Vec<BackupFile>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct BackupFile {
+    /// The file's name in the database directory.
+    name: String,
+    /// Where the file is kept, relative to the repository.
+    location: PathBuf,
+    size: u64,
+    checksum: u32,
+}
+
+impl BackupMeta {
+    fn info(&self) -> BackupInfo {
+        BackupInfo {
+            id: self.id,
+            timestamp: UNIX_EPOCH + Duration::from_secs(self.timestamp),
+            files: self.files.len(),
+            size: self.files.iter().map(|f| f.size).sum(),
+        }
+    }
+}
+
+impl BackupEngine {
+    pub fn open<P: Into<PathBuf>>(dir: P) -> Result<Self, Error> {
+        Self::open_with_options(dir, BackupOptions::default())
+    }
+
+    pub fn open_with_options<P: Into<PathBuf>>(
+        dir: P,
+        options: BackupOptions,
+    ) -> Result<Self, Error> {
+        let engine = Self {
+            dir: dir.into(),
+            fs: options.file_system,
+        };
+        driver::create_dir(&*engine.fs, &engine.dir, true)?;
+        for sub in [SHARED, PRIVATE, META] {
+            driver::create_dir(&*engine.fs, &engine.dir.join(sub), true)?;
+        }
+        engine.collect_garbage()?;
+        Ok(engine)
+    }
+
+    /// Backs up the database, returning the new backup.
+    pub fn create_backup(&self, driver: &Driver) -> Result<BackupInfo, Error> {
+        self.create_backup_with(|dir| driver.create_checkpoint(dir))
+    }
+
+    /// Backs up a database shared through `Db`, holding off its writes only
+    /// while the checkpoint is taken.
+    pub fn create_db_backup(&self, db: &Db) -> Result<BackupInfo, Error> {
+        self.create_backup_with(|dir| db.checkpoint(dir))
+    }
+
+    /// Backs up the database `checkpoint` writes to the directory it is given.
+    pub(crate) fn create_backup_with(
+        &self,
+        checkpoint: impl FnOnce(&Path) -> Result<(), Error>,
+    ) -> Result<BackupInfo, Error> {
+        let id = self.allocate_id()?;
+        let private = self.private_dir(id);
+        // Left by a backup that failed since the repository was opened.
+        if self.fs.exists(&private) {
+            self.remove_private_dir(&private)?;
+        }
+        checkpoint(&private)?;
+
+        let shared = self.shared_files()?;
+        let mut files = Vec::new();
+        let mut paths = self.fs.list_dir(&private)?;
+        paths.sort();
+        for path in paths {
+            let name = file_name(&path);
+            let size = self.fs.open(&path)?.len()?;
+            let extension = path.extension().and_then(|e| e.to_str());
+            let shareable = matches!(extension, Some(SST_EXTENSION | VALUE_LOG_EXTENSION));
+            let file = match shared.get(&(name.clone(), size)) {
+                Some(file) if shareable => {
+                    self.fs.remove_file(&path)?;
+                    file.clone()
+                }
+                _ => {
+                    let (_, checksum) = self.stream(&path, None)?;
+                    let location = match shareable {
+                        true => {
+                            let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+                            let ext = extension.unwrap_or("");
+                            let shared = format!("{}_{:08x}_{}.{}", stem, checksum, size, ext);
+                            let location = Path::new(SHARED).join(shared);
+                            self.fs.rename(&path, &self.dir.join(&location))?;
+                            location
+                        }
+                        false => Path::new(PRIVATE).join(id.to_string()).join(&name),
+                    };
+                    BackupFile {
+                        name,
+                        location,
+                        size,
+                        checksum,
+                    }
+                }
+            };
+            files.push(file);
+        }
+        self.fs.sync_dir(&self.dir.join(SHARED))?;
+        self.fs.sync_dir(&private)?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let meta = BackupMeta {
+            id,
+            timestamp,
+            files,
+        };
+        self.save_meta(&meta)?;
+        Ok(meta.info())
+    }
+
+    /// Lists the backups, oldest first.
+    pub fn backups(&self) -> Result<Vec<BackupInfo>, Error> {
+        self.ids()?
+            .into_iter()
+            .map(|id| Ok(self.load_meta(id)?.info()))
+            .collect()
+    }
+
+    /// Checks that every file of a backup is present with the size and
+    /// checksum it was backed up with.
+    pub fn verify_backup(&self, id: u64) -> Result<(), Error> {
+        for file in self.load_meta(id)?.files {
+            self.copy_file(&file, None)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes a backup, along with the shared files no other backup needs.
+    pub fn delete_backup(&self, id: u64) -> Result<(), Error> {
+        let path = self.dir.join(META).join(id.to_string());
+        match self.fs.remove_file(&path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(Error::BackupNotFound(id)),
+            result => result?,
+        }
+        self.fs.sync_dir(&self.dir.join(META))?;
+        self.collect_garbage()
+    }
+
+    /// Deletes all but the newest `keep` backups.
+    pub fn purge_old_backups(&self, keep: usize) -> Result<(), Error> {
+        let ids = self.ids()?;
+        for &id in &ids[..ids.len().saturating_sub(keep)] {
+            self.delete_backup(id)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the files of a backup into `dir`, which must not exist yet,
+    /// checking each against its checksum. The result opens as an ordinary
+    /// database. A restore that fails leaves `dir` without a manifest.
+    pub fn restore<P: AsRef<Path>>(&self, id: u64, dir: P) -> Result<(), Error> {
+        let dir = dir.as_ref();
+        let meta = self.load_meta(id)?;
+        if self.fs.exists(dir) {
+            let message = format!("{} already exists", dir.display());
+            return Err(std::io::Error::new(ErrorKind::AlreadyExists, message).into());
+        }
+        driver::create_dir(&*self.fs, dir, true)?;
+
+        // The manifest goes last, as in a checkpoint.
+        let (manifest, files): (Vec<_>, Vec<_>) =
+            meta.files.iter().partition(|f| f.name == MANIFEST);
+        for group in [files, manifest] {
+            for file in group {
+                let mut out = self.fs.create(&dir.join(&file.name))?;
+                self.copy_file(file, Some(&mut *out))?;
+                out.sync()?;
+            }
+            self.fs.sync_dir(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a file of a backup, appending it to `out` if given, and checks
+    /// it against its size and checksum.
+    fn copy_file(
+        &self,
+        file: &BackupFile,
+        out: Option<&mut dyn WritableFile>,
+    ) -> Result<(), Error> {
+        let path = self.dir.join(&file.location);
+        if self.stream(&path, out)? != (file.size, file.checksum) {
+            return Err(Error::Corruption {
+                file: path,
+                offset: 0,
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads the file at `path` a chunk at a time, appending each chunk to
+    /// `out` if given, and returns the file's size and checksum.
+    fn stream(
+        &self,
+        path: &Path,
+        mut out: Option<&mut dyn WritableFile>,
+    ) -> Result<(u64, u32), Error> {
+        let file = self.fs.open(path)?;
+        let size = file.len()?;
+        let mut checksum = 0;
+        let mut buf = Vec::new();
+        let mut offset = 0;
+        while offset < size {
+            buf.resize(CHUNK_SIZE.min(size - offset) as usize, 0);
+            file.read_at(&mut buf, offset)?;
+            checksum = crc32c::crc32c_append(checksum, &buf);
+            if let Some(out) = &mut out {
+                out.append(&buf)?;
+            }
+            offset += buf.len() as u64;
+        }
+        Ok((size, checksum))
+    }
+
+    /// The shared files by their name in the database directory and size.
+    fn shared_files(&self) -> Result<HashMap<(String, u64), BackupFile>, Error> {
+        let mut files = HashMap::new();
+        for path in self.fs.list_dir(&self.dir.join(SHARED))? {
+            let shared = file_name(&path);
+            let Some((stem, rest)) = shared.split_once('_') else {
+                continue;
+            };
+            let Some((checksum, rest)) = rest.split_once('_') else {
+                continue;
+            };
+            let Some((size, ext)) = rest.split_once('.') else {
+                continue;
+            };
+            if let (Ok(checksum), Ok(size)) = (u32::from_str_radix(checksum, 16), size.parse()) {
+                let file = BackupFile {
+                    name: format!("{}.{}", stem, ext),
+                    location: Path::new(SHARED).join(&shared),
+                    size,
+                    checksum,
+                };
+                files.insert((file.name.clone(), size), file);
+            }
+        }
+        Ok(files)
+    }
+
+    fn private_dir(&self, id: u64) -> PathBuf {
+        self.dir.join(PRIVATE).join(id.to_string())
+    }
+
+    /// Reserves the id of a new backup. The next id is kept on disk, so the
+    /// id of a deleted backup is never handed out again. Repositories from
+    /// before it was kept start after their newest backup.
+    fn allocate_id(&self) -> Result<u64, Error> {
+        let path = self.dir.join(NEXT_ID);
+        let next = match self.fs.read(&path) {
+            Ok(bytes) => match bytes.try_into() {
+                Ok(bytes) => u64::from_le_bytes(bytes),
+                Err(_) => {
+                    return Err(Error::Corruption {
+                        file: path,
+                        offset: 0,
+                    })
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => 1,
+            Err(e) => return Err(e.into()),
+        };
+        let id = self.ids()?.last().map_or(next, |last| next.max(last + 1));
+        self.write_atomically(&self.dir, &path, &(id + 1).to_le_bytes())?;
+        Ok(id)
+    }
+
+    /// The ids of the complete backups, in ascending order.
+    fn ids(&self) -> Result<Vec<u64>, Error> {
+        let mut ids: Vec<u64> = self
+            .fs
+            .list_dir(&self.dir.join(META))?
+            .iter()
+            .filter_map(|path| file_name(path).parse().ok())
+            .collect();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    /// Removes private directories and shared files that no complete backup
+    /// refers to, left by deleted backups or by a crash mid-backup.
+    fn collect_garbage(&self) -> Result<(), Error> {
+        let ids = self.ids()?;
+        let mut live = HashSet::new();
+        for &id in &ids {
+            live.extend(self.load_meta(id)?.files.into_iter().map(|f| f.location));
+        }
+
+        for path in self.fs.list_dir(&self.dir.join(SHARED))? {
+            if !live.contains(&Path::new(SHARED).join(file_name(&path))) {
+                self.fs.remove_file(&path)?;
+            }
+        }
+        for dir in self.fs.list_dir(&self.dir.join(PRIVATE))? {
+            if !file_name(&dir).parse().is_ok_and(|id| ids.contains(&id)) {
+                self.remove_private_dir(&dir)?;
+            }
+        }
+        for path in self.fs.list_dir(&self.dir.join(META))? {
+            if path.extension().and_then(|e| e.to_str()) == Some(TMP_EXTENSION) {
+                self.fs.remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_private_dir(&self, dir: &Path) -> Result<(), Error> {
+        for path in self.fs.list_dir(dir)? {
+            self.fs.remove_file(&path)?;
+        }
+        self.fs.remove_dir(dir)?;
+        Ok(())
+    }
+
+    fn load_meta(&self, id: u64) -> Result<BackupMeta, Error> {
+        let path = self.dir.join(META).join(id.to_string());
+        let bytes = match self.fs.read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(Error::BackupNotFound(id)),
+            Err(e) => return Err(e.into()),
+        };
+
+        let split = bytes.len().checked_sub(4).ok_or(Error::Corruption {
+            file: path.clone(),
+            offset: 0,
+        })?;
+        let (bytes, checksum) = bytes.split_at(split);
+        if crc32c::crc32c(bytes).to_le_bytes() != checksum {
+            return Err(Error::Corruption {
+                file: path,
+                offset: 0,
+            });
+        }
+        bincode::deserialize(bytes).map_err(|_| Error::BincodeError)
+    }
+
+    /// Writes the metadata of a backup, so that the backup appears complete
+    /// or not at all.
+    fn save_meta(&self, meta: &BackupMeta) -> Result<(), Error> {
+        let mut bytes = bincode::serialize(meta).map_err(|_| Error::BincodeError)?;
+        bytes.extend_from_slice(&crc32c::crc32c(&bytes).to_le_bytes());
+        let dir = self.dir.join(META);
+        self.write_atomically(&dir, &dir.join(meta.id.to_string()), &bytes)
+    }
+
+    /// Replaces the file at `path` in `dir` through a temporary file.
+    fn write_atomically(&self, dir: &Path, path: &Path, bytes: &[u8]) -> Result<(), Error> {
+        let tmp = path.with_extension(TMP_EXTENSION);
+        let mut file = self.fs.create(&tmp)?;
+        file.append(bytes)?;
+        file.sync()?;
+        self.fs.rename(&tmp, path)?;
+        self.fs.sync_dir(dir)?;
+        Ok(())
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::MemoryFileSystem;
+    use crate::options::{ColumnFamilyOptions, Options};
+
+    use super::*;
+
+    fn shared_files(fs: &dyn FileSystem) -> usize {
+        fs.list_dir(Path::new("/backup/shared")).unwrap().len()
+    }
+
+    #[test]
+    fn backup_and_restore() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let options = Options {
+            default_column_family: ColumnFamilyOptions {
+                min_value_log_size: Some(1024),
+                ..ColumnFamilyOptions::default()
+            },
+            file_system: Arc::clone(&fs),
+            ..Options::default()
+        };
+        let backup_options = BackupOptions {
+            file_system: Arc::clone(&fs),
+        };
+        let engine = BackupEngine::open_with_options("/backup", backup_options).unwrap();
+        let mut driver = Driver::open_with_options("/db", options.clone()).unwrap();
+
+        driver.write(String::from("blob"), vec![1; 4096]).unwrap();
+        driver.write(String::from("a"), vec![1]).unwrap();
+        driver.flush_table().unwrap();
+        driver.write(String::from("b"), vec![2]).unwrap();
+        let first = engine.create_backup(&driver).unwrap();
+        assert_eq!(2, shared_files(&*fs));
+
+        driver.write(String::from("a"), vec![3]).unwrap();
+        driver.flush_table().unwrap();
+        driver.compact().unwrap();
+        let second = engine.create_backup(&driver).unwrap();
+        // Only the compacted table is added, the value log is shared.
+        assert_eq!(3, shared_files(&*fs));
+        assert_eq!(
+            vec![first.clone(), second.clone()],
+            engine.backups().unwrap()
+        );
+        assert!(second.size > 4096);
+        engine.verify_backup(first.id).unwrap();
+        engine.verify_backup(second.id).unwrap();
+
+        engine.purge_old_backups(1).unwrap();
+        assert_eq!(vec![second.clone()], engine.backups().unwrap());
+        assert!(matches!(
+            engine.verify_backup(first.id),
+            Err(Error::BackupNotFound(1))
+        ));
+        assert_eq!(2, shared_files(&*fs));
+
+        engine.restore(second.id, "/restored").unwrap();
+        let restored = Driver::open_with_options("/restored", options).unwrap();
+        assert_eq!(Some(vec![1; 4096]), restored.read("blob").unwrap());
+        assert_eq!(Some(vec![3]), restored.read("a").unwrap());
+        assert_eq!(Some(vec![2]), restored.read("b").unwrap());
+        assert!(engine.restore(second.id, "/restored").is_err());
+
+        // A damaged shared table fails verification and restores.
+        let shared = fs.list_dir(Path::new("/backup/shared")).unwrap();
+        let table = shared
+            .iter()
+            .find(|p| p.extension().is_some_and(|e| e == SST_EXTENSION))
+            .unwrap();
+        fs.create(table).unwrap().append(b"garbage").unwrap();
+        assert!(matches!(
+            engine.verify_backup(second.id),
+            Err(Error::Corruption { .. })
+        ));
+        assert!(engine.restore(second.id, "/restored-again").is_err());
+    }
+
+    #[test]
+    fn incomplete_backups_are_removed() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let options = BackupOptions {
+            file_system: Arc::clone(&fs),
+        };
+        let engine = BackupEngine::open_with_options("/backup", options.clone()).unwrap();
+        let result = engine.create_backup_with(|dir| {
+            fs.create_dir_all(dir)?;
+            fs.create(&dir.join("1.sst"))?.append(b"table")?;
+            Err(Error::MemTableFull)
+        });
+        assert!(result.is_err());
+        assert!(fs.exists(Path::new("/backup/private/1")));
+
+        BackupEngine::open_with_options("/backup", options).unwrap();
+        assert!(!fs.exists(Path::new("/backup/private/1")));
+        assert!(engine.backups().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ids_are_never_reused() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let options = BackupOptions {
+            file_system: Arc::clone(&fs),
+        };
+        let engine = BackupEngine::open_with_options("/backup", options.clone()).unwrap();
+        let backup = |engine: &BackupEngine| {
+            engine
+                .create_backup_with(|dir| fs.create_dir_all(dir).map_err(Error::from))
+                .unwrap()
+                .id
+        };
+        assert_eq!(1, backup(&engine));
+        assert_eq!(2, backup(&engine));
+        engine.delete_backup(2).unwrap();
+        assert_eq!(3, backup(&engine));
+
+        engine.delete_backup(3).unwrap();
+        let engine = BackupEngine::open_with_options("/backup", options).unwrap();
+        assert_eq!(4, backup(&engine));
+        let ids: Vec<u64> = engine.backups().unwrap().iter().map(|b| b.id).collect();
+        assert_eq!(vec![1, 4], ids);
+    }
+
+    #[test]
+    fn shared_files_are_matched_by_name_and_size() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let options = BackupOptions {
+            file_system: Arc::clone(&fs),
+        };
+        let engine = BackupEngine::open_with_options("/backup", options).unwrap();
+        // Larger than a chunk, so it is checksummed and copied in pieces.
+        let log: Vec<u8> = (0..CHUNK_SIZE * 5 / 2).map(|i| i as u8).collect();
+        let backup = |table: &[u8]| {
+            engine
+                .create_backup_with(|dir| {
+                    fs.create_dir_all(dir)?;
+                    fs.create(&dir.join("1.sst"))?.append(table)?;
+                    fs.create(&dir.join("2.log"))?.append(&log)?;
+                    Ok(())
+                })
+                .unwrap()
+                .id
+        };
+
+        let first = backup(b"table");
+        // A table of the same name and size is not read again, only the
+        // file already shared is kept.
+        let second = backup(b"TABLE");
+        assert_eq!(1, shared_files(&*fs));
+        let third = backup(b"longer table");
+        assert_eq!(2, shared_files(&*fs));
+
+        for (id, table) in [
+            (first, &b"table"[..]),
+            (second, b"table"),
+            (third, b"longer table"),
+        ] {
+            engine.verify_backup(id).unwrap();
+            let dir = PathBuf::from(format!("/restored/{}", id));
+            engine.restore(id, &dir).unwrap();
+            assert_eq!(table, fs.read(&dir.join("1.sst")).unwrap());
+            assert!(log == fs.read(&dir.join("2.log")).unwrap());
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::driver::DEFAULT_COLUMN_FAMILY;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum Operation {
+    Put { key: String, value: Vec<u8> },
+    DeleteRange { start: String, end: String },
+    Delete { key: String },
+}
+
+/// A group of operations, possibly spanning column families, that is logged
+/// as a single WAL record and so is applied either entirely or not at all.
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    operations: Vec<(String, Operation)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: String, value: Vec<u8>) {
+        self.put_cf(DEFAULT_COLUMN_FAMILY, key, value);
+    }
+
+    pub fn put_cf<S: Into<String>>(&mut self, cf: S, key: String, value: Vec<u8>) {
+        self.operations
+            .push((cf.into(), Operation::Put { key, value }));
+    }
+
+    pub fn delete(&mut self, key: String) {
+        self.delete_cf(DEFAULT_COLUMN_FAMILY, key);
+    }
+
+    pub fn delete_cf<S: Into<String>>(&mut self, cf: S, key: String) {
+        self.operations.push((cf.into(), Operation::Delete { key }));
+    }
+
+    pub fn delete_range(&mut self, range: Range<String>) {
+        self.delete_range_cf(DEFAULT_COLUMN_FAMILY, range);
+    }
+
+    pub fn delete_range_cf<S: Into<String>>(&mut self, cf: S, range: Range<String>) {
+        self.operations.push((
+            cf.into(),
+            Operation::DeleteRange {
+                start: range.start,
+                end: range.end,
+            },
+        ));
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    pub fn into_operations(self) -> Vec<(String, Operation)> {
+        self.operations
+    }
+}
@@ -0,0 +1,204 @@
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compression::Compression;
+use crate::Error;
+
+/// Codec id, uncompressed length, stored length and checksum.
+pub const HEADER_SIZE: usize = 1 + 4 + 4 + 4;
+/// Three block handles, the magic number and the footer's own checksum.
+pub const FOOTER_SIZE: usize = 3 * 16 + 8 + 4;
+
+const MAGIC: u64 = 0x6c6f_676f_735f_7373;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub struct BlockHandle {
+    pub offset: u64,
+    pub size: u64,
+}
+
+fn corruption(offset: u64) -> Error {
+    Error::Corruption {
+        file: PathBuf::new(),
+        offset,
+    }
+}
+
+/// Compresses `payload` and appends it to `buf` behind a block header. The
+/// block is stored uncompressed when the codec fails to make it smaller. The
+/// header's CRC32C covers the rest of the header and the stored bytes.
+pub fn write_block(
+    buf: &mut Vec<u8>,
+    payload: &[u8],
+    compression: Compression,
+) -> Result<BlockHandle, Error> {
+    let compressed = compression.compress(payload)?;
+    let (compression, stored) = match compressed.len() < payload.len() {
+        true => (compression, compressed.as_slice()),
+        false => (Compression::None, payload),
+    };
+
+    let offset = buf.len() as u64;
+    let mut header = [0; HEADER_SIZE - 4];
+    header[0] = compression.id();
+    header[1..5].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    header[5..9].copy_from_slice(&(stored.len() as u32).to_le_bytes());
+    let checksum = crc32c::crc32c_append(crc32c::crc32c(&header), stored);
+
+    buf.extend_from_slice(&header);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf.extend_from_slice(stored);
+    Ok(BlockHandle {
+        offset,
+        size: buf.len() as u64 - offset,
+    })
+}
+
+/// Reads the block at `handle`. Corruption is reported with an empty file
+/// name; callers that know which file `bytes` came from fill it in.
+pub fn read_block(bytes: &[u8], handle: BlockHandle, verify: bool) -> Result<Cow<'_, [u8]>, Error> {
+    let start = handle.offset as usize;
+    let block = start
+        .checked_add(handle.size as usize)
+        .and_then(|end| bytes.get(start..end))
+        .ok_or_else(|| corruption(handle.offset))?;
+    decode_block(block, handle.offset, verify)
+}
+
+/// Decodes a block read on its own from `offset` in its file. Uncompressed
+/// blocks are returned without copying.
+pub fn decode_block(block: &[u8], offset: u64, verify: bool) -> Result<Cow<'_, [u8]>, Error> {
+    if block.len() < HEADER_SIZE {
+        return Err(corruption(offset));
+    }
+    let stored_len = u32::from_le_bytes(block[5..9].try_into().unwrap()) as usize;
+    let stored = block
+        .get(HEADER_SIZE..HEADER_SIZE + stored_len)
+        .ok_or_else(|| corruption(offset))?;
+    if verify {
+        let checksum = u32::from_le_bytes(block[9..13].try_into().unwrap());
+        if crc32c::crc32c_append(crc32c::crc32c(&block[..9]), stored) != checksum {
+            return Err(corruption(offset));
+        }
+    }
+
+    let compression = Compression::from_id(block[0]).map_err(|_| corruption(offset))?;
+    let uncompressed_len = u32::from_le_bytes(block[1..5].try_into().unwrap()) as usize;
+    match compression {
+        Compression::None if stored.len() == uncompressed_len => Ok(Cow::Borrowed(stored)),
+        _ => compression
+            .decompress(stored, uncompressed_len)
+            .map(Cow::Owned)
+            .map_err(|_| corruption(offset)),
+    }
+}
+
+/// Locates the block starting at `offset` from the lengths in its header,
+/// without verifying it.
+pub fn handle_at(bytes: &[u8], offset: u64) -> Option<BlockHandle> {
+    let header = bytes.get(offset as usize..offset as usize + HEADER_SIZE)?;
+    let stored_len = u32::from_le_bytes(header[5..9].try_into().unwrap()) as u64;
+    Some(BlockHandle {
+        offset,
+        size: HEADER_SIZE as u64 + stored_len,
+    })
+}
+
+/// The fixed-size trailer of an SSTable file locating its index, range
+/// tombstone and properties blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Footer {
+    pub index: BlockHandle,
+    pub tombstones: BlockHandle,
+    pub properties: BlockHandle,
+}
+
+impl Footer {
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        for handle in [self.index, self.tombstones, self.properties] {
+            buf.extend_from_slice(&handle.offset.to_le_bytes());
+            buf.extend_from_slice(&handle.size.to_le_bytes());
+        }
+        buf.extend_from_slice(&MAGIC.to_le_bytes());
+        let checksum = crc32c::crc32c(&buf[start..]);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+    }
+
+    /// Reads the footer at the end of `bytes`. Its checksum is always
+    /// verified, since every other block is found through it.
+    pub fn read(bytes: &[u8]) -> Result<Self, Error> {
+        let start = bytes.len().saturating_sub(FOOTER_SIZE);
+        let footer = &bytes[start..];
+        if footer.len() < FOOTER_SIZE {
+            return Err(corruption(start as u64));
+        }
+
+        let (footer, checksum) = footer.split_at(FOOTER_SIZE - 4);
+        let words: Vec<u64> = footer
+            .chunks_exact(8)
+            .map(|w| u64::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        if words[6] != MAGIC || crc32c::crc32c(footer).to_le_bytes() != checksum {
+            return Err(corruption(start as u64));
+        }
+
+        let handle = |i: usize| BlockHandle {
+            offset: words[2 * i],
+            size: words[2 * i + 1],
+        };
+        Ok(Self {
+            index: handle(0),
+            tombstones: handle(1),
+            properties: handle(2),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn incompressible_block_is_stored_raw() {
+        let mut buf = Vec::new();
+        let handle = write_block(&mut buf, b"abc", Compression::Zstd).unwrap();
+        assert_eq!(Compression::None.id(), buf[0]);
+        assert_eq!(b"abc", &*read_block(&buf, handle, true).unwrap());
+    }
+
+    #[test]
+    fn corrupted_block() {
+        let mut buf = vec![0; 5];
+        let handle = write_block(&mut buf, b"abcdef", Compression::None).unwrap();
+        let last = buf.len() - 1;
+        buf[last] ^= 1;
+
+        assert!(matches!(
+            read_block(&buf, handle, true),
+            Err(Error::Corruption { offset: 5, .. })
+        ));
+        assert_eq!(b"abcdeg", &*read_block(&buf, handle, false).unwrap());
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let footer = Footer {
+            index: BlockHandle { offset: 1, size: 2 },
+            tombstones: BlockHandle { offset: 3, size: 4 },
+            properties: BlockHandle { offset: 5, size: 6 },
+        };
+        let mut buf = vec![0; 7];
+        footer.write(&mut buf);
+        assert_eq!(footer, Footer::read(&buf).unwrap());
+        assert!(Footer::read(&buf[1..buf.len() - 1]).is_err());
+
+        buf[8] ^= 1;
+        assert!(matches!(
+            Footer::read(&buf),
+            Err(Error::Corruption { offset: 7, .. })
+        ));
+    }
+}
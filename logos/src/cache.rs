@@ -0,0 +1,201 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Debug};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::data_block::DataBlock;
+use crate::db::IndexEntry;
+
+const DEFAULT_CAPACITY: usize = 8 << 20;
+const SHARDS: usize = 16;
+
+/// A decoded block, shared between the cache and the readers using it.
+#[derive(Clone)]
+pub enum Block {
+    Data(Arc<DataBlock>),
+    Index(Arc<Vec<IndexEntry>>),
+}
+
+/// Identifies a block by the table it belongs to and its offset in the file.
+pub type BlockKey = (u64, u64);
+
+#[derive(Default)]
+struct Shard {
+    blocks: HashMap<BlockKey, (Block, usize, u64)>,
+    /// Keys ordered from least to most recently used.
+    lru: BTreeMap<u64, BlockKey>,
+    usage: usize,
+    tick: u64,
+}
+
+impl Shard {
+    fn get(&mut self, key: &BlockKey) -> Option<Block> {
+        let (block, _, tick) = self.blocks.get_mut(key)?;
+        self.lru.remove(tick);
+        self.tick += 1;
+        *tick = self.tick;
+        self.lru.insert(self.tick, *key);
+        Some(block.clone())
+    }
+
+    fn insert(&mut self, key: BlockKey, block: Block, charge: usize, capacity: usize) {
+        if let Some((_, old, tick)) = self.blocks.remove(&key) {
+            self.lru.remove(&tick);
+            self.usage -= old;
+        }
+        if charge > capacity {
+            return;
+        }
+
+        while self.usage + charge > capacity {
+            let Some((_, oldest)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some((_, old, _)) = self.blocks.remove(&oldest) {
+                self.usage -= old;
+            }
+        }
+
+        self.tick += 1;
+        self.usage += charge;
+        self.lru.insert(self.tick, key);
+        self.blocks.insert(key, (block, charge, self.tick));
+    }
+}
+
+/// A sharded LRU cache of decompressed SSTable blocks bounded by the total
+/// size of the blocks it holds. One cache is shared by every
+/// table of a `Driver`, and may be shared between drivers by passing the same
+/// cache in their options.
+pub struct BlockCache {
+    shards: Vec<Mutex<Shard>>,
+    shard_capacity: usize,
+    next_id: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Debug for BlockCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("capacity", &self.capacity())
+            .field("usage", &self.usage())
+            .finish()
+    }
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            shard_capacity: capacity / SHARDS,
+            next_id: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Allocates the id a newly opened table uses to key its blocks, unique
+    /// across every driver sharing this cache.
+    pub fn new_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shard_capacity * SHARDS
+    }
+
+    pub fn usage(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().usage).sum()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn get(&self, key: &BlockKey) -> Option<Block> {
+        let block = self.shard(key).lock().unwrap().get(key);
+        let counter = match block {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        block
+    }
+
+    /// Caches `block`, charging `charge` bytes against the capacity and
+    /// evicting the least recently used blocks of its shard to make room.
+    pub fn insert(&self, key: BlockKey, block: Block, charge: usize) {
+        self.shard(&key)
+            .lock()
+            .unwrap()
+            .insert(key, block, charge, self.shard_capacity);
+    }
+
+    fn shard(&self, key: &BlockKey) -> &Mutex<Shard> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data_block::DataBlockBuilder;
+    use crate::db::Value;
+
+    use super::*;
+
+    fn block(value: u32) -> Block {
+        let mut builder = DataBlockBuilder::new(16);
+        builder.add(&value.to_string(), &Value::Inline(Vec::new()));
+        Block::Data(Arc::new(DataBlock::new(builder.finish(), 0).unwrap()))
+    }
+
+    fn value(block: Option<Block>) -> Option<u32> {
+        match block? {
+            Block::Data(block) => block.iter().next()?.unwrap().key.parse().ok(),
+            Block::Index(_) => None,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut shard = Shard::default();
+        shard.insert((0, 0), block(0), 40, 100);
+        shard.insert((0, 1), block(1), 40, 100);
+        assert_eq!(Some(0), value(shard.get(&(0, 0))));
+
+        shard.insert((0, 2), block(2), 40, 100);
+        assert_eq!(80, shard.usage);
+        assert_eq!(Some(0), value(shard.get(&(0, 0))));
+        assert_eq!(None, value(shard.get(&(0, 1))));
+        assert_eq!(Some(2), value(shard.get(&(0, 2))));
+
+        shard.insert((0, 3), block(3), 200, 100);
+        assert_eq!(None, value(shard.get(&(0, 3))));
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let cache = BlockCache::new(1 << 20);
+        assert!(cache.get(&(1, 0)).is_none());
+        cache.insert((1, 0), block(7), 10);
+        assert_eq!(Some(7), value(cache.get(&(1, 0))));
+        assert_eq!((1, 1), (cache.hits(), cache.misses()));
+        assert_eq!(10, cache.usage());
+        assert_ne!(cache.new_id(), cache.new_id());
+    }
+}
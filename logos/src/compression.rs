@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// The codec applied to an SSTable block. Each block records the codec it
+/// was written with, so tables mixing codecs remain readable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    pub fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, Error> {
+        match id {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            2 => Ok(Compression::Zstd),
+            _ => Err(Error::CompressionError),
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Lz4 => Ok(lz4_flex::compress(data)),
+            Compression::Zstd => {
+                zstd::bulk::compress(data, ZSTD_LEVEL).map_err(|_| Error::CompressionError)
+            }
+        }
+    }
+
+    pub fn decompress(self, data: &[u8], uncompressed_len: usize) -> Result<Vec<u8>, Error> {
+        let bytes = match self {
+            Compression::None => data.to_vec(),
+            Compression::Lz4 => {
+                lz4_flex::decompress(data, uncompressed_len).map_err(|_| Error::CompressionError)?
+            }
+            Compression::Zstd => zstd::bulk::decompress(data, uncompressed_len)
+                .map_err(|_| Error::CompressionError)?,
+        };
+        match bytes.len() == uncompressed_len {
+            true => Ok(bytes),
+            false => Err(Error::CompressionError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data = br#"{"name":"apple","tags":["fruit","red"]}"#.repeat(20);
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            let compressed = compression.compress(&data).unwrap();
+            let id = Compression::from_id(compression.id()).unwrap();
+            assert_eq!(data, id.decompress(&compressed, data.len()).unwrap());
+        }
+    }
+
+    #[test]
+    fn compresses_repetitive_data() {
+        let data = br#"{"name":"apple","tags":["fruit","red"]}"#.repeat(20);
+        assert!(Compression::Lz4.compress(&data).unwrap().len() < data.len() / 4);
+        assert!(Compression::Zstd.compress(&data).unwrap().len() < data.len() / 4);
+    }
+}
@@ -0,0 +1,440 @@
+use std::cmp::Ordering;
+use std::ops::{Deref, Range};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::db::{Entry, Value, ValuePointer};
+use crate::env::MappedFile;
+use crate::Error;
+
+const U32_SIZE: usize = 4;
+const U64_SIZE: usize = 8;
+
+const INLINE: u32 = 0;
+const POINTER: u32 = 1;
+const TOMBSTONE: u32 = 2;
+const POINTER_SIZE: usize = U64_SIZE + U64_SIZE + U32_SIZE;
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn get_varint(bytes: &[u8], offset: &mut usize) -> Option<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let byte = *bytes.get(*offset)?;
+        *offset += 1;
+        value |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+        if byte < 0x80 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn encode_pointer(pointer: &ValuePointer) -> [u8; POINTER_SIZE] {
+    let mut buf = [0; POINTER_SIZE];
+    buf[..U64_SIZE].copy_from_slice(&(pointer.file as u64).to_le_bytes());
+    buf[U64_SIZE..2 * U64_SIZE].copy_from_slice(&pointer.offset.to_le_bytes());
+    buf[2 * U64_SIZE..].copy_from_slice(&pointer.size.to_le_bytes());
+    buf
+}
+
+fn decode_value(kind: u32, bytes: &[u8]) -> Option<Value> {
+    match (kind, bytes.len()) {
+        (INLINE, _) => Some(Value::Inline(bytes.to_vec())),
+        (TOMBSTONE, 0) => Some(Value::Tombstone),
+        (POINTER, POINTER_SIZE) => Some(Value::Pointer(ValuePointer {
+            file: u64::from_le_bytes(bytes[..U64_SIZE].try_into().unwrap()) as usize,
+            offset: u64::from_le_bytes(bytes[U64_SIZE..2 * U64_SIZE].try_into().unwrap()),
+            size: u32::from_le_bytes(bytes[2 * U64_SIZE..].try_into().unwrap()),
+        })),
+        _ => None,
+    }
+}
+
+fn shared_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+/// Builds a data block whose keys are delta-encoded against the previous
+/// key. Every `restart_interval` entries the full key is stored instead, and
+/// the offsets of these restart points are appended to the block so that
+/// readers can binary-search them.
+///
+/// Each entry is laid out as `shared | unshared | kind | value length | key
+/// suffix | value`, with the first four as varints, and the block ends with
+/// the restart offsets and their count as little-endian `u32`s. The kind
+/// tells inline values apart from pointers into a value log and from the
+/// empty values of tombstones.
+pub struct DataBlockBuilder {
+    buf: Vec<u8>,
+    restarts: Vec<u32>,
+    last_key: Vec<u8>,
+    count: usize,
+    restart_interval: usize,
+}
+
+impl DataBlockBuilder {
+    pub fn new(restart_interval: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            restarts: Vec::new(),
+            last_key: Vec::new(),
+            count: 0,
+            restart_interval: restart_interval.max(1),
+        }
+    }
+
+    /// Appends an entry. Keys must be added in ascending order.
+    pub fn add(&mut self, key: &str, value: &Value) {
+        let key = key.as_bytes();
+        let shared = match self.count % self.restart_interval {
+            0 => {
+                self.restarts.push(self.buf.len() as u32);
+                0
+            }
+            _ => shared_prefix(&self.last_key, key),
+        };
+
+        let pointer;
+        let (kind, value) = match value {
+            Value::Inline(value) => (INLINE, value.as_slice()),
+            Value::Pointer(p) => {
+                pointer = encode_pointer(p);
+                (POINTER, &pointer[..])
+            }
+            Value::Tombstone => (TOMBSTONE, &[][..]),
+        };
+        put_varint(&mut self.buf, shared as u32);
+        put_varint(&mut self.buf, (key.len() - shared) as u32);
+        put_varint(&mut self.buf, kind);
+        put_varint(&mut self.buf, value.len() as u32);
+        self.buf.extend_from_slice(&key[shared..]);
+        self.buf.extend_from_slice(value);
+
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.count += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The size the block will have once finished.
+    pub fn size(&self) -> usize {
+        self.buf.len() + (self.restarts.len() + 1) * U32_SIZE
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        for restart in &self.restarts {
+            self.buf.extend_from_slice(&restart.to_le_bytes());
+        }
+        self.buf
+            .extend_from_slice(&(self.restarts.len() as u32).to_le_bytes());
+        self.buf
+    }
+}
+
+/// The bytes of a data block: a buffer of its own, or, for an uncompressed
+/// block of a mapped table, its range of the mapping, which saves copying it.
+pub enum BlockData {
+    Owned(Vec<u8>),
+    Mapped(Arc<MappedFile>, Range<usize>),
+}
+
+impl Deref for BlockData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            BlockData::Owned(data) => data,
+            BlockData::Mapped(map, range) => &(***map).as_ref()[range.clone()],
+        }
+    }
+}
+
+impl From<Vec<u8>> for BlockData {
+    fn from(value: Vec<u8>) -> Self {
+        BlockData::Owned(value)
+    }
+}
+
+/// A decoded data block, kept in its compact encoded form.
+pub struct DataBlock {
+    data: BlockData,
+    /// Where the restart offsets begin, which is also where entries end.
+    restarts: usize,
+    num_restarts: usize,
+    /// The block's offset in its file, for reporting corruption.
+    offset: u64,
+}
+
+impl DataBlock {
+    pub fn new<D: Into<BlockData>>(data: D, offset: u64) -> Result<Self, Error> {
+        let data = data.into();
+        let corruption = || Error::Corruption {
+            file: PathBuf::new(),
+            offset,
+        };
+        let split = data.len().checked_sub(U32_SIZE).ok_or_else(corruption)?;
+        let num_restarts = u32::from_le_bytes(data[split..].try_into().unwrap()) as usize;
+        let restarts = num_restarts
+            .checked_mul(U32_SIZE)
+            .and_then(|size| split.checked_sub(size))
+            .ok_or_else(corruption)?;
+        Ok(Self {
+            data,
+            restarts,
+            num_restarts,
+            offset,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn iter(&self) -> DataBlockIter<'_> {
+        DataBlockIter {
+            block: self,
+            offset: 0,
+            key: Vec::new(),
+        }
+    }
+
+    /// Positions an iterator at the first entry whose key is at least
+    /// `target`. The restart points are binary-searched for the last one
+    /// before `target`, leaving at most one restart interval to scan.
+    pub fn seek(&self, target: &str) -> Result<DataBlockIter<'_>, Error> {
+        let target = target.as_bytes();
+        let (mut lo, mut hi) = (0, self.num_restarts);
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            match self.restart_key(mid)?.cmp(target) {
+                Ordering::Less => lo = mid,
+                _ => hi = mid,
+            }
+        }
+
+        let mut iter = DataBlockIter {
+            block: self,
+            offset: match self.num_restarts {
+                0 => self.restarts,
+                _ => self.restart(lo)?,
+            },
+            key: Vec::new(),
+        };
+        loop {
+            let (offset, key) = (iter.offset, iter.key.clone());
+            match iter.next().transpose()? {
+                Some(entry) if entry.key.as_bytes() < target => continue,
+                Some(_) => {
+                    iter.offset = offset;
+                    iter.key = key;
+                }
+                None => {}
+            }
+            return Ok(iter);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Value>, Error> {
+        match self.seek(key)?.next().transpose()? {
+            Some(entry) if entry.key == key => Ok(Some(entry.value)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn entries(&self) -> Result<Vec<Entry>, Error> {
+        self.iter().collect()
+    }
+
+    fn corruption(&self) -> Error {
+        Error::Corruption {
+            file: PathBuf::new(),
+            offset: self.offset,
+        }
+    }
+
+    fn restart(&self, i: usize) -> Result<usize, Error> {
+        let start = self.restarts + i * U32_SIZE;
+        let offset = u32::from_le_bytes(self.data[start..start + U32_SIZE].try_into().unwrap());
+        match (offset as usize) < self.restarts {
+            true => Ok(offset as usize),
+            false => Err(self.corruption()),
+        }
+    }
+
+    fn restart_key(&self, i: usize) -> Result<&[u8], Error> {
+        let mut offset = self.restart(i)?;
+        let header = (
+            get_varint(&self.data, &mut offset),
+            get_varint(&self.data, &mut offset),
+            get_varint(&self.data, &mut offset),
+            get_varint(&self.data, &mut offset),
+        );
+        match header {
+            (Some(0), Some(unshared), Some(_), Some(_)) => self
+                .data
+                .get(offset..offset + unshared as usize)
+                .filter(|_| offset + unshared as usize <= self.restarts)
+                .ok_or_else(|| self.corruption()),
+            _ => Err(self.corruption()),
+        }
+    }
+}
+
+pub struct DataBlockIter<'a> {
+    block: &'a DataBlock,
+    offset: usize,
+    key: Vec<u8>,
+}
+
+impl Iterator for DataBlockIter<'_> {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.block.restarts {
+            return None;
+        }
+
+        let entries = &self.block.data[..self.block.restarts];
+        let mut offset = self.offset;
+        let shared = get_varint(entries, &mut offset);
+        let unshared = get_varint(entries, &mut offset);
+        let kind = get_varint(entries, &mut offset);
+        let len = get_varint(entries, &mut offset);
+        let (shared, unshared, len, value) = match (shared, unshared, kind, len) {
+            (Some(shared), Some(unshared), Some(kind), Some(len))
+                if shared as usize <= self.key.len()
+                    && offset + unshared as usize + len as usize <= entries.len() =>
+            {
+                let start = offset + unshared as usize;
+                match decode_value(kind, &entries[start..start + len as usize]) {
+                    Some(value) => (shared as usize, unshared as usize, len as usize, value),
+                    None => {
+                        self.offset = self.block.restarts;
+                        return Some(Err(self.block.corruption()));
+                    }
+                }
+            }
+            _ => {
+                self.offset = self.block.restarts;
+                return Some(Err(self.block.corruption()));
+            }
+        };
+
+        self.key.truncate(shared);
+        self.key
+            .extend_from_slice(&entries[offset..offset + unshared]);
+        self.offset = offset + unshared + len;
+        match String::from_utf8(self.key.clone()) {
+            Ok(key) => Some(Ok(Entry { key, value })),
+            Err(_) => {
+                self.offset = self.block.restarts;
+                Some(Err(self.block.corruption()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Every third value is a pointer into a value log.
+    fn value(i: u32) -> Value {
+        match i % 3 {
+            0 => Value::Pointer(ValuePointer {
+                file: i as usize,
+                offset: i as u64 * 4096,
+                size: 4096,
+            }),
+            _ => Value::Inline(i.to_string().into_bytes()),
+        }
+    }
+
+    fn block(restart_interval: usize) -> DataBlock {
+        let mut builder = DataBlockBuilder::new(restart_interval);
+        for i in 0..100 {
+            builder.add(&format!("tenant/0001/user/{:05}", i * 2), &value(i));
+        }
+        let size = builder.size();
+        let data = builder.finish();
+        assert_eq!(size, data.len());
+        DataBlock::new(data, 0).unwrap()
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        let mut buf = Vec::new();
+        for value in [0, 1, 127, 128, 300, u32::MAX] {
+            put_varint(&mut buf, value);
+        }
+        let mut offset = 0;
+        for value in [0, 1, 127, 128, 300, u32::MAX] {
+            assert_eq!(Some(value), get_varint(&buf, &mut offset));
+        }
+        assert_eq!(None, get_varint(&buf, &mut offset));
+    }
+
+    #[test]
+    fn prefixes_are_shared() {
+        assert!(block(16).size() < block(1).size() / 2);
+    }
+
+    #[test]
+    fn iterate_and_seek() {
+        for restart_interval in [1, 4, 16, 1000] {
+            let block = block(restart_interval);
+            let entries: Vec<(String, Value)> = block
+                .entries()
+                .unwrap()
+                .into_iter()
+                .map(|e| (e.key, e.value))
+                .collect();
+            let expected: Vec<(String, Value)> = (0..100)
+                .map(|i| (format!("tenant/0001/user/{:05}", i * 2), value(i)))
+                .collect();
+            assert_eq!(expected, entries);
+
+            assert_eq!(
+                Some(value(21)),
+                block.get("tenant/0001/user/00042").unwrap()
+            );
+            assert_eq!(None, block.get("tenant/0001/user/00043").unwrap());
+            assert_eq!(Some(value(0)), block.get("tenant/0001/user/00000").unwrap());
+            assert_eq!(None, block.get("a").unwrap());
+            assert_eq!(None, block.get("z").unwrap());
+
+            let mut iter = block.seek("tenant/0001/user/00043").unwrap();
+            let entry = iter.next().unwrap().unwrap();
+            assert_eq!("tenant/0001/user/00044", entry.key);
+            assert_eq!(value(22), entry.value);
+            assert!(block.seek("z").unwrap().next().is_none());
+        }
+    }
+
+    #[test]
+    fn empty_block() {
+        let block = DataBlock::new(DataBlockBuilder::new(16).finish(), 0).unwrap();
+        assert!(block.entries().unwrap().is_empty());
+        assert_eq!(None, block.get("a").unwrap());
+    }
+
+    #[test]
+    fn malformed_block() {
+        assert!(DataBlock::new(vec![1, 2], 7).is_err());
+        let block = DataBlock::new(vec![5, 0, 0, 1, 0, 0, 0, 0], 7).unwrap();
+        assert!(matches!(
+            block.entries(),
+            Err(Error::Corruption { offset: 7, .. })
+        ));
+    }
+}
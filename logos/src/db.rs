@@ -1,14 +1,40 @@
-use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::ops::{Bound, Range, RangeBounds};
+use std::sync::Arc;
 
+use crate::batch::Operation;
+use crate::block::{self, BlockHandle, Footer};
+use crate::data_block::{DataBlock, DataBlockBuilder};
+use crate::options::ColumnFamilyOptions;
 use crate::Error;
+use bincode::Options;
 use serde::{Deserialize, Serialize};
 
 const DEFAULT_CAPACITY: usize = 10_000;
 
+/// Where a value separated from its key lives in a value log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ValuePointer {
+    pub file: usize,
+    pub offset: u64,
+    /// Size of the whole value log record holding the value.
+    pub size: u32,
+}
+
+/// A value as stored in the LSM tree: either the bytes themselves or, for
+/// values separated into a value log, where to find them. A deleted key is
+/// stored with a tombstone, which hides the key's values in older tables.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum Value {
+    Inline(Vec<u8>),
+    Pointer(ValuePointer),
+    Tombstone,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Entry {
     pub key: String,
-    pub value: u32,
+    pub value: Value,
 }
 
 impl PartialEq for Entry {
@@ -17,12 +43,55 @@ impl PartialEq for Entry {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RangeTombstone {
+    pub start: String,
+    pub end: String,
+}
+
+impl RangeTombstone {
+    pub fn covers<S: AsRef<str>>(&self, key: S) -> bool {
+        let key = key.as_ref();
+        self.start.as_str() <= key && key < self.end.as_str()
+    }
+
+    pub fn contains(&self, other: &RangeTombstone) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+}
+
+impl From<Range<String>> for RangeTombstone {
+    fn from(value: Range<String>) -> Self {
+        Self {
+            start: value.start,
+            end: value.end,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Lookup {
+    Found(Value),
+    Deleted,
+    Absent,
+}
+
+/// The writes not yet flushed to an SSTable. Its maps are persistent, so a
+/// clone is a cheap snapshot that later writes to the original do not change.
+#[derive(Clone)]
 pub struct MemTable {
-    items: BTreeMap<String, u32>,
+    items: imbl::OrdMap<String, Value>,
+    tombstones: Arc<Vec<RangeTombstone>>,
     size: usize,
     capacity: usize,
 }
 
+impl Default for MemTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MemTable {
     pub fn new() -> Self {
         Self::with_capacity(DEFAULT_CAPACITY)
@@ -30,19 +99,76 @@ impl MemTable {
 
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
-            items: BTreeMap::new(),
+            items: imbl::OrdMap::new(),
+            tombstones: Arc::default(),
             size: 0,
             capacity,
         }
     }
 
-    pub fn write(&mut self, key: String, value: u32) {
-        self.items.insert(key, value);
+    pub fn write(&mut self, key: String, value: Vec<u8>) {
+        self.items.insert(key, Value::Inline(value));
+        self.size += 1;
+    }
+
+    pub fn delete(&mut self, key: String) {
+        self.items.insert(key, Value::Tombstone);
+        self.size += 1;
+    }
+
+    /// Deletes the keys in `range`. An empty range deletes nothing.
+    pub fn delete_range(&mut self, range: Range<String>) {
+        if range.start >= range.end {
+            return;
+        }
+        let covered: Vec<String> = self
+            .items
+            .range(range.clone())
+            .map(|(k, _)| k.clone())
+            .collect();
+        for key in covered {
+            self.items.remove(&key);
+        }
+        Arc::make_mut(&mut self.tombstones).push(RangeTombstone::from(range));
         self.size += 1;
     }
 
-    pub fn read<S: AsRef<str>>(&self, key: S) -> Option<&u32> {
-        self.items.get(key.as_ref())
+    pub fn apply(&mut self, operation: Operation) {
+        event!(TRACE, size = self.size, "applying operation to memtable");
+        match operation {
+            Operation::Put { key, value } => self.write(key, value),
+            Operation::DeleteRange { start, end } => self.delete_range(start..end),
+            Operation::Delete { key } => self.delete(key),
+        }
+    }
+
+    pub fn read<S: AsRef<str>>(&self, key: S) -> Option<&[u8]> {
+        match self.items.get(key.as_ref()) {
+            Some(Value::Inline(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn lookup<S: AsRef<str>>(&self, key: S) -> Lookup {
+        let key = key.as_ref();
+        match self.items.get(key) {
+            Some(Value::Tombstone) => Lookup::Deleted,
+            Some(value) => Lookup::Found(value.clone()),
+            None if self.tombstones.iter().any(|t| t.covers(key)) => Lookup::Deleted,
+            None => Lookup::Absent,
+        }
+    }
+
+    /// The entries with keys in `range`, in order, deleted keys included.
+    pub fn range<R: RangeBounds<String>>(&self, range: R) -> impl Iterator<Item = Entry> + '_ {
+        let items = match is_empty_range(&range) {
+            true => None,
+            false => Some(self.items.range(range)),
+        };
+        items.into_iter().flatten().map(|(k, v)| Entry {
+            key: k.to_owned(),
+            value: v.to_owned(),
+        })
     }
 
     pub fn items(&self) -> Vec<Entry> {
@@ -55,36 +181,370 @@ impl MemTable {
             .collect()
     }
 
+    pub fn tombstones(&self) -> &[RangeTombstone] {
+        &self.tombstones
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Whether any key or range tombstone falls within `smallest..=largest`.
+    pub fn overlaps(&self, smallest: &str, largest: &str) -> bool {
+        let bounds = (Bound::Included(smallest), Bound::Included(largest));
+        let mut tombstones = self.tombstones.iter();
+        self.items.range::<_, str>(bounds).next().is_some()
+            || tombstones.any(|t| t.start.as_str() <= largest && smallest < t.end.as_str())
+    }
+
+    /// Operations applied since the memtable was created.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     pub fn at_capacity(&self) -> bool {
         self.size >= self.capacity
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IndexEntry {
+    pub last_key: String,
+    pub handle: BlockHandle,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct TableProperties {
+    pub entries: u64,
+    pub tombstones: u64,
+    pub data_blocks: u64,
+    /// Size of the data blocks before compression.
+    pub raw_data_size: u64,
+    /// Size of the data blocks as stored in the file.
+    pub data_size: u64,
+    pub smallest_key: Option<String>,
+    pub largest_key: Option<String>,
+}
+
+impl TableProperties {
+    pub fn compression_ratio(&self) -> f64 {
+        match self.data_size {
+            0 => 1.0,
+            size => self.raw_data_size as f64 / size as f64,
+        }
+    }
+}
+
 pub struct SSTable {
     entries: Vec<Entry>,
+    tombstones: Vec<RangeTombstone>,
 }
 
 impl From<&MemTable> for SSTable {
     fn from(value: &MemTable) -> Self {
         SSTable {
             entries: value.items(),
+            tombstones: value.tombstones().to_vec(),
         }
     }
 }
 
 impl SSTable {
-    pub fn into_bytes(&self) -> Result<Vec<u8>, Error> {
-        bincode::serialize(self).map_err(|_| Error::BincodeError)
+    pub fn new(entries: Vec<Entry>, tombstones: Vec<RangeTombstone>) -> Self {
+        Self {
+            entries,
+            tombstones,
+        }
+    }
+
+    /// Encodes the table as a sequence of prefix-compressed data blocks of
+    /// roughly `block_size` bytes each, followed by index, range tombstone and
+    /// properties blocks and a footer locating them.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(entries = self.entries.len()))
+    )]
+    pub fn into_bytes(
+        &self,
+        options: &ColumnFamilyOptions,
+    ) -> Result<(Vec<u8>, TableProperties), Error> {
+        let mut buf = Vec::new();
+        let mut index = Vec::new();
+        let mut properties = TableProperties {
+            entries: self.entries.len() as u64,
+            tombstones: self.tombstones.len() as u64,
+            smallest_key: self.entries.first().map(|e| e.key.clone()),
+            largest_key: self.entries.last().map(|e| e.key.clone()),
+            ..TableProperties::default()
+        };
+
+        let mut entries = self.entries.iter().peekable();
+        while entries.peek().is_some() {
+            let mut builder = DataBlockBuilder::new(options.block_restart_interval);
+            let mut last_key = String::new();
+            while let Some(entry) =
+                entries.next_if(|_| builder.is_empty() || builder.size() < options.block_size)
+            {
+                builder.add(&entry.key, &entry.value);
+                last_key.clone_from(&entry.key);
+            }
+
+            let payload = builder.finish();
+            let handle = block::write_block(&mut buf, &payload, options.compression)?;
+            properties.data_blocks += 1;
+            properties.raw_data_size += payload.len() as u64;
+            properties.data_size += handle.size;
+            index.push(IndexEntry { last_key, handle });
+        }
+
+        let compression = options.compression;
+        let footer = Footer {
+            index: block::write_block(&mut buf, &serialize(&index)?, compression)?,
+            tombstones: block::write_block(&mut buf, &serialize(&self.tombstones)?, compression)?,
+            properties: block::write_block(&mut buf, &serialize(&properties)?, compression)?,
+        };
+        footer.write(&mut buf);
+        event!(
+            DEBUG,
+            data_blocks = properties.data_blocks,
+            raw_bytes = properties.raw_data_size,
+            bytes = buf.len(),
+            "encoded table"
+        );
+        Ok((buf, properties))
+    }
+
+    /// Decodes a table written by `into_bytes`, checking every block against
+    /// its checksum when `verify` is set.
+    pub fn from_bytes(bytes: &[u8], verify: bool) -> Result<Self, Error> {
+        let footer = Footer::read(bytes)?;
+        let index: Vec<IndexEntry> = deserialize(&block::read_block(bytes, footer.index, verify)?)?;
+
+        let mut entries = Vec::new();
+        for entry in index {
+            let block = block::read_block(bytes, entry.handle, verify)?;
+            entries.extend(DataBlock::new(block.into_owned(), entry.handle.offset)?.entries()?);
+        }
+        let tombstones = deserialize(&block::read_block(bytes, footer.tombstones, verify)?)?;
+        Ok(Self {
+            entries,
+            tombstones,
+        })
+    }
+
+    /// Recovers whatever entries and tombstones of a damaged table can still
+    /// be read, along with the corruption found in each unreadable block.
+    /// Data blocks are located through the index when it survives, and
+    /// otherwise by walking the blocks from the start of the file until one
+    /// fails to read.
+    pub fn salvage(bytes: &[u8]) -> (Self, Vec<Error>) {
+        let mut errors = Vec::new();
+        let footer = Footer::read(bytes).map_err(|e| errors.push(e)).ok();
+        let index = footer.and_then(|f| {
+            block::read_block(bytes, f.index, true)
+                .and_then(|b| deserialize::<Vec<IndexEntry>>(&b))
+                .map_err(|e| errors.push(e))
+                .ok()
+        });
+
+        let mut entries: Vec<Entry> = Vec::new();
+        match index {
+            Some(index) => {
+                for entry in index {
+                    match block::read_block(bytes, entry.handle, true)
+                        .and_then(|b| DataBlock::new(b.into_owned(), entry.handle.offset))
+                        .and_then(|b| b.entries())
+                    {
+                        Ok(block) => entries.extend(block),
+                        Err(e) => errors.push(e),
+                    }
+                }
+            }
+            None => {
+                let end = footer.map_or(bytes.len(), |f| f.index.offset as usize);
+                let mut offset = 0;
+                while offset < end {
+                    let handle = match block::handle_at(bytes, offset as u64) {
+                        Some(handle) => handle,
+                        None => break,
+                    };
+                    let block = match block::read_block(bytes, handle, true) {
+                        Ok(block) => block,
+                        Err(e) => {
+                            errors.push(e);
+                            break;
+                        }
+                    };
+                    match DataBlock::new(block.into_owned(), handle.offset)
+                        .and_then(|b| b.entries())
+                    {
+                        Ok(block)
+                            if block.first().map(|e| &e.key) > entries.last().map(|e| &e.key) =>
+                        {
+                            entries.extend(block)
+                        }
+                        _ => break,
+                    }
+                    offset += handle.size as usize;
+                }
+            }
+        }
+
+        let tombstones = footer
+            .ok_or(Error::BincodeError)
+            .and_then(|f| block::read_block(bytes, f.tombstones, true))
+            .and_then(|b| deserialize(&b));
+        let tombstones = match (footer, tombstones) {
+            (_, Ok(tombstones)) => tombstones,
+            (Some(_), Err(e)) => {
+                errors.push(e);
+                Vec::new()
+            }
+            (None, Err(_)) => Vec::new(),
+        };
+        (Self::new(entries, tombstones), errors)
+    }
+
+    pub fn properties(bytes: &[u8]) -> Result<TableProperties, Error> {
+        let footer = Footer::read(bytes)?;
+        deserialize(&block::read_block(bytes, footer.properties, true)?)
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn tombstones(&self) -> &[RangeTombstone] {
+        &self.tombstones
     }
 }
 
+fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+    bincode::serialize(value).map_err(|_| Error::BincodeError)
+}
+
+/// Blocks hold exactly one value, so trailing bytes mean the block is not
+/// what the caller expected.
+pub(crate) fn deserialize<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> Result<T, Error> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(bytes)
+        .map_err(|_| Error::BincodeError)
+}
+
+/// Whether no key can fall within `range`, as when it starts past its end.
+pub(crate) fn is_empty_range<R: RangeBounds<String>>(range: &R) -> bool {
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Excluded(start), Bound::Included(end))
+        | (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/// Entries in key order, as read from a memtable or an SSTable.
+pub type Entries<'a> = Box<dyn Iterator<Item = Result<Entry, Error>> + 'a>;
+
+/// Merges sources of entries, ordered newest first, into the entries still
+/// visible, in key order. An entry is hidden when a newer source already has
+/// the key or carries a range tombstone covering it, and deleted keys are
+/// left out. Sources are only read as far
+/// as the entries taken from the merge, so a scan can stop early.
+pub struct Merge<'a> {
+    sources: Vec<(Peekable<Entries<'a>>, &'a [RangeTombstone])>,
+}
+
+impl<'a> Merge<'a> {
+    pub fn new(sources: Vec<(Entries<'a>, &'a [RangeTombstone])>) -> Self {
+        let sources = sources
+            .into_iter()
+            .map(|(entries, tombstones)| (entries.peekable(), tombstones))
+            .collect();
+        Self { sources }
+    }
+}
+
+impl Iterator for Merge<'_> {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // The smallest key, taken from the newest source holding it.
+            let mut newest: Option<(usize, String)> = None;
+            for (i, (entries, _)) in self.sources.iter_mut().enumerate() {
+                match entries.peek() {
+                    Some(Err(_)) => return entries.next(),
+                    Some(Ok(entry)) if newest.as_ref().is_none_or(|(_, key)| &entry.key < key) => {
+                        newest = Some((i, entry.key.clone()));
+                    }
+                    Some(Ok(_)) | None => {}
+                }
+            }
+            let (source, key) = newest?;
+            let entry = self.sources[source].0.next()?;
+            for (entries, _) in &mut self.sources[source + 1..] {
+                entries.next_if(|e| matches!(e, Ok(e) if e.key == key));
+            }
+
+            let mut newer = self.sources[..source].iter().flat_map(|(_, t)| t.iter());
+            let deleted = matches!(
+                entry,
+                Ok(Entry {
+                    value: Value::Tombstone,
+                    ..
+                })
+            );
+            if !deleted && !newer.any(|t| t.covers(&key)) {
+                return Some(entry);
+            }
+        }
+    }
+}
+
+/// Resolves the entries in `range` that are still visible across `sources`,
+/// ordered newest first, as `Merge` does.
+pub fn merge<R: RangeBounds<String>>(
+    sources: &[(&[Entry], &[RangeTombstone])],
+    range: &R,
+) -> Vec<Entry> {
+    let sources = sources
+        .iter()
+        .map(|(entries, tombstones)| {
+            let entries = entries.iter().filter(|e| range.contains(&e.key));
+            let entries: Entries = Box::new(entries.cloned().map(Ok));
+            (entries, *tombstones)
+        })
+        .collect();
+    // Entries already in memory cannot fail to read.
+    Merge::new(sources).flatten().collect()
+}
+
 #[cfg(test)]
 mod test {
+    use crate::compression::Compression;
+
     use super::*;
 
     fn write(m: &mut MemTable, key: &str, value: u32) {
-        m.write(String::from(key), value);
+        m.write(String::from(key), value.to_string().into_bytes());
+    }
+
+    fn found(value: u32) -> Lookup {
+        Lookup::Found(Value::Inline(value.to_string().into_bytes()))
+    }
+
+    /// The keys and values of `entries`, since entries compare by key alone.
+    fn pairs(entries: &[Entry]) -> Vec<(&str, &Value)> {
+        entries.iter().map(|e| (e.key.as_str(), &e.value)).collect()
+    }
+
+    fn options(compression: Compression, block_size: usize) -> ColumnFamilyOptions {
+        ColumnFamilyOptions {
+            compression,
+            block_size,
+            ..ColumnFamilyOptions::default()
+        }
     }
 
     #[test]
@@ -94,15 +554,15 @@ mod test {
         write(&mut m, "banana", 2);
         write(&mut m, "cactus", 3);
 
-        assert_eq!(Some(&1), m.read("apple"));
-        assert_eq!(Some(&2), m.read("banana"));
-        assert_eq!(Some(&3), m.read("cactus"));
+        assert_eq!(Some(&b"1"[..]), m.read("apple"));
+        assert_eq!(Some(&b"2"[..]), m.read("banana"));
+        assert_eq!(Some(&b"3"[..]), m.read("cactus"));
         assert_eq!(None, m.read("dummy"));
 
         write(&mut m, "apple", 5);
-        assert_eq!(Some(&5), m.read("apple"));
-        assert_eq!(Some(&2), m.read("banana"));
-        assert_eq!(Some(&3), m.read("cactus"));
+        assert_eq!(Some(&b"5"[..]), m.read("apple"));
+        assert_eq!(Some(&b"2"[..]), m.read("banana"));
+        assert_eq!(Some(&b"3"[..]), m.read("cactus"));
         assert_eq!(None, m.read("dummy"));
     }
 
@@ -120,17 +580,137 @@ mod test {
             vec![
                 Entry {
                     key: String::from("apple"),
-                    value: 5
+                    value: Value::Inline(b"5".to_vec()),
                 },
                 Entry {
                     key: String::from("banana"),
-                    value: 2
+                    value: Value::Inline(b"2".to_vec()),
                 },
                 Entry {
                     key: String::from("cactus"),
-                    value: 3
+                    value: Value::Inline(b"3".to_vec()),
                 },
             ]
         )
     }
+
+    #[test]
+    fn delete_range() {
+        let mut m = MemTable::new();
+        write(&mut m, "apple", 1);
+        write(&mut m, "banana", 2);
+        write(&mut m, "cactus", 3);
+
+        m.delete_range(String::from("b")..String::from("c"));
+        assert_eq!(found(1), m.lookup("apple"));
+        assert_eq!(Lookup::Deleted, m.lookup("banana"));
+        assert_eq!(found(3), m.lookup("cactus"));
+        assert_eq!(Lookup::Deleted, m.lookup("blueberry"));
+        assert_eq!(Lookup::Absent, m.lookup("dummy"));
+    }
+
+    #[test]
+    fn point_deletes() {
+        let mut m = MemTable::new();
+        write(&mut m, "apple", 1);
+        write(&mut m, "banana", 2);
+        m.delete(String::from("apple"));
+        m.delete(String::from("cherry"));
+        m.delete_range(String::from("c")..String::from("a"));
+
+        assert_eq!(Lookup::Deleted, m.lookup("apple"));
+        assert_eq!(Lookup::Deleted, m.lookup("cherry"));
+        assert_eq!(found(2), m.lookup("banana"));
+        assert_eq!(None, m.read("apple"));
+        assert!(m.tombstones().is_empty());
+
+        // A deleted key hides older values and is itself left out.
+        let mut older = MemTable::new();
+        write(&mut older, "apple", 0);
+        write(&mut older, "cherry", 0);
+        write(&mut older, "date", 0);
+        let merged = merge(
+            &[
+                (&m.items(), m.tombstones()),
+                (&older.items(), older.tombstones()),
+            ],
+            &(..),
+        );
+        let keys: Vec<&str> = merged.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(vec!["banana", "date"], keys);
+    }
+
+    #[test]
+    fn sstable_round_trip() {
+        let mut m = MemTable::new();
+        for i in 0..1000 {
+            write(&mut m, &format!("user/{:06}/profile", i), i);
+        }
+        m.delete_range(String::from("user/000500")..String::from("user/000600"));
+
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            let sst = SSTable::from(&m);
+            let (bytes, properties) = sst.into_bytes(&options(compression, 1024)).unwrap();
+            let decoded = SSTable::from_bytes(&bytes, true).unwrap();
+            assert_eq!(pairs(sst.entries()), pairs(decoded.entries()));
+            assert_eq!(sst.tombstones(), decoded.tombstones());
+            assert_eq!(properties, SSTable::properties(&bytes).unwrap());
+            assert_eq!(900, properties.entries);
+            assert!(properties.data_blocks > 1);
+            match compression {
+                Compression::None => assert_eq!(1.0, properties.compression_ratio().round()),
+                _ => assert!(properties.compression_ratio() > 2.0),
+            }
+        }
+    }
+
+    #[test]
+    fn sstable_corruption() {
+        let mut m = MemTable::new();
+        for i in 0..100 {
+            write(&mut m, &format!("key/{:03}", i), i);
+        }
+        let (mut bytes, _) = SSTable::from(&m)
+            .into_bytes(&options(Compression::None, 256))
+            .unwrap();
+        bytes[block::HEADER_SIZE + 10] ^= 0xff;
+
+        assert!(matches!(
+            SSTable::from_bytes(&bytes, true),
+            Err(Error::Corruption { offset: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn salvage() {
+        let mut m = MemTable::new();
+        for i in 0..1000 {
+            write(&mut m, &format!("key/{:04}", i), i);
+        }
+        let sst = SSTable::from(&m);
+        let (bytes, properties) = sst.into_bytes(&options(Compression::Lz4, 256)).unwrap();
+
+        let (intact, errors) = SSTable::salvage(&bytes);
+        assert!(errors.is_empty());
+        assert_eq!(pairs(sst.entries()), pairs(intact.entries()));
+
+        let mut damaged = bytes.clone();
+        damaged[block::HEADER_SIZE] ^= 0xff;
+        let (salvaged, errors) = SSTable::salvage(&damaged);
+        assert_eq!(1, errors.len());
+        assert!(salvaged.entries().len() < sst.entries().len());
+        assert_eq!(
+            pairs(sst.entries()).last(),
+            pairs(salvaged.entries()).last()
+        );
+
+        let truncated = &bytes[..properties.data_size as usize / 2];
+        let (salvaged, errors) = SSTable::salvage(truncated);
+        assert!(!errors.is_empty());
+        assert!(!salvaged.entries().is_empty());
+        assert_eq!(
+            pairs(sst.entries()).first(),
+            pairs(salvaged.entries()).first()
+        );
+    }
 }
@@ -1,80 +1,2076 @@
-use std::fs::File;
-use std::io::Write;
+use std::collections::{BTreeMap, HashSet};
+use std::io::ErrorKind;
+use std::ops::{Range, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::db::{MemTable, SSTable};
+use arc_swap::ArcSwap;
+
+use crate::batch::{Operation, WriteBatch};
+use crate::cache::BlockCache;
+use crate::db::{Entries, Lookup, MemTable, Merge, RangeTombstone, SSTable, Value};
+use crate::env::FileSystem;
+use crate::listener::{
+    CompactionJobInfo, EventListener, FlushJobInfo, WriteStallCondition, WriteStallInfo,
+};
+use crate::manifest::{ColumnFamilyMeta, Manifest};
+use crate::options::{ColumnFamilyOptions, Options, SyncPolicy, WriteOptions};
+use crate::stats::{Metrics, Statistics};
+use crate::table::Table;
+use crate::value_log::{self, ValueLog, VALUE_LOG_EXTENSION};
+use crate::wal::{self, Wal};
 use crate::Error;
 
-pub struct Driver {
+pub const DEFAULT_COLUMN_FAMILY: &str = "default";
+
+pub(crate) const SST_EXTENSION: &str = "sst";
+pub(crate) const WAL_EXTENSION: &str = "log";
+
+/// A column family's memtable, tables and value logs. Cloning it is cheap and
+/// takes a snapshot: writes to the original after that are not visible
+/// through the clone.
+#[derive(Clone)]
+struct ColumnFamily {
+    id: u32,
+    name: String,
+    options: ColumnFamilyOptions,
     master: MemTable,
-    offset: usize,
+    /// Memtables a flush took over whose tables the manifest does not list
+    /// yet, oldest first. Reads see them after `master` and before the
+    /// tables.
+    immutables: Vec<MemTable>,
+    tables: Vec<(usize, Arc<Table>)>,
+    value_logs: BTreeMap<usize, Arc<ValueLog>>,
+    log_number: usize,
+    /// Shared with the driver, so reads through a published `Version` count
+    /// too.
+    metrics: Arc<Metrics>,
 }
 
-impl Driver {
-    pub fn new() -> Self {
+impl ColumnFamily {
+    fn new(
+        id: u32,
+        name: String,
+        options: ColumnFamilyOptions,
+        log_number: usize,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         Self {
-            master: MemTable::new(),
-            offset: 0,
+            id,
+            name,
+            master: MemTable::with_capacity(options.memtable_capacity),
+            immutables: Vec::new(),
+            options,
+            tables: Vec::new(),
+            value_logs: BTreeMap::new(),
+            log_number,
+            metrics,
+        }
+    }
+
+    fn apply(&mut self, operation: Operation) {
+        self.master.apply(operation);
+    }
+
+    /// The memtable and the immutable ones, newest first.
+    fn memtables(&self) -> impl Iterator<Item = &MemTable> {
+        std::iter::once(&self.master).chain(self.immutables.iter().rev())
+    }
+
+    fn lookup(&self, key: &str) -> Result<Option<Value>, Error> {
+        let memtables = self.memtables().map(|memtable| Ok(memtable.lookup(key)));
+        let lookups = memtables.chain(self.tables.iter().rev().map(|(_, table)| {
+            self.metrics
+                .key_range_checks
+                .fetch_add(1, Ordering::Relaxed);
+            match table.may_contain(key) {
+                true => table.lookup_in_range(key),
+                false => {
+                    self.metrics.key_range_skips.fetch_add(1, Ordering::Relaxed);
+                    Ok(table.lookup_tombstones(key))
+                }
+            }
+        }));
+
+        for lookup in lookups {
+            match lookup? {
+                Lookup::Found(value) => return Ok(Some(value)),
+                Lookup::Deleted => return Ok(None),
+                Lookup::Absent => continue,
+            }
+        }
+        Ok(None)
+    }
+
+    /// The live entries in `range`, merged lazily from the memtables and the
+    /// tables, newest first.
+    fn scan<R: RangeBounds<String> + Clone>(&self, range: R) -> Result<Merge<'_>, Error> {
+        let mut sources = Vec::new();
+        for memtable in self.memtables() {
+            let entries: Entries = Box::new(memtable.range(range.clone()).map(Ok));
+            sources.push((entries, memtable.tombstones()));
+        }
+        for (_, table) in self.tables.iter().rev() {
+            let entries: Entries = Box::new(table.iter(range.clone())?);
+            sources.push((entries, table.tombstones()));
+        }
+        Ok(Merge::new(sources))
+    }
+
+    /// Reads a value out of its value log if it was separated from its key.
+    fn resolve(&self, path: &Path, value: Value) -> Result<Vec<u8>, Error> {
+        match value {
+            Value::Inline(value) => Ok(value),
+            Value::Pointer(pointer) => match self.value_logs.get(&pointer.file) {
+                Some(log) => log.read(&pointer),
+                None => Err(Error::Corruption {
+                    file: value_log::value_log_path(path, pointer.file),
+                    offset: pointer.offset,
+                }),
+            },
+            Value::Tombstone => unreachable!("lookups report deleted keys as such"),
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(self, path), fields(column_family = %self.name))
+    )]
+    fn read(&self, path: &Path, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let start = Instant::now();
+        let value = self
+            .lookup(key)?
+            .map(|value| self.resolve(path, value))
+            .transpose()?;
+
+        let metrics = &self.metrics;
+        metrics.reads.fetch_add(1, Ordering::Relaxed);
+        let size = value.as_ref().map_or(0, Vec::len) as u64;
+        metrics.bytes_read.fetch_add(size, Ordering::Relaxed);
+        metrics.read_latency.record(start.elapsed());
+        event!(TRACE, found = value.is_some(), bytes = size, "read key");
+        Ok(value)
+    }
+
+    /// The keys and values in `range`, read as they are taken.
+    fn iter_values<'a, R: RangeBounds<String> + Clone>(
+        &'a self,
+        path: &'a Path,
+        range: R,
+    ) -> Result<impl Iterator<Item = Result<(String, Vec<u8>), Error>> + 'a, Error> {
+        self.metrics.scans.fetch_add(1, Ordering::Relaxed);
+        Ok(self.scan(range)?.map(move |entry| {
+            let entry = entry?;
+            let value = self.resolve(path, entry.value)?;
+            let size = value.len() as u64;
+            self.metrics.bytes_read.fetch_add(size, Ordering::Relaxed);
+            Ok((entry.key, value))
+        }))
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(self, path), fields(column_family = %self.name))
+    )]
+    fn scan_values(
+        &self,
+        path: &Path,
+        range: Range<String>,
+    ) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let entries: Vec<_> = self.iter_values(path, range)?.collect::<Result<_, _>>()?;
+        event!(
+            TRACE,
+            entries = entries.len(),
+            bytes = entries.iter().map(|(_, value)| value.len()).sum::<usize>(),
+            "scanned range"
+        );
+        Ok(entries)
+    }
+
+    fn meta(&self) -> ColumnFamilyMeta {
+        ColumnFamilyMeta {
+            id: self.id,
+            name: self.name.clone(),
+            options: self.options.clone(),
+            log_number: self.log_number,
+            files: self.tables.iter().map(|(file, _)| *file).collect(),
+            value_logs: self.value_logs.keys().copied().collect(),
         }
     }
+}
+
+/// The column families of a driver as of its last commit, flush, compaction,
+/// garbage collection or change to the set of column families. A version
+/// never changes once published, so reads through it need no lock on the
+/// driver or its memtables and see either all of a write batch or none of it.
+#[derive(Default)]
+pub(crate) struct Version {
+    path: PathBuf,
+    column_families: Vec<ColumnFamily>,
+    metrics: Arc<Metrics>,
+}
+
+impl Version {
+    fn column_family(&self, name: &str) -> Result<&ColumnFamily, Error> {
+        self.column_families
+            .iter()
+            .find(|cf| cf.name == name)
+            .ok_or_else(|| Error::ColumnFamilyNotFound(name.to_owned()))
+    }
+
+    pub(crate) fn column_families(&self) -> Vec<String> {
+        self.column_families
+            .iter()
+            .map(|cf| cf.name.clone())
+            .collect()
+    }
+
+    /// The driver's statistics as of this version.
+    pub(crate) fn stats(&self, cache: &BlockCache) -> Statistics {
+        statistics(&self.metrics, cache, &self.column_families)
+    }
+
+    pub(crate) fn read_cf(&self, cf: &str, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.column_family(cf)?.read(&self.path, key)
+    }
+
+    pub(crate) fn scan_cf(
+        &self,
+        cf: &str,
+        range: Range<String>,
+    ) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        self.column_family(cf)?.scan_values(&self.path, range)
+    }
+
+    pub(crate) fn iter_cf<R: RangeBounds<String> + Clone>(
+        &self,
+        cf: &str,
+        range: R,
+    ) -> Result<impl Iterator<Item = Result<(String, Vec<u8>), Error>> + '_, Error> {
+        self.column_family(cf)?.iter_values(&self.path, range)
+    }
+}
+
+/// A database directory holding any number of column families. Every column
+/// family has its own memtable and SSTables, while writes to all of them
+/// share a single write-ahead log.
+///
+/// Its methods are synchronous and do their I/O on the calling thread. To
+/// share a database between threads use `Db`, or `AsyncDb` from async code.
+pub struct Driver {
+    column_families: Vec<ColumnFamily>,
+    wal: Wal,
+    /// When the WAL was last synced, by `Options::clock`.
+    last_sync: Duration,
+    /// Whether the WAL holds records appended since it was last synced.
+    unsynced: bool,
+    /// Why appending to or syncing the WAL last failed, after which writes
+    /// are refused until `resume`, and the size of the segment holding only
+    /// the records that were applied.
+    wal_error: Option<(Error, u64)>,
+    path: PathBuf,
+    next_file: usize,
+    next_column_family: u32,
+    /// Counters shared with the column families and published versions, so
+    /// that readers update them and a `Db` reports them without taking the
+    /// driver.
+    metrics: Arc<Metrics>,
+    options: Options,
+    current: Arc<ArcSwap<Version>>,
+}
+
+impl Driver {
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, Options::default())
+    }
+
+    pub fn open_with_options<P: Into<PathBuf>>(path: P, options: Options) -> Result<Self, Error> {
+        Self::load(path.into(), options)
+    }
 
-    pub async fn write(&mut self, key: String, value: u32) -> Result<(), Error> {
-        if self.master.at_capacity() {
-            self.flush_table().await?;
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(path = %path.display()))
+    )]
+    pub(crate) fn load(path: PathBuf, options: Options) -> Result<Self, Error> {
+        let fs = Arc::clone(&options.file_system);
+        let manifest = match Manifest::load(&*fs, &path)? {
+            Some(manifest) => manifest,
+            None if options.create_if_missing => {
+                create_dir(&*fs, &path, options.sync.syncs_files())?;
+                Manifest {
+                    next_file: 1,
+                    next_column_family: 1,
+                    column_families: vec![ColumnFamilyMeta {
+                        id: 0,
+                        name: String::from(DEFAULT_COLUMN_FAMILY),
+                        options: options.default_column_family.clone(),
+                        log_number: 0,
+                        files: Vec::new(),
+                        value_logs: Vec::new(),
+                    }],
+                }
+            }
+            None => return Err(Error::DatabaseNotFound(path)),
+        };
+
+        let metrics = Arc::new(Metrics::default());
+        let Recovered {
+            column_families,
+            logs,
+            torn,
+        } = recover(&path, &options, manifest.column_families, &metrics)?;
+        // Replay would otherwise stop at the torn record again once the
+        // segment is no longer the last one.
+        if let Some((number, offset)) = torn {
+            wal::truncate_segment(&*fs, &path, number, offset, options.sync.syncs_files())?;
         }
-        self.master.write(key, value);
+
+        let live: HashSet<usize> = column_families
+            .iter()
+            .flat_map(|cf| cf.tables.iter().map(|(file, _)| *file))
+            .collect();
+        for file in list_files(&*fs, &path, SST_EXTENSION)? {
+            if !live.contains(&file) {
+                remove_file(&*fs, &sst_path(&path, file))?;
+            }
+        }
+        let live_value_logs: HashSet<usize> = column_families
+            .iter()
+            .flat_map(|cf| cf.value_logs.keys().copied())
+            .collect();
+        for number in list_files(&*fs, &path, VALUE_LOG_EXTENSION)? {
+            if !live_value_logs.contains(&number) {
+                remove_file(&*fs, &value_log::value_log_path(&path, number))?;
+            }
+        }
+
+        let next_file = logs
+            .iter()
+            .chain(live.iter())
+            .chain(live_value_logs.iter())
+            .map(|n| n + 1)
+            .fold(manifest.next_file, usize::max);
+        let driver = Self {
+            column_families,
+            wal: create_wal(&path, next_file, &options)?,
+            last_sync: options.clock.now(),
+            unsynced: false,
+            wal_error: None,
+            path,
+            next_file: next_file + 1,
+            next_column_family: manifest.next_column_family,
+            metrics,
+            options,
+            current: Arc::default(),
+        };
+        driver.save_manifest()?;
+        driver.publish();
+        event!(
+            DEBUG,
+            column_families = driver.column_families.len(),
+            tables = live.len(),
+            wal = next_file,
+            "opened database"
+        );
+        Ok(driver)
+    }
+
+    /// Reads the database at `path` as it is now without changing anything
+    /// on disk: the WAL is replayed into memory, but no segment is started,
+    /// no manifest written and no file removed. A directory without a
+    /// manifest is refused rather than taken for an empty database.
+    pub(crate) fn load_read_only(path: PathBuf, options: &Options) -> Result<Version, Error> {
+        let manifest = Manifest::load(&*options.file_system, &path)?
+            .ok_or_else(|| Error::DatabaseNotFound(path.clone()))?;
+        let metrics = Arc::new(Metrics::default());
+        let column_families =
+            recover(&path, options, manifest.column_families, &metrics)?.column_families;
+        Ok(Version {
+            path,
+            column_families,
+            metrics,
+        })
+    }
+
+    pub fn stats(&self) -> Statistics {
+        statistics(
+            &self.metrics,
+            &self.options.block_cache,
+            &self.column_families,
+        )
+    }
+
+    pub fn column_families(&self) -> Vec<&str> {
+        self.column_families
+            .iter()
+            .map(|cf| cf.name.as_str())
+            .collect()
+    }
+
+    pub fn create_column_family<S: Into<String>>(
+        &mut self,
+        name: S,
+        options: ColumnFamilyOptions,
+    ) -> Result<(), Error> {
+        self.add_column_family(name.into(), options)
+    }
+
+    pub fn drop_column_family<S: AsRef<str>>(&mut self, name: S) -> Result<(), Error> {
+        self.remove_column_family(name.as_ref())
+    }
+
+    pub fn write(&mut self, key: String, value: Vec<u8>) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write_batch(batch)
+    }
+
+    pub fn delete(&mut self, key: String) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write_batch(batch)
+    }
+
+    pub fn delete_range(&mut self, range: Range<String>) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.delete_range(range);
+        self.write_batch(batch)
+    }
+
+    pub fn write_batch(&mut self, batch: WriteBatch) -> Result<(), Error> {
+        self.apply_batch(batch, &WriteOptions::default())
+    }
+
+    pub fn write_batch_with_options(
+        &mut self,
+        batch: WriteBatch,
+        options: &WriteOptions,
+    ) -> Result<(), Error> {
+        self.apply_batch(batch, options)
+    }
+
+    /// Makes every write so far durable, whatever the `SyncPolicy`.
+    pub fn sync_wal(&mut self) -> Result<(), Error> {
+        self.sync()
+    }
+
+    /// Lets writes through again after appending to or syncing the WAL
+    /// failed. The segment may end in part of the failed record, behind
+    /// which later records would be cut off when the database is next
+    /// opened, or in a whole record of a write that failed to sync, so it is
+    /// first cut back to the records before it and synced. Does nothing if
+    /// the WAL has not failed.
+    pub fn resume(&mut self) -> Result<(), Error> {
+        let Some((_, size)) = self.wal_error else {
+            return Ok(());
+        };
+        let number = self.wal.number();
+        let fs = &*self.options.file_system;
+        // Synced whatever the policy, as the records it keeps include any
+        // synced on request.
+        wal::truncate_segment(fs, &self.path, number, size, true)?;
+        self.wal = Wal::create(fs, &self.path, number)?;
+        self.wal_error = None;
+        self.unsynced = false;
+        self.last_sync = self.options.clock.now();
+        event!(DEBUG, wal = number, "resumed writing the WAL");
         Ok(())
     }
 
-    pub async fn flush_table(&mut self) -> Result<(), Error> {
-        let sst = SSTable::from(&self.master);
-        self.master = MemTable::new();
+    pub fn read<S: AsRef<str>>(&self, key: S) -> Result<Option<Vec<u8>>, Error> {
+        self.column_families[0].read(&self.path, key.as_ref())
+    }
+
+    pub fn read_cf<S: AsRef<str>>(&self, cf: &str, key: S) -> Result<Option<Vec<u8>>, Error> {
+        self.column_families[self.index(cf)?].read(&self.path, key.as_ref())
+    }
+
+    pub fn scan(&self, range: Range<String>) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        self.column_families[0].scan_values(&self.path, range)
+    }
+
+    pub fn scan_cf(&self, cf: &str, range: Range<String>) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        self.column_families[self.index(cf)?].scan_values(&self.path, range)
+    }
+
+    /// Flushes the memtable of every column family.
+    pub fn flush_table(&mut self) -> Result<(), Error> {
+        self.flush_all()
+    }
+
+    pub fn flush_cf(&mut self, cf: &str) -> Result<(), Error> {
+        let index = self.index(cf)?;
+        self.flush(&[index])
+    }
+
+    pub fn compact(&mut self) -> Result<(), Error> {
+        self.compact_column_family(0)
+    }
 
-        let bytes = sst.into_bytes()?;
-        let offset = self.offset;
-        self.offset += 1;
+    pub fn compact_cf(&mut self, cf: &str) -> Result<(), Error> {
+        let index = self.index(cf)?;
+        self.compact_column_family(index)
+    }
+
+    /// Adds SSTables built by `SstFileWriter` to the default column family
+    /// without going through the memtable or the WAL. See
+    /// `ingest_external_files_cf`.
+    pub fn ingest_external_files<P: AsRef<Path>>(&mut self, paths: &[P]) -> Result<(), Error> {
+        let paths: Vec<PathBuf> = paths.iter().map(|p| p.as_ref().to_path_buf()).collect();
+        self.ingest(0, &paths)
+    }
+
+    /// Adds SSTables built by `SstFileWriter` to a column family, all of them
+    /// or none. They become its newest tables, so their values replace any
+    /// written before; the memtable is flushed first if it holds keys in
+    /// their range. The files are hard-linked into the database where the
+    /// file system allows it and must not be modified afterwards. Files whose
+    /// key ranges overlap each other cannot be ingested together.
+    pub fn ingest_external_files_cf<P: AsRef<Path>>(
+        &mut self,
+        cf: &str,
+        paths: &[P],
+    ) -> Result<(), Error> {
+        let index = self.index(cf)?;
+        let paths: Vec<PathBuf> = paths.iter().map(|p| p.as_ref().to_path_buf()).collect();
+        self.ingest(index, &paths)
+    }
+
+    /// Writes a consistent copy of the database to `dir`, which must not exist
+    /// yet, for `Driver::open` to open as an independent database. Nothing is
+    /// flushed: SSTables and value logs are never modified once written, so
+    /// they are hard-linked where the file system allows it, and the WAL
+    /// segments the memtables still depend on are copied as they are now,
+    /// unsynced writes included.
+    pub fn checkpoint<P: AsRef<Path>>(&self, dir: P) -> Result<(), Error> {
+        self.create_checkpoint(dir.as_ref())
+    }
+
+    /// Reclaims space from the value logs of the default column family.
+    pub fn collect_garbage(&mut self) -> Result<(), Error> {
+        self.collect_value_log_garbage(0)
+    }
+
+    pub fn collect_garbage_cf(&mut self, cf: &str) -> Result<(), Error> {
+        let index = self.index(cf)?;
+        self.collect_value_log_garbage(index)
+    }
+
+    pub(crate) fn add_column_family(
+        &mut self,
+        name: String,
+        options: ColumnFamilyOptions,
+    ) -> Result<(), Error> {
+        if self.column_families.iter().any(|cf| cf.name == name) {
+            return Err(Error::ColumnFamilyExists(name));
+        }
+
+        let id = self.next_column_family;
+        self.next_column_family += 1;
+        let log_number = self.wal.number();
+        self.column_families.push(ColumnFamily::new(
+            id,
+            name,
+            options,
+            log_number,
+            Arc::clone(&self.metrics),
+        ));
+        self.save_manifest()?;
+        self.publish();
+        Ok(())
+    }
+
+    pub(crate) fn remove_column_family(&mut self, name: &str) -> Result<(), Error> {
+        if name == DEFAULT_COLUMN_FAMILY {
+            return Err(Error::DropDefaultColumnFamily);
+        }
+
+        let index = self.index(name)?;
+        let cf = self.column_families.remove(index);
+        self.save_manifest()?;
+        self.publish();
+        for (file, _) in cf.tables {
+            self.remove_table_file(&sst_path(&self.path, file))?;
+        }
+        let fs = &*self.options.file_system;
+        for number in cf.value_logs.into_keys() {
+            remove_file(fs, &value_log::value_log_path(&self.path, number))?;
+        }
+        self.purge_logs()
+    }
+
+    pub(crate) fn flush_all(&mut self) -> Result<(), Error> {
+        let all: Vec<usize> = (0..self.column_families.len()).collect();
+        self.flush(&all)
+    }
+
+    pub(crate) fn index(&self, name: &str) -> Result<usize, Error> {
+        self.column_families
+            .iter()
+            .position(|cf| cf.name == name)
+            .ok_or_else(|| Error::ColumnFamilyNotFound(name.to_owned()))
+    }
+
+    fn allocate_file(&mut self) -> usize {
+        let file = self.next_file;
+        self.next_file += 1;
+        file
+    }
+
+    fn apply_batch(&mut self, batch: WriteBatch, options: &WriteOptions) -> Result<(), Error> {
+        let operations = self.resolve(batch)?;
+        self.commit(operations, options.sync)
+    }
+
+    /// Resolves the column family of each operation in a batch.
+    pub(crate) fn resolve(&self, batch: WriteBatch) -> Result<Vec<(usize, Operation)>, Error> {
+        batch
+            .into_operations()
+            .into_iter()
+            .map(|(name, operation)| Ok((self.index(&name)?, operation)))
+            .collect()
+    }
+
+    /// Logs resolved operations as a single WAL record, syncing it if asked
+    /// to or if the `SyncPolicy` calls for it, and then applies them in order
+    /// and publishes the result at once.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(operations = operations.len(), sync))
+    )]
+    pub(crate) fn commit(
+        &mut self,
+        operations: Vec<(usize, Operation)>,
+        sync: bool,
+    ) -> Result<(), Error> {
+        self.check_wal()?;
+        let start = Instant::now();
+        let mut full = Vec::new();
+        for (index, _) in &operations {
+            let master = &self.column_families[*index].master;
+            if master.at_capacity() && !full.contains(index) {
+                full.push(*index);
+            }
+        }
+        if !full.is_empty() {
+            use WriteStallCondition::{Normal, Stopped};
+            event!(
+                DEBUG,
+                column_families = full.len(),
+                "stalling writes to flush"
+            );
+            self.notify_stalls(&full, Normal, Stopped);
+            let flushed = self.flush(&full);
+            self.notify_stalls(&full, Stopped, Normal);
+            flushed?;
+        }
+
+        let record: Vec<(u32, &Operation)> = operations
+            .iter()
+            .map(|(index, operation)| (self.column_families[*index].id, operation))
+            .collect();
+        let bytes = bincode::serialize(&record).map_err(|_| Error::BincodeError)?;
+        let wal_size = self.wal.size();
+        if let Err(e) = self.wal.append(&bytes) {
+            return Err(self.fail_wal(e));
+        }
+        self.unsynced = true;
+        self.metrics.wal_records.fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .wal_bytes_written
+            .fetch_add(self.wal.size() - wal_size, Ordering::Relaxed);
+        event!(
+            TRACE,
+            wal = self.wal.number(),
+            offset = wal_size,
+            bytes = bytes.len(),
+            "appended WAL record"
+        );
+        let sync = sync
+            || match self.options.sync {
+                SyncPolicy::Always => true,
+                SyncPolicy::Interval(interval) => self.since_sync() >= interval,
+                SyncPolicy::OnFlush | SyncPolicy::Never => false,
+            };
+        if sync {
+            if let Err(e) = self.sync() {
+                // The write is not applied, so its record must go as well.
+                if let Some((_, size)) = &mut self.wal_error {
+                    *size = wal_size;
+                }
+                return Err(e);
+            }
+        }
+
+        for (index, operation) in operations {
+            self.metrics.writes.fetch_add(1, Ordering::Relaxed);
+            let bytes = match &operation {
+                Operation::Put { key, value } => key.len() + value.len(),
+                Operation::DeleteRange { start, end } => start.len() + end.len(),
+                Operation::Delete { key } => key.len(),
+            };
+            let metrics = &self.metrics;
+            metrics
+                .user_bytes_written
+                .fetch_add(bytes as u64, Ordering::Relaxed);
+            self.column_families[index].apply(operation);
+        }
+        self.publish();
+        self.metrics.write_latency.record(start.elapsed());
+        event!(TRACE, elapsed = ?start.elapsed(), "committed");
+        Ok(())
+    }
+
+    pub(crate) fn sync(&mut self) -> Result<(), Error> {
+        self.check_wal()?;
+        if let Err(e) = self.wal.sync() {
+            return Err(self.fail_wal(e));
+        }
+        event!(TRACE, wal = self.wal.number(), "synced WAL");
+        self.unsynced = false;
+        self.metrics.wal_syncs.fetch_add(1, Ordering::Relaxed);
+        self.last_sync = self.options.clock.now();
+        Ok(())
+    }
+
+    /// Refuses further writes once appending to or syncing the WAL failed
+    /// with `e`, as a partial record may be left at its end.
+    fn fail_wal(&mut self, e: Error) -> Error {
+        event!(WARN, wal = self.wal.number(), error = %e, "WAL failed");
+        self.wal_error = Some((e.duplicate(), self.wal.size()));
+        e
+    }
+
+    fn check_wal(&self) -> Result<(), Error> {
+        match &self.wal_error {
+            Some((e, _)) => Err(e.duplicate()),
+            None => Ok(()),
+        }
+    }
+
+    fn since_sync(&self) -> Duration {
+        self.options.clock.now().saturating_sub(self.last_sync)
+    }
+
+    /// Syncs the WAL if `SyncPolicy::Interval` calls for it now, so the last
+    /// writes before a quiet period do not wait for the next write. Returns
+    /// how long until the writes still unsynced fall due, which is when to
+    /// call again.
+    pub fn sync_if_due(&mut self) -> Result<Option<Duration>, Error> {
+        let SyncPolicy::Interval(interval) = self.options.sync else {
+            return Ok(None);
+        };
+        if self.unsynced && self.since_sync() >= interval {
+            self.sync()?;
+        }
+        Ok(match self.unsynced {
+            true => Some(interval.saturating_sub(self.since_sync())),
+            false => None,
+        })
+    }
+
+    /// Does the work a `Db` leaves to its background thread: syncs the
+    /// writes that have fallen due and compacts the column families holding
+    /// at least `trigger` tables. Returns how long until it is next due, as
+    /// `sync_if_due` does. A failure is reported to the listeners.
+    pub(crate) fn run_background_work(
+        &mut self,
+        trigger: Option<usize>,
+    ) -> Result<Option<Duration>, Error> {
+        let result = self.background_work(trigger);
+        if let Err(e) = &result {
+            self.notify(|listener| listener.on_background_error(e));
+        }
+        result
+    }
+
+    fn background_work(&mut self, trigger: Option<usize>) -> Result<Option<Duration>, Error> {
+        self.sync_if_due()?;
+        // Compacting a single table would only rewrite it.
+        if let Some(trigger) = trigger.map(|t| t.max(2)) {
+            for index in self.compaction_candidates(trigger) {
+                self.compact_column_family(index)?;
+            }
+        }
+        // Writes may have fallen due while compacting.
+        self.sync_if_due()
+    }
+
+    /// The column families holding at least `trigger` tables.
+    fn compaction_candidates(&self, trigger: usize) -> Vec<usize> {
+        (0..self.column_families.len())
+            .filter(|&index| self.column_families[index].tables.len() >= trigger)
+            .collect()
+    }
+
+    /// Writes the memtables of the given column families to SSTables. The WAL
+    /// is rolled first so the flushed column families no longer need any of
+    /// the older segments, which are removed once no column family does.
+    /// Each memtable stays readable as an immutable one until its table is in
+    /// the saved manifest, and the column families move on to the new segment
+    /// only then, so a flush that fails loses nothing and the next one writes
+    /// the memtables it left.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(column_families = indices.len()))
+    )]
+    pub(crate) fn flush(&mut self, indices: &[usize]) -> Result<(), Error> {
+        // Rolling the WAL would leave a failed segment behind the new one,
+        // where replay takes a partial record for corruption.
+        self.check_wal()?;
+        let start = Instant::now();
+        let log_number = self.allocate_file();
+        self.wal = create_wal(&self.path, log_number, &self.options)?;
+        self.unsynced = false;
+
+        let mut outputs = Vec::new();
+        for &index in indices {
+            let cf = &mut self.column_families[index];
+            let capacity = cf.options.memtable_capacity;
+            let master = std::mem::replace(&mut cf.master, MemTable::with_capacity(capacity));
+            if !master.is_empty() {
+                cf.immutables.push(master);
+            }
+            // Tables already written are removed as unreferenced when the
+            // database is next opened.
+            outputs.push((index, self.write_immutables(index)?));
+        }
+
+        let mut previous = Vec::new();
+        for (index, output) in &outputs {
+            let cf = &mut self.column_families[*index];
+            let tables_before = cf.tables.len();
+            cf.tables.extend(output.tables.iter().cloned());
+            for log in &output.value_logs {
+                cf.value_logs.insert(log.number(), Arc::clone(log));
+            }
+            let immutables = std::mem::take(&mut cf.immutables);
+            let log_before = std::mem::replace(&mut cf.log_number, log_number);
+            previous.push((tables_before, immutables, log_before));
+        }
+        if let Err(e) = self.save_manifest() {
+            for ((index, output), (tables_before, immutables, log_before)) in
+                outputs.iter().zip(previous)
+            {
+                let cf = &mut self.column_families[*index];
+                cf.tables.truncate(tables_before);
+                for log in &output.value_logs {
+                    cf.value_logs.remove(&log.number());
+                }
+                cf.immutables = immutables;
+                cf.log_number = log_before;
+            }
+            return Err(e);
+        }
+        self.publish();
+        self.metrics.flushes.fetch_add(1, Ordering::Relaxed);
+        self.metrics.flush_duration.record(start.elapsed());
+        event!(DEBUG, wal = log_number, elapsed = ?start.elapsed(), "flush completed");
+        for (_, output) in &outputs {
+            for info in &output.jobs {
+                self.notify(|listener| listener.on_flush_completed(info));
+            }
+        }
+        self.purge_logs()
+    }
+
+    /// Writes each immutable memtable of a column family to a table of its
+    /// own, oldest first, without adding anything to the column family.
+    fn write_immutables(&mut self, index: usize) -> Result<FlushOutput, Error> {
+        let mut output = FlushOutput::default();
+        for memtable in self.column_families[index].immutables.clone() {
+            let mut info = FlushJobInfo {
+                column_family: self.column_families[index].name.clone(),
+                entries: memtable.size(),
+                output_files: Vec::new(),
+            };
+            self.notify(|listener| listener.on_flush_begin(&info));
+            let (sst, value_log) = self.separate_values(index, &memtable)?;
+            let (file, table) = self.write_table(index, sst)?;
+            output.tables.push((file, table));
+            info.output_files.push(sst_path(&self.path, file));
+            if let Some(log) = value_log {
+                let path = value_log::value_log_path(&self.path, log.number());
+                info.output_files.push(path);
+                output.value_logs.push(Arc::new(log));
+            }
+            event!(
+                DEBUG,
+                column_family = %info.column_family,
+                entries = info.entries,
+                file,
+                "flushed memtable"
+            );
+            output.jobs.push(info);
+        }
+        Ok(output)
+    }
+
+    /// Merges every table of a column family into one. Tables whose contents
+    /// all fall inside a newer range tombstone are dropped without being read,
+    /// and since the oldest table takes part, the merged table needs no
+    /// tombstones of its own. The column family keeps its tables until the
+    /// merged one is written and in the manifest, so a compaction that fails
+    /// changes nothing.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip(self),
+            fields(column_family = %self.column_families[index].name)
+        )
+    )]
+    pub(crate) fn compact_column_family(&mut self, index: usize) -> Result<(), Error> {
+        let start = Instant::now();
+        let tables = self.column_families[index].tables.clone();
+        let mut live = Vec::new();
+        let mut newer: Vec<&RangeTombstone> = Vec::new();
+        for (_, table) in tables.iter().rev() {
+            if !newer.iter().any(|t| table.covered_by(t)) {
+                let entries: Entries = Box::new(table.iter(..)?);
+                live.push((entries, table.tombstones()));
+            }
+            newer.extend(table.tombstones());
+        }
+
+        event!(
+            DEBUG,
+            inputs = tables.len(),
+            skipped = tables.len() - live.len(),
+            "merging tables"
+        );
+        let entries = Merge::new(live).collect::<Result<Vec<_>, _>>()?;
+        let mut output = Vec::new();
+        if !entries.is_empty() {
+            output.push(self.write_table(index, SSTable::new(entries, Vec::new()))?);
+        }
+        let output_files = output
+            .iter()
+            .map(|(file, _)| sst_path(&self.path, *file))
+            .collect();
+        self.column_families[index].tables = output;
+        if let Err(e) = self.save_manifest() {
+            // The merged table, if the manifest never came to list it, is
+            // removed as unreferenced when the database is next opened.
+            self.column_families[index].tables = tables;
+            return Err(e);
+        }
+        self.publish();
+        self.metrics.compactions.fetch_add(1, Ordering::Relaxed);
+        let duration = start.elapsed();
+        self.metrics.compaction_duration.record(duration);
+        event!(DEBUG, ?duration, "compaction completed");
+
+        let info = CompactionJobInfo {
+            column_family: self.column_families[index].name.clone(),
+            input_files: tables
+                .iter()
+                .map(|(file, _)| sst_path(&self.path, *file))
+                .collect(),
+            output_files,
+            duration,
+        };
+        self.notify(|listener| listener.on_compaction_completed(&info));
+        for path in info.input_files {
+            self.remove_table_file(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the values of a flushed memtable that reach the column family's
+    /// `min_value_log_size` into a new value log, leaving pointers to them in
+    /// the table to be written. Returns the table and the value log, if one
+    /// was needed.
+    fn separate_values(
+        &mut self,
+        index: usize,
+        master: &MemTable,
+    ) -> Result<(SSTable, Option<ValueLog>), Error> {
+        let mut entries = master.items();
+        let mut value_log = None;
+        if let Some(min_size) = self.column_families[index].options.min_value_log_size {
+            let mut log = None;
+            for entry in entries.iter_mut() {
+                let Value::Inline(value) = &entry.value else {
+                    continue;
+                };
+                if value.len() < min_size {
+                    continue;
+                }
+                let log = match &mut log {
+                    Some(log) => log,
+                    None => {
+                        let number = self.allocate_file();
+                        let fs = &*self.options.file_system;
+                        log.insert(ValueLog::create(fs, &self.path, number)?)
+                    }
+                };
+                entry.value = Value::Pointer(log.append(&entry.key, value)?);
+            }
+            if let Some(mut log) = log {
+                if self.options.sync.syncs_files() {
+                    log.sync()?;
+                }
+                event!(
+                    DEBUG,
+                    value_log = log.number(),
+                    bytes = log.size(),
+                    "wrote value log"
+                );
+                self.metrics
+                    .value_log_bytes_written
+                    .fetch_add(log.size(), Ordering::Relaxed);
+                value_log = Some(log);
+            }
+        }
+        let sst = SSTable::new(entries, master.tombstones().to_vec());
+        Ok((sst, value_log))
+    }
+
+    /// Deletes the value logs of a column family in which the share of
+    /// garbage, values no longer referenced by the tree, reaches
+    /// `value_log_gc_threshold`. Their live values are written again and
+    /// flushed into a new value log first, which makes the old pointers to
+    /// them unreachable.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip(self),
+            fields(column_family = %self.column_families[index].name)
+        )
+    )]
+    pub(crate) fn collect_value_log_garbage(&mut self, index: usize) -> Result<(), Error> {
+        let cf = &self.column_families[index];
+        let mut collected = Vec::new();
+        let mut batch = WriteBatch::new();
+        for log in cf.value_logs.values() {
+            let mut live = Vec::new();
+            let mut live_size = 0;
+            for record in log.records()? {
+                if cf.lookup(&record.key)? == Some(Value::Pointer(record.pointer)) {
+                    live_size += record.pointer.size as u64;
+                    live.push(record);
+                }
+            }
+
+            let garbage = 1.0 - live_size as f64 / log.size().max(1) as f64;
+            if garbage >= cf.options.value_log_gc_threshold {
+                event!(
+                    DEBUG,
+                    value_log = log.number(),
+                    garbage,
+                    live = live.len(),
+                    "collecting value log"
+                );
+                collected.push(log.number());
+                for record in live {
+                    batch.put_cf(cf.name.clone(), record.key, record.value);
+                }
+            }
+        }
+        if collected.is_empty() {
+            return Ok(());
+        }
+
+        self.apply_batch(batch, &WriteOptions::default())?;
+        self.flush(&[index])?;
+        let cf = &mut self.column_families[index];
+        let logs: Vec<Arc<ValueLog>> = collected
+            .iter()
+            .filter_map(|number| cf.value_logs.remove(number))
+            .collect();
+        self.save_manifest()?;
+        self.publish();
+        for log in logs {
+            self.metrics
+                .value_log_bytes_reclaimed
+                .fetch_add(log.size(), Ordering::Relaxed);
+            let path = value_log::value_log_path(&self.path, log.number());
+            remove_file(&*self.options.file_system, &path)?;
+        }
+        Ok(())
+    }
 
-        tokio::task::spawn(async move {
-            let _ = write_sst(offset, bytes);
+    /// Every file is read through, verifying its checksums, before any is
+    /// added. All of them are then linked into the database and opened, and
+    /// the manifest lists them at once. If any step fails, none is added and
+    /// the links already made are removed.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip(self),
+            fields(column_family = %self.column_families[index].name)
+        )
+    )]
+    pub(crate) fn ingest(&mut self, index: usize, paths: &[PathBuf]) -> Result<(), Error> {
+        let fs = Arc::clone(&self.options.file_system);
+        let invalid = |file: &Path, reason: &str| Error::InvalidExternalFile {
+            file: file.to_path_buf(),
+            reason: reason.to_owned(),
+        };
+
+        let mut ranges = Vec::new();
+        for path in paths {
+            let cache = Arc::clone(&self.options.block_cache);
+            let table = Table::open(&*fs, path, cache, true, false)?;
+            if !table.tombstones().is_empty() {
+                return Err(invalid(path, "it holds range tombstones"));
+            }
+            for entry in table.iter(..)? {
+                match entry?.value {
+                    Value::Inline(_) => {}
+                    Value::Pointer(_) => return Err(invalid(path, "it points into value logs")),
+                    Value::Tombstone => return Err(invalid(path, "it holds tombstones")),
+                }
+            }
+            let properties = table.properties();
+            if let (Some(smallest), Some(largest)) =
+                (&properties.smallest_key, &properties.largest_key)
+            {
+                ranges.push((smallest.clone(), largest.clone(), path));
+            }
+        }
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            if pair[0].1 >= pair[1].0 {
+                return Err(invalid(pair[1].2, "its keys overlap another file's"));
+            }
+        }
+
+        let overlaps = self.column_families[index]
+            .memtables()
+            .any(|memtable| ranges.iter().any(|(s, l, _)| memtable.overlaps(s, l)));
+        if overlaps {
+            self.flush(&[index])?;
+        }
+
+        let mut files = Vec::new();
+        let mut link = || {
+            for (_, _, path) in &ranges {
+                let file = self.allocate_file();
+                fs.hard_link(path, &sst_path(&self.path, file))?;
+                files.push(file);
+            }
+            files
+                .iter()
+                .map(|&file| Ok((file, Arc::new(open_table(&self.path, file, &self.options)?))))
+                .collect::<Result<Vec<_>, Error>>()
+        };
+        let linked = link();
+        let result = linked.and_then(|tables| {
+            let tables_before = self.column_families[index].tables.len();
+            self.column_families[index].tables.extend(tables);
+            let saved = self.save_manifest();
+            if saved.is_err() {
+                self.column_families[index].tables.truncate(tables_before);
+            }
+            saved
         });
+        if let Err(e) = result {
+            for file in files {
+                // Anything left is removed as unreferenced on the next load.
+                let _ = remove_file(&*fs, &sst_path(&self.path, file));
+            }
+            return Err(e);
+        }
+        self.publish();
+        event!(
+            DEBUG,
+            files = ranges.len(),
+            flushed = overlaps,
+            "ingested external files"
+        );
+        Ok(())
+    }
+
+    /// The manifest is written last, so that a checkpoint cut short by a
+    /// crash can be told apart by having none.
+    pub(crate) fn create_checkpoint(&self, dir: &Path) -> Result<(), Error> {
+        let fs = &*self.options.file_system;
+        if fs.exists(dir) {
+            let message = format!("{} already exists", dir.display());
+            return Err(std::io::Error::new(ErrorKind::AlreadyExists, message).into());
+        }
+        create_dir(fs, dir, true)?;
+
+        for cf in &self.column_families {
+            for (file, _) in &cf.tables {
+                fs.hard_link(&sst_path(&self.path, *file), &sst_path(dir, *file))?;
+            }
+            for &number in cf.value_logs.keys() {
+                let from = value_log::value_log_path(&self.path, number);
+                fs.hard_link(&from, &value_log::value_log_path(dir, number))?;
+            }
+        }
+
+        let min_log = self.column_families.iter().map(|cf| cf.log_number).min();
+        for number in list_files(fs, &self.path, WAL_EXTENSION)? {
+            if Some(number) >= min_log {
+                let bytes = fs.read(&wal::wal_path(&self.path, number))?;
+                let mut file = fs.create(&wal::wal_path(dir, number))?;
+                file.append(&bytes)?;
+                file.sync()?;
+            }
+        }
+
+        self.manifest().save(fs, dir, true)?;
+        event!(DEBUG, dir = %dir.display(), "created checkpoint");
+        Ok(())
+    }
+
+    /// Writes an SSTable with a column family's options, returning its number
+    /// and the table opened for reading.
+    fn write_table(&mut self, index: usize, sst: SSTable) -> Result<(usize, Arc<Table>), Error> {
+        let file = self.allocate_file();
+        let cf = &self.column_families[index];
+        let (bytes, properties) = sst.into_bytes(&cf.options)?;
+        let fs = &*self.options.file_system;
+        write_sst(fs, &self.path, file, bytes, self.options.sync.syncs_files())?;
+        let table = Arc::new(open_table(&self.path, file, &self.options)?);
+
+        self.metrics
+            .raw_bytes_written
+            .fetch_add(properties.raw_data_size, Ordering::Relaxed);
+        self.metrics
+            .bytes_written
+            .fetch_add(properties.data_size, Ordering::Relaxed);
+        event!(
+            DEBUG,
+            file,
+            entries = properties.entries,
+            data_blocks = properties.data_blocks,
+            raw_bytes = properties.raw_data_size,
+            bytes = properties.data_size,
+            "wrote SSTable"
+        );
+        Ok((file, table))
+    }
+
+    fn remove_table_file(&self, path: &Path) -> Result<(), Error> {
+        remove_file(&*self.options.file_system, path)?;
+        self.notify(|listener| listener.on_table_file_deleted(path));
+        Ok(())
+    }
+
+    /// Calls `event` on every registered listener, in the order they were
+    /// added.
+    fn notify(&self, event: impl Fn(&dyn EventListener)) {
+        for listener in &self.options.listeners {
+            event(&**listener);
+        }
+    }
+
+    fn notify_stalls(
+        &self,
+        indices: &[usize],
+        previous: WriteStallCondition,
+        current: WriteStallCondition,
+    ) {
+        for &index in indices {
+            let info = WriteStallInfo {
+                column_family: self.column_families[index].name.clone(),
+                previous,
+                current,
+            };
+            self.notify(|listener| listener.on_stall_conditions_changed(&info));
+        }
+    }
+
+    /// The versions the driver publishes, for readers that do not hold it.
+    pub(crate) fn current(&self) -> Arc<ArcSwap<Version>> {
+        Arc::clone(&self.current)
+    }
+
+    /// Makes the column families as they are now the current version.
+    fn publish(&self) {
+        let version = Version {
+            path: self.path.clone(),
+            column_families: self.column_families.clone(),
+            metrics: Arc::clone(&self.metrics),
+        };
+        self.current.store(Arc::new(version));
+    }
 
+    fn manifest(&self) -> Manifest {
+        Manifest {
+            next_file: self.next_file,
+            next_column_family: self.next_column_family,
+            column_families: self.column_families.iter().map(|cf| cf.meta()).collect(),
+        }
+    }
+
+    fn save_manifest(&self) -> Result<(), Error> {
+        let fs = &*self.options.file_system;
+        self.manifest()
+            .save(fs, &self.path, self.options.sync.syncs_files())
+    }
+
+    fn purge_logs(&self) -> Result<(), Error> {
+        let min_log = self
+            .column_families
+            .iter()
+            .map(|cf| cf.log_number)
+            .min()
+            .unwrap_or(self.wal.number());
+        let fs = &*self.options.file_system;
+        for number in list_files(fs, &self.path, WAL_EXTENSION)? {
+            if number < min_log {
+                remove_file(fs, &wal::wal_path(&self.path, number))?;
+            }
+        }
         Ok(())
     }
 }
 
-fn write_sst(offset: usize, bytes: Vec<u8>) -> Result<(), Error> {
-    let path = format!("logos/{}.sst", offset);
-    let mut file = File::create(path)?;
-    file.write_all(&bytes)?;
+/// The tables and value logs a flush wrote for a column family, which it
+/// takes once the manifest lists them, and the jobs that wrote them.
+#[derive(Default)]
+struct FlushOutput {
+    tables: Vec<(usize, Arc<Table>)>,
+    value_logs: Vec<Arc<ValueLog>>,
+    jobs: Vec<FlushJobInfo>,
+}
+
+/// The state `recover` rebuilds from the files of a database.
+struct Recovered {
+    column_families: Vec<ColumnFamily>,
+    /// Every WAL segment in the directory.
+    logs: Vec<usize>,
+    /// The last segment and the offset of the torn record replay stopped at.
+    torn: Option<(usize, u64)>,
+}
+
+/// Opens the column families a manifest lists and replays the WAL segments
+/// they still need into their memtables. A damaged record ending the last
+/// segment is where a crash cut a write short, so replay stops there rather
+/// than failing. Nothing on disk is changed.
+fn recover(
+    path: &Path,
+    options: &Options,
+    metas: Vec<ColumnFamilyMeta>,
+    metrics: &Arc<Metrics>,
+) -> Result<Recovered, Error> {
+    let fs = &*options.file_system;
+    let mut column_families = Vec::new();
+    for meta in metas {
+        let cf_options = match meta.name == DEFAULT_COLUMN_FAMILY {
+            true => options.default_column_family.clone(),
+            false => meta.options,
+        };
+        let mut cf = ColumnFamily::new(
+            meta.id,
+            meta.name,
+            cf_options,
+            meta.log_number,
+            Arc::clone(metrics),
+        );
+        for file in meta.files {
+            cf.tables
+                .push((file, Arc::new(open_table(path, file, options)?)));
+        }
+        for number in meta.value_logs {
+            cf.value_logs
+                .insert(number, Arc::new(ValueLog::open(fs, path, number)?));
+        }
+        column_families.push(cf);
+    }
+
+    let min_log = column_families.iter().map(|cf| cf.log_number).min();
+    let logs = list_files(fs, path, WAL_EXTENSION)?;
+    let mut torn = None;
+    for &number in logs.iter().filter(|n| Some(**n) >= min_log) {
+        event!(DEBUG, segment = number, "replaying WAL segment");
+        let records = match Some(&number) == logs.last() {
+            true => {
+                let (records, tail) = wal::read_last_segment(fs, path, number)?;
+                if let Some(offset) = tail {
+                    event!(WARN, segment = number, offset, "dropping a torn WAL tail");
+                    torn = Some((number, offset));
+                }
+                records
+            }
+            false => wal::read_segment(fs, path, number)?,
+        };
+        for record in records {
+            let operations: Vec<(u32, Operation)> =
+                bincode::deserialize(&record).map_err(|_| Error::BincodeError)?;
+            for (id, operation) in operations {
+                let cf = column_families.iter_mut().find(|cf| cf.id == id);
+                if let Some(cf) = cf.filter(|cf| number >= cf.log_number) {
+                    cf.apply(operation);
+                }
+            }
+        }
+    }
+    Ok(Recovered {
+        column_families,
+        logs,
+        torn,
+    })
+}
+
+pub fn sst_path(path: &Path, file: usize) -> PathBuf {
+    path.join(format!("{}.{}", file, SST_EXTENSION))
+}
+
+pub(crate) fn write_sst(
+    fs: &dyn FileSystem,
+    path: &Path,
+    file: usize,
+    bytes: Vec<u8>,
+    sync: bool,
+) -> Result<(), Error> {
+    let mut file = fs.create(&sst_path(path, file))?;
+    file.append(&bytes)?;
+    if sync {
+        file.sync()?;
+    }
     Ok(())
 }
 
+/// Gathers the counters in `metrics` and the block cache with the memtable
+/// sizes and table counts of the given column families.
+fn statistics(
+    metrics: &Metrics,
+    cache: &BlockCache,
+    column_families: &[ColumnFamily],
+) -> Statistics {
+    let mut stats = Statistics {
+        block_cache_hits: cache.hits(),
+        block_cache_misses: cache.misses(),
+        ..Statistics::default()
+    };
+    metrics.report(&mut stats);
+    for cf in column_families {
+        stats.memtable_size += cf.memtables().map(MemTable::size).sum::<usize>() as u64;
+        stats.tables.insert(cf.name.clone(), cf.tables.len() as u64);
+    }
+    stats
+}
+
+/// Starts WAL segment `number`. Unless the policy is `Never`, its directory
+/// entry is synced, so that records synced into it cannot lose their file to
+/// a power loss.
+fn create_wal(path: &Path, number: usize, options: &Options) -> Result<Wal, Error> {
+    let fs = &*options.file_system;
+    let wal = Wal::create(fs, path, number)?;
+    if options.sync.syncs_files() {
+        fs.sync_dir(path)?;
+    }
+    Ok(wal)
+}
+
+fn open_table(path: &Path, file: usize, options: &Options) -> Result<Table, Error> {
+    Table::open(
+        &*options.file_system,
+        &sst_path(path, file),
+        Arc::clone(&options.block_cache),
+        options.paranoid_checks,
+        options.mmap_reads,
+    )
+}
+
+pub(crate) fn remove_file(fs: &dyn FileSystem, path: &Path) -> Result<(), Error> {
+    match fs.remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Creates the directory `path` and its missing parents. With `sync`, the
+/// entry of a newly created `path` is synced into its parent, since a power
+/// loss would otherwise take the directory along with everything in it.
+pub(crate) fn create_dir(fs: &dyn FileSystem, path: &Path, sync: bool) -> Result<(), Error> {
+    if fs.exists(path) {
+        return Ok(());
+    }
+    fs.create_dir_all(path)?;
+    match path.parent() {
+        Some(parent) if sync && parent.as_os_str().is_empty() => fs.sync_dir(Path::new("."))?,
+        Some(parent) if sync => fs.sync_dir(parent)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Lists the numbered files with the given extension, in ascending order.
+pub(crate) fn list_files(
+    fs: &dyn FileSystem,
+    path: &Path,
+    extension: &str,
+) -> Result<Vec<usize>, Error> {
+    let mut numbers = Vec::new();
+    for path in fs.list_dir(path)? {
+        if path.extension().and_then(|e| e.to_str()) != Some(extension) {
+            continue;
+        }
+        if let Some(number) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse().ok())
+        {
+            numbers.push(number);
+        }
+    }
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
 #[cfg(test)]
 mod test {
+    use crate::block;
+    use crate::cache::BlockCache;
+    use crate::compression::Compression;
     use crate::db::Entry;
+    use crate::env::MemoryFileSystem;
 
     use super::*;
 
-    #[tokio::test]
-    async fn memtable_capacity() {
-        let mut driver = Driver {
-            master: MemTable::with_capacity(10),
-            offset: 0,
-        };
+    fn value(value: u32) -> Vec<u8> {
+        value.to_string().into_bytes()
+    }
+
+    fn entry(key: &str, v: u32) -> (String, Vec<u8>) {
+        (String::from(key), value(v))
+    }
+
+    fn range(start: &str, end: &str) -> Range<String> {
+        String::from(start)..String::from(end)
+    }
+
+    fn memory() -> Options {
+        Options {
+            file_system: Arc::new(MemoryFileSystem::new()),
+            ..Options::default()
+        }
+    }
+
+    fn with_capacity(capacity: usize) -> Options {
+        Options {
+            default_column_family: ColumnFamilyOptions {
+                memtable_capacity: capacity,
+                ..ColumnFamilyOptions::default()
+            },
+            ..memory()
+        }
+    }
+
+    #[test]
+    fn memtable_capacity() {
+        let dir = Path::new("/db");
+        let mut driver = Driver::open_with_options(dir, with_capacity(10)).unwrap();
 
         for i in 0..10 {
-            driver.write(i.to_string(), i).await.unwrap();
+            driver.write(i.to_string(), value(i)).unwrap();
         }
 
-        assert!(driver.master.at_capacity());
+        assert!(driver.column_families[0].master.at_capacity());
 
-        driver.write(String::from("11"), 11).await.unwrap();
+        driver.write(String::from("11"), value(11)).unwrap();
 
+        let master = &driver.column_families[0].master;
         assert_eq!(
-            driver.master.items(),
+            master.items(),
             vec![Entry {
                 key: String::from("11"),
-                value: 11,
+                value: Value::Inline(value(11)),
             }]
-        )
+        );
+    }
+
+    #[test]
+    fn delete_range_in_memtable() {
+        let (dir, options) = (Path::new("/db"), memory());
+        let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+        for key in ["a", "b", "c", "d"] {
+            driver.write(String::from(key), value(1)).unwrap();
+        }
+
+        driver.delete_range(range("b", "d")).unwrap();
+        assert_eq!(Some(value(1)), driver.read("a").unwrap());
+        assert_eq!(None, driver.read("b").unwrap());
+        assert_eq!(None, driver.read("c").unwrap());
+        assert_eq!(Some(value(1)), driver.read("d").unwrap());
+
+        driver.write(String::from("c"), value(2)).unwrap();
+        assert_eq!(Some(value(2)), driver.read("c").unwrap());
+        assert_eq!(
+            driver.scan(range("a", "z")).unwrap(),
+            vec![entry("a", 1), entry("c", 2), entry("d", 1)]
+        );
+    }
+
+    #[test]
+    fn delete_range_across_tables() {
+        let (dir, options) = (Path::new("/db"), memory());
+        let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+        for key in ["tenant1/a", "tenant1/b", "tenant2/a"] {
+            driver.write(String::from(key), value(1)).unwrap();
+        }
+        driver.flush_table().unwrap();
+
+        driver.delete_range(range("tenant1/", "tenant10")).unwrap();
+        driver.flush_table().unwrap();
+        driver.write(String::from("tenant1/b"), value(2)).unwrap();
+
+        assert_eq!(None, driver.read("tenant1/a").unwrap());
+        assert_eq!(Some(value(2)), driver.read("tenant1/b").unwrap());
+        assert_eq!(Some(value(1)), driver.read("tenant2/a").unwrap());
+        assert_eq!(
+            driver.scan(range("tenant", "tenant3")).unwrap(),
+            vec![entry("tenant1/b", 2), entry("tenant2/a", 1)]
+        );
+    }
+
+    #[test]
+    fn point_deletes_across_tables() {
+        let (dir, options) = (Path::new("/db"), memory());
+        let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+        for key in ["a", "b", "c"] {
+            driver.write(String::from(key), value(1)).unwrap();
+        }
+        driver.flush_table().unwrap();
+        driver.delete(String::from("b")).unwrap();
+        driver.flush_table().unwrap();
+
+        let tables = &driver.column_families[0].tables;
+        assert!(tables
+            .iter()
+            .all(|(_, table)| table.tombstones().is_empty()));
+        assert_eq!(
+            vec![Entry {
+                key: String::from("b"),
+                value: Value::Tombstone,
+            }],
+            tables[1].1.entries().unwrap()
+        );
+        assert_eq!(None, driver.read("b").unwrap());
+        assert_eq!(
+            driver.scan(range("a", "z")).unwrap(),
+            vec![entry("a", 1), entry("c", 1)]
+        );
+
+        driver.compact().unwrap();
+        let tables = &driver.column_families[0].tables;
+        assert_eq!(2, tables[0].1.entries().unwrap().len());
+        driver.delete(String::from("a")).unwrap();
+        drop(driver);
+
+        let driver = Driver::open_with_options(dir, options).unwrap();
+        assert_eq!(None, driver.read("a").unwrap());
+        assert_eq!(None, driver.read("b").unwrap());
+        assert_eq!(driver.scan(range("a", "z")).unwrap(), vec![entry("c", 1)]);
+    }
+
+    #[test]
+    fn compaction_drops_covered_tables() {
+        let (dir, options) = (Path::new("/db"), memory());
+        let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+        for key in ["b", "c"] {
+            driver.write(String::from(key), value(1)).unwrap();
+        }
+        driver.flush_table().unwrap();
+        for key in ["a", "e"] {
+            driver.write(String::from(key), value(1)).unwrap();
+        }
+        driver.flush_table().unwrap();
+        driver.delete_range(range("b", "e")).unwrap();
+        driver.flush_table().unwrap();
+
+        driver.compact().unwrap();
+        let tables = &driver.column_families[0].tables;
+        assert_eq!(1, tables.len());
+        assert_eq!(
+            vec![tables[0].0],
+            list_files(&*options.file_system, dir, SST_EXTENSION).unwrap()
+        );
+        let keys: Vec<String> = tables[0]
+            .1
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+        assert_eq!(keys, vec!["a", "e"]);
+        assert!(tables[0].1.tombstones().is_empty());
+        assert_eq!(
+            driver.scan(range("a", "z")).unwrap(),
+            vec![entry("a", 1), entry("e", 1)]
+        );
+    }
+
+    #[test]
+    fn recover_from_wal() {
+        let (dir, options) = (Path::new("/db"), memory());
+        {
+            let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+            driver.write(String::from("a"), value(1)).unwrap();
+            driver.flush_table().unwrap();
+            driver.write(String::from("b"), value(2)).unwrap();
+            driver.delete_range(range("a", "b")).unwrap();
+        }
+
+        let driver = Driver::open_with_options(dir, options.clone()).unwrap();
+        assert_eq!(None, driver.read("a").unwrap());
+        assert_eq!(Some(value(2)), driver.read("b").unwrap());
+    }
+
+    #[test]
+    fn torn_wal_tail_is_truncated() {
+        for tail in [vec![0; 64], [5, 0, 0, 0, 1, 2, 3, 4, 0, 0, 0].to_vec()] {
+            let (dir, options) = (Path::new("/db"), memory());
+            let fs = Arc::clone(&options.file_system);
+            {
+                let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+                driver.write(String::from("a"), value(1)).unwrap();
+                driver.write(String::from("b"), value(2)).unwrap();
+            }
+            let number = *list_files(&*fs, dir, WAL_EXTENSION)
+                .unwrap()
+                .last()
+                .unwrap();
+            fs.append(&wal::wal_path(dir, number))
+                .unwrap()
+                .append(&tail)
+                .unwrap();
+
+            {
+                let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+                assert_eq!(Some(value(2)), driver.read("b").unwrap());
+                driver.write(String::from("c"), value(3)).unwrap();
+            }
+            // The torn segment is no longer the last one.
+            let driver = Driver::open_with_options(dir, options.clone()).unwrap();
+            assert_eq!(Some(value(1)), driver.read("a").unwrap());
+            assert_eq!(Some(value(3)), driver.read("c").unwrap());
+        }
+    }
+
+    #[test]
+    fn damaged_wal_record_is_reported() {
+        let (dir, options) = (Path::new("/db"), memory());
+        let fs = Arc::clone(&options.file_system);
+        {
+            let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+            driver.write(String::from("a"), value(1)).unwrap();
+            driver.write(String::from("b"), value(2)).unwrap();
+        }
+        let path = wal::wal_path(dir, 1);
+        let mut bytes = fs.read(&path).unwrap();
+        bytes[wal::HEADER_SIZE + 1] ^= 1;
+        fs.create(&path).unwrap().append(&bytes).unwrap();
+
+        assert!(matches!(
+            Driver::open_with_options(dir, options.clone()),
+            Err(Error::Corruption { offset: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn column_families() {
+        let (dir, options) = (Path::new("/db"), memory());
+        {
+            let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+            driver
+                .create_column_family("index", ColumnFamilyOptions::default())
+                .unwrap();
+            driver
+                .create_column_family("meta", ColumnFamilyOptions::default())
+                .unwrap();
+            assert!(matches!(
+                driver.create_column_family("meta", ColumnFamilyOptions::default()),
+                Err(Error::ColumnFamilyExists(_))
+            ));
+
+            let mut batch = WriteBatch::new();
+            batch.put(String::from("user/1"), value(1));
+            batch.put_cf("index", String::from("age/30/1"), value(1));
+            batch.put_cf("meta", String::from("users"), value(1));
+            driver.write_batch(batch).unwrap();
+            driver.flush_cf("index").unwrap();
+
+            driver.drop_column_family("meta").unwrap();
+            assert!(matches!(
+                driver.drop_column_family(DEFAULT_COLUMN_FAMILY),
+                Err(Error::DropDefaultColumnFamily)
+            ));
+        }
+
+        let driver = Driver::open_with_options(dir, options.clone()).unwrap();
+        assert_eq!(
+            vec![DEFAULT_COLUMN_FAMILY, "index"],
+            driver.column_families()
+        );
+        assert_eq!(Some(value(1)), driver.read("user/1").unwrap());
+        assert_eq!(None, driver.read("age/30/1").unwrap());
+        assert_eq!(Some(value(1)), driver.read_cf("index", "age/30/1").unwrap());
+        assert!(matches!(
+            driver.read_cf("meta", "users"),
+            Err(Error::ColumnFamilyNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn write_batch_is_all_or_nothing() {
+        let (dir, options) = (Path::new("/db"), memory());
+        let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put(String::from("a"), value(1));
+        batch.put_cf("missing", String::from("b"), value(2));
+        assert!(driver.write_batch(batch).is_err());
+        assert_eq!(None, driver.read("a").unwrap());
+    }
+
+    #[test]
+    fn obsolete_logs_are_removed() {
+        let (dir, options) = (Path::new("/db"), memory());
+        let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+        driver
+            .create_column_family("index", ColumnFamilyOptions::default())
+            .unwrap();
+        driver.write(String::from("a"), value(1)).unwrap();
+        driver
+            .write_batch({
+                let mut batch = WriteBatch::new();
+                batch.put_cf("index", String::from("b"), value(1));
+                batch
+            })
+            .unwrap();
+
+        let first_log = driver.wal.number();
+        driver.flush_cf(DEFAULT_COLUMN_FAMILY).unwrap();
+        assert!(list_files(&*options.file_system, dir, WAL_EXTENSION)
+            .unwrap()
+            .contains(&first_log));
+
+        driver.flush_cf("index").unwrap();
+        assert!(!list_files(&*options.file_system, dir, WAL_EXTENSION)
+            .unwrap()
+            .contains(&first_log));
+    }
+
+    #[test]
+    fn compressed_tables() {
+        let (dir, options) = (Path::new("/db"), memory());
+        let key = |i: u32| format!("{{\"user\":{},\"kind\":\"profile\"}}", i);
+
+        for (compression, keys) in [(Compression::Zstd, 0..1000), (Compression::Lz4, 1000..2000)] {
+            let options = Options {
+                default_column_family: ColumnFamilyOptions {
+                    compression,
+                    ..ColumnFamilyOptions::default()
+                },
+                ..options.clone()
+            };
+            let mut driver = Driver::open_with_options(dir, options).unwrap();
+            for i in keys {
+                driver.write(key(i), value(i)).unwrap();
+            }
+            driver.flush_table().unwrap();
+            assert!(driver.stats().compression_ratio() > 3.0);
+        }
+
+        let driver = Driver::open_with_options(dir, options).unwrap();
+        assert_eq!(Some(value(7)), driver.read(key(7)).unwrap());
+        assert_eq!(Some(value(1007)), driver.read(key(1007)).unwrap());
+    }
+
+    #[test]
+    fn corrupted_table() {
+        let (dir, options) = (Path::new("/db"), memory());
+        let file = {
+            let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+            driver.write(String::from("a"), value(1)).unwrap();
+            driver.flush_table().unwrap();
+            driver.column_families[0].tables[0].0
+        };
+
+        let path = sst_path(dir, file);
+        let fs = &*options.file_system;
+        let mut bytes = fs.read(&path).unwrap();
+        bytes[block::HEADER_SIZE] ^= 0xff;
+        fs.create(&path).unwrap().append(&bytes).unwrap();
+
+        let driver = Driver::open_with_options(dir, options.clone()).unwrap();
+        match driver.read("a") {
+            Err(Error::Corruption { file, offset }) => {
+                assert_eq!(path, file);
+                assert_eq!(0, offset);
+            }
+            _ => panic!("expected corruption"),
+        }
+    }
+
+    #[test]
+    fn shared_block_cache() {
+        let cache = Arc::new(BlockCache::new(1 << 20));
+        let mut drivers = Vec::new();
+        for _ in 0..2 {
+            let dir = Path::new("/db");
+            let options = Options {
+                block_cache: Arc::clone(&cache),
+                ..memory()
+            };
+            let mut driver = Driver::open_with_options(dir, options).unwrap();
+            driver.write(String::from("a"), value(1)).unwrap();
+            driver.flush_table().unwrap();
+            drivers.push((dir, driver));
+        }
+
+        for (_, driver) in &drivers {
+            assert_eq!(Some(value(1)), driver.read("a").unwrap());
+            assert_eq!(Some(value(1)), driver.read("a").unwrap());
+        }
+        let stats = drivers[0].1.stats();
+        assert_eq!(4, stats.block_cache_misses);
+        assert_eq!(4, stats.block_cache_hits);
+        assert_eq!(0.5, stats.block_cache_hit_rate());
+    }
+
+    #[test]
+    fn mmap_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            mmap_reads: true,
+            ..Options::default()
+        };
+        let mut driver = Driver::open_with_options(dir.path(), options).unwrap();
+        for i in 0..100 {
+            driver.write(format!("{:03}", i), value(i)).unwrap();
+        }
+        driver.flush_table().unwrap();
+        driver.delete_range(range("010", "020")).unwrap();
+        driver.flush_table().unwrap();
+        driver.compact().unwrap();
+
+        assert_eq!(Some(value(42)), driver.read("042").unwrap());
+        assert_eq!(None, driver.read("015").unwrap());
+        assert_eq!(90, driver.scan(range("000", "999")).unwrap().len());
+    }
+
+    #[test]
+    fn value_log() {
+        let dir = Path::new("/db");
+        let options = Options {
+            default_column_family: ColumnFamilyOptions {
+                min_value_log_size: Some(1024),
+                ..ColumnFamilyOptions::default()
+            },
+            ..memory()
+        };
+        let blob = |i: u32| vec![i as u8; 4096];
+        let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+        for i in 0..10 {
+            driver.write(format!("blob/{}", i), blob(i)).unwrap();
+        }
+        driver.write(String::from("small"), value(1)).unwrap();
+        driver.flush_table().unwrap();
+        assert!(driver.stats().value_log_bytes_written > 10 * 4096);
+        assert!(driver.stats().raw_bytes_written < 4096);
+        let first_log = *driver.column_families[0].value_logs.keys().next().unwrap();
+
+        for i in 0..8 {
+            driver.write(format!("blob/{}", i), blob(i + 100)).unwrap();
+        }
+        driver.flush_table().unwrap();
+        let written = driver.stats().value_log_bytes_written;
+        driver.compact().unwrap();
+        assert_eq!(written, driver.stats().value_log_bytes_written);
+
+        driver.collect_garbage().unwrap();
+        let logs = list_files(&*options.file_system, dir, VALUE_LOG_EXTENSION).unwrap();
+        assert_eq!(2, logs.len());
+        assert!(!logs.contains(&first_log));
+        assert!(driver.stats().value_log_bytes_reclaimed > 10 * 4096);
+
+        assert_eq!(Some(blob(9)), driver.read("blob/9").unwrap());
+        assert_eq!(Some(blob(100)), driver.read("blob/0").unwrap());
+        assert_eq!(Some(value(1)), driver.read("small").unwrap());
+        assert_eq!(11, driver.scan(range("a", "z")).unwrap().len());
+
+        drop(driver);
+        let driver = Driver::open_with_options(dir, options).unwrap();
+        assert_eq!(Some(blob(8)), driver.read("blob/8").unwrap());
+        assert_eq!(Some(blob(107)), driver.read("blob/7").unwrap());
+    }
+
+    #[test]
+    fn statistics() {
+        let mut driver = Driver::open_with_options("/db", memory()).unwrap();
+        for i in 0..10 {
+            driver.write(format!("a/{}", i), value(i)).unwrap();
+        }
+        driver.flush_table().unwrap();
+        for i in 0..10 {
+            driver.write(format!("b/{}", i), value(i)).unwrap();
+        }
+        driver.flush_table().unwrap();
+        driver.write(String::from("c"), value(0)).unwrap();
+
+        assert_eq!(Some(value(3)), driver.read("a/3").unwrap());
+        assert_eq!(None, driver.read("d").unwrap());
+        assert_eq!(21, driver.scan(range("a", "z")).unwrap().len());
+
+        let stats = driver.stats();
+        assert_eq!(21, stats.writes);
+        assert_eq!(20 * 4 + 2, stats.user_bytes_written);
+        assert_eq!((2, 1), (stats.reads, stats.scans));
+        assert_eq!(22, stats.bytes_read);
+        assert_eq!(2, stats.read_latency.count);
+        assert_eq!(21, stats.write_latency.count);
+        assert_eq!(2, stats.flushes);
+        assert_eq!(1, stats.memtable_size);
+        assert_eq!(Some(&2), stats.tables.get(DEFAULT_COLUMN_FAMILY));
+        // "a/3" skips the newer table of "b" keys and "d" skips both.
+        assert_eq!((4, 3), (stats.key_range_checks, stats.key_range_skips));
+        assert!(stats.wal_bytes_written > stats.user_bytes_written);
+
+        let text = stats.to_prometheus();
+        assert!(text.contains("logos_reads_total 2\n"));
+        assert!(text.contains("logos_key_range_skips_total 3\n"));
+        assert!(text.contains("logos_tables{column_family=\"default\"} 2\n"));
+    }
+
+    #[test]
+    fn checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, checkpoint) = (dir.path().join("db"), dir.path().join("checkpoint"));
+        let options = Options {
+            default_column_family: ColumnFamilyOptions {
+                min_value_log_size: Some(1024),
+                ..ColumnFamilyOptions::default()
+            },
+            ..Options::default()
+        };
+        let mut driver = Driver::open_with_options(&path, options.clone()).unwrap();
+        driver.write(String::from("blob"), vec![7; 4096]).unwrap();
+        for i in 0..10 {
+            driver.write(format!("{}", i), value(i)).unwrap();
+        }
+        driver.flush_table().unwrap();
+        driver.write(String::from("unflushed"), value(1)).unwrap();
+
+        driver.checkpoint(&checkpoint).unwrap();
+        let error = driver.checkpoint(&checkpoint).unwrap_err();
+        assert!(matches!(error, Error::IoError(e) if e.kind() == ErrorKind::AlreadyExists));
+
+        driver.write(String::from("later"), value(2)).unwrap();
+        driver.delete_range(range("0", "5")).unwrap();
+        driver.flush_table().unwrap();
+        driver.compact().unwrap();
+
+        let copy = Driver::open_with_options(&checkpoint, options).unwrap();
+        assert_eq!(Some(vec![7; 4096]), copy.read("blob").unwrap());
+        assert_eq!(Some(value(3)), copy.read("3").unwrap());
+        assert_eq!(Some(value(1)), copy.read("unflushed").unwrap());
+        assert_eq!(None, copy.read("later").unwrap());
+        assert_eq!(None, driver.read("3").unwrap());
+        assert_eq!(Some(value(2)), driver.read("later").unwrap());
     }
 }
@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::ops::Range;
+
+use crate::batch::{Operation, WriteBatch};
+use crate::bitcask::Bitcask;
+use crate::driver::{Driver, DEFAULT_COLUMN_FAMILY};
+use crate::Error;
+
+/// The operations every storage engine offers on its default column family,
+/// so callers can be written once against this trait and run on whichever
+/// engine suits the workload, or on `MemoryEngine` in tests.
+pub trait StorageEngine {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Returns the entries in `range` in key order.
+    fn scan(&self, range: Range<String>) -> Result<Vec<(String, Vec<u8>)>, Error>;
+
+    /// Applies every operation of `batch` or, on failure, none of them.
+    fn write_batch(&mut self, batch: WriteBatch) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Persists any writes the engine still buffers in memory.
+    fn flush(&mut self) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Releases the engine and its files. Writes already applied remain
+    /// durable to the same extent they would after dropping the engine.
+    fn close(self) -> impl Future<Output = Result<(), Error>> + Send
+    where
+        Self: Sized;
+
+    fn put(&mut self, key: String, value: Vec<u8>) -> impl Future<Output = Result<(), Error>> + Send
+    where
+        Self: Send,
+    {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write_batch(batch)
+    }
+
+    fn delete(&mut self, key: String) -> impl Future<Output = Result<(), Error>> + Send
+    where
+        Self: Send,
+    {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write_batch(batch)
+    }
+}
+
+impl StorageEngine for Driver {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.read(key)
+    }
+
+    fn scan(&self, range: Range<String>) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        Driver::scan(self, range)
+    }
+
+    async fn write_batch(&mut self, batch: WriteBatch) -> Result<(), Error> {
+        Driver::write_batch(self, batch)
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        self.flush_table()
+    }
+
+    async fn close(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl StorageEngine for Bitcask {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.read(key)
+    }
+
+    fn scan(&self, range: Range<String>) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        Bitcask::scan(self, range)
+    }
+
+    async fn write_batch(&mut self, batch: WriteBatch) -> Result<(), Error> {
+        Bitcask::write_batch(self, batch).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        Bitcask::flush(self).await
+    }
+
+    async fn close(self) -> Result<(), Error> {
+        Bitcask::close(self).await
+    }
+}
+
+/// An engine keeping everything in a `BTreeMap`, for tests and for data that
+/// does not need to outlive the process. Like `Bitcask`, it only has the
+/// default column family.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryEngine {
+    items: BTreeMap<String, Vec<u8>>,
+}
+
+impl MemoryEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StorageEngine for MemoryEngine {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.items.get(key).cloned())
+    }
+
+    fn scan(&self, range: Range<String>) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        if range.start >= range.end {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .items
+            .range(range)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    async fn write_batch(&mut self, batch: WriteBatch) -> Result<(), Error> {
+        let operations = batch.into_operations();
+        if let Some((cf, _)) = operations
+            .iter()
+            .find(|(cf, _)| cf != DEFAULT_COLUMN_FAMILY)
+        {
+            return Err(Error::ColumnFamilyNotFound(cf.clone()));
+        }
+
+        for (_, operation) in operations {
+            match operation {
+                Operation::Put { key, value } => {
+                    self.items.insert(key, value);
+                }
+                Operation::Delete { key } => {
+                    self.items.remove(&key);
+                }
+                Operation::DeleteRange { start, end } if start >= end => {}
+                Operation::DeleteRange { start, end } => {
+                    let deleted: Vec<String> = self
+                        .items
+                        .range(start..end)
+                        .map(|(k, _)| k.clone())
+                        .collect();
+                    for key in deleted {
+                        self.items.remove(&key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn close(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn value(value: u32) -> Vec<u8> {
+        value.to_string().into_bytes()
+    }
+
+    /// Runs the same workload against any engine.
+    async fn exercise<E: StorageEngine + Send>(mut engine: E) {
+        for i in 0..10 {
+            engine.put(format!("key/{}", i), value(i)).await.unwrap();
+        }
+        engine.delete(String::from("key/3")).await.unwrap();
+        engine.flush().await.unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put(String::from("key/3"), value(33));
+        batch.delete_range(String::from("key/5")..String::from("key/8"));
+        batch.delete(String::from("key/9"));
+        engine.write_batch(batch).await.unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put(String::from("key/0"), value(100));
+        batch.put_cf("missing", String::from("key/0"), value(100));
+        assert!(engine.write_batch(batch).await.is_err());
+
+        assert_eq!(Some(value(0)), engine.get("key/0").unwrap());
+        assert_eq!(Some(value(33)), engine.get("key/3").unwrap());
+        assert_eq!(None, engine.get("key/6").unwrap());
+        let keys: Vec<String> = engine
+            .scan(String::from("key/")..String::from("key0"))
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(
+            vec!["key/0", "key/1", "key/2", "key/3", "key/4", "key/8"],
+            keys
+        );
+        engine.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn engines_are_interchangeable() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise(Driver::open(dir.path().join("lsm")).unwrap()).await;
+        exercise(Bitcask::open(dir.path().join("bitcask")).await.unwrap()).await;
+        exercise(MemoryEngine::new()).await;
+    }
+
+    /// Ranges whose start is not before their end hold no keys.
+    async fn empty_ranges<E: StorageEngine + Send>(mut engine: E) {
+        for key in ["a", "b", "c"] {
+            engine.put(String::from(key), value(1)).await.unwrap();
+        }
+        let mut batch = WriteBatch::new();
+        batch.delete_range(String::from("c")..String::from("a"));
+        batch.delete_range(String::from("b")..String::from("b"));
+        engine.write_batch(batch).await.unwrap();
+        engine.flush().await.unwrap();
+
+        assert_eq!(
+            Vec::<(String, Vec<u8>)>::new(),
+            engine.scan(String::from("c")..String::from("a")).unwrap()
+        );
+        assert_eq!(
+            Vec::<(String, Vec<u8>)>::new(),
+            engine.scan(String::from("b")..String::from("b")).unwrap()
+        );
+        assert_eq!(
+            3,
+            engine
+                .scan(String::from("a")..String::from("d"))
+                .unwrap()
+                .len()
+        );
+        engine.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn empty_ranges_on_every_engine() {
+        let dir = tempfile::tempdir().unwrap();
+        empty_ranges(Driver::open(dir.path().join("lsm")).unwrap()).await;
+        empty_ranges(Bitcask::open(dir.path().join("bitcask")).await.unwrap()).await;
+        empty_ranges(MemoryEngine::new()).await;
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use memmap2::Mmap;
+
+/// A file opened for appending.
+pub trait WritableFile: Send + Sync {
+    fn append(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// Makes everything appended so far durable.
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+/// A file opened for positioned reads.
+pub trait RandomAccessFile: Send + Sync {
+    /// Fills `buf` from `offset`, failing with `UnexpectedEof` if the file
+    /// ends first.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+    fn len(&self) -> io::Result<u64>;
+
+    fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+}
+
+/// The whole contents of a file mapped into memory. The contents stay
+/// readable even once the file is deleted.
+pub type MappedFile = Box<dyn AsRef<[u8]> + Send + Sync>;
+
+/// Every file operation the engines perform, so they can run on the real
+/// disk, entirely in memory, or on any other storage.
+pub trait FileSystem: Debug + Send + Sync {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Creates a file, truncating it if it exists.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>>;
+
+    /// Opens a file for appending, creating it if it does not exist.
+    fn append(&self, path: &Path) -> io::Result<Box<dyn WritableFile>>;
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn RandomAccessFile>>;
+
+    fn map(&self, path: &Path) -> io::Result<MappedFile>;
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let file = self.open(path)?;
+        let mut buf = vec![0; file.len()? as usize];
+        file.read_at(&mut buf, 0)?;
+        Ok(buf)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Gives the file at `from` a second name, `to`. File systems that cannot
+    /// link files copy and sync it instead, which is as good for files that
+    /// are never modified.
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut file = self.create(to)?;
+        file.append(&self.read(from)?)?;
+        file.sync()
+    }
+
+    /// Makes the files created, renamed and removed in a directory durable.
+    fn sync_dir(&self, path: &Path) -> io::Result<()>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes an empty directory.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Lists the paths of the files and directories directly inside `path`.
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    fn exists(&self, path: &Path) -> bool;
+}
+
+/// The time background work is scheduled by, so that a simulation can run
+/// it on a virtual clock.
+pub trait Clock: Debug + Send + Sync {
+    /// The time elapsed since some fixed point, the same for every call.
+    fn now(&self) -> Duration;
+}
+
+/// The real, monotonic clock, counting from when it was created.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// The operating system's file system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsFileSystem;
+
+struct OsFile(File);
+
+impl WritableFile for OsFile {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.0.write_all(data)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.0.sync_data()
+    }
+}
+
+impl RandomAccessFile for OsFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self.0.read_exact_at(buf, offset)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.0.metadata()?.len())
+    }
+}
+
+impl FileSystem for OsFileSystem {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        Ok(Box::new(OsFile(File::create(path)?)))
+    }
+
+    fn append(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Box::new(OsFile(file)))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn RandomAccessFile>> {
+        Ok(Box::new(OsFile(File::open(path)?)))
+    }
+
+    fn map(&self, path: &Path) -> io::Result<MappedFile> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() == 0 {
+            return Ok(Box::new(Vec::new()));
+        }
+        // SAFETY: the engines never modify a file they map, only unlink it,
+        // which leaves the mapping intact.
+        Ok(Box::new(unsafe { Mmap::map(&file)? }))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        match fs::hard_link(from, to) {
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                fs::copy(from, to)?;
+                File::open(to)?.sync_all()
+            }
+            result => result,
+        }
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        File::open(path)?.sync_all()
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect()
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+}
+
+type Contents = Arc<RwLock<Vec<u8>>>;
+
+/// A file system held entirely in memory, for hermetic tests and for
+/// embedding the engines without a disk. Open files keep their contents
+/// alive after being deleted or replaced, as on unix.
+#[derive(Debug, Default)]
+pub struct MemoryFileSystem {
+    files: Mutex<BTreeMap<PathBuf, Contents>>,
+    dirs: Mutex<BTreeSet<PathBuf>>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn contents(&self, path: &Path) -> io::Result<Contents> {
+        match self.files.lock().unwrap().get(path) {
+            Some(contents) => Ok(Arc::clone(contents)),
+            None => Err(not_found(path)),
+        }
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(ErrorKind::NotFound, path.display().to_string())
+}
+
+struct MemoryFile(Contents);
+
+impl WritableFile for MemoryFile {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.0.write().unwrap().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl RandomAccessFile for MemoryFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let contents = self.0.read().unwrap();
+        let bytes = usize::try_from(offset)
+            .ok()
+            .and_then(|start| contents.get(start..start.checked_add(buf.len())?))
+            .ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.0.read().unwrap().len() as u64)
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut dirs = self.dirs.lock().unwrap();
+        for dir in path.ancestors() {
+            dirs.insert(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let contents = Contents::default();
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), Arc::clone(&contents));
+        Ok(Box::new(MemoryFile(contents)))
+    }
+
+    fn append(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let contents = self
+            .files
+            .lock()
+            .unwrap()
+            .entry(path.to_path_buf())
+            .or_default()
+            .clone();
+        Ok(Box::new(MemoryFile(contents)))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn RandomAccessFile>> {
+        Ok(Box::new(MemoryFile(self.contents(path)?)))
+    }
+
+    fn map(&self, path: &Path) -> io::Result<MappedFile> {
+        let contents = self.contents(path)?;
+        let bytes = contents.read().unwrap().clone();
+        Ok(Box::new(bytes))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let contents = files.remove(from).ok_or_else(|| not_found(from))?;
+        files.insert(to.to_path_buf(), contents);
+        Ok(())
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let contents = Arc::clone(files.get(from).ok_or_else(|| not_found(from))?);
+        if files.contains_key(to) {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                to.display().to_string(),
+            ));
+        }
+        files.insert(to.to_path_buf(), contents);
+        Ok(())
+    }
+
+    fn sync_dir(&self, _: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        match self.files.lock().unwrap().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        if !self.list_dir(path)?.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::DirectoryNotEmpty,
+                path.display().to_string(),
+            ));
+        }
+        self.dirs.lock().unwrap().remove(path);
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        if !self.dirs.lock().unwrap().contains(path) {
+            return Err(not_found(path));
+        }
+        let files = self.files.lock().unwrap();
+        let dirs = self.dirs.lock().unwrap();
+        Ok(files
+            .keys()
+            .chain(dirs.iter())
+            .filter(|p| p.parent() == Some(path))
+            .cloned()
+            .collect())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.lock().unwrap().contains_key(path) || self.dirs.lock().unwrap().contains(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn memory_file_system() {
+        let fs = MemoryFileSystem::new();
+        let dir = Path::new("/db");
+        fs.create_dir_all(dir).unwrap();
+
+        let mut file = fs.create(&dir.join("1.log")).unwrap();
+        file.append(b"hello").unwrap();
+        let reader = fs.open(&dir.join("1.log")).unwrap();
+        file.append(b" world").unwrap();
+        let mut buf = [0; 5];
+        reader.read_at(&mut buf, 6).unwrap();
+        assert_eq!(b"world", &buf);
+        assert_eq!(
+            ErrorKind::UnexpectedEof,
+            reader.read_at(&mut buf, 8).unwrap_err().kind()
+        );
+
+        fs.rename(&dir.join("1.log"), &dir.join("2.log")).unwrap();
+        assert!(!fs.exists(&dir.join("1.log")));
+        assert_eq!(
+            b"hello world".to_vec(),
+            fs.read(&dir.join("2.log")).unwrap()
+        );
+        assert_eq!(vec![dir.join("2.log")], fs.list_dir(dir).unwrap());
+
+        let mapped = fs.map(&dir.join("2.log")).unwrap();
+        fs.remove_file(&dir.join("2.log")).unwrap();
+        assert_eq!(11, reader.len().unwrap());
+        assert_eq!(b"hello world", (*mapped).as_ref());
+        assert!(fs.list_dir(dir).unwrap().is_empty());
+        assert_eq!(
+            ErrorKind::NotFound,
+            fs.open(&dir.join("2.log")).err().unwrap().kind()
+        );
+    }
+}
@@ -0,0 +1,851 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::env::{FileSystem, MappedFile, RandomAccessFile, WritableFile};
+
+#[derive(Debug, Default)]
+struct FileState {
+    data: Vec<u8>,
+    /// How much of `data` a power loss cannot take away.
+    synced: usize,
+}
+
+type Contents = Arc<Mutex<FileState>>;
+
+/// A xorshift64* generator, so that a seed reproduces the same faults.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number from `0` to `bound`, inclusive.
+    pub(crate) fn up_to(&mut self, bound: usize) -> usize {
+        (self.next() % (bound as u64 + 1)) as usize
+    }
+
+    /// A number from `0` up to, but excluding, `1`.
+    pub(crate) fn fraction(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    files: BTreeMap<PathBuf, Contents>,
+    dirs: BTreeSet<PathBuf>,
+    /// The files and directories as of the last sync of the directory
+    /// holding each, which is what a power loss leaves.
+    durable_files: BTreeMap<PathBuf, Contents>,
+    durable_dirs: BTreeSet<PathBuf>,
+    rng: Rng,
+    ops_until_crash: Option<u64>,
+    crashed: bool,
+    error_probability: f64,
+    dir_syncs: u64,
+}
+
+impl State {
+    /// Decides the fate of the next operation: whether it fails because the
+    /// machine has crashed, or fails with an injected error.
+    fn fault(&mut self) -> Result<(), Fault> {
+        if self.crashed {
+            return Err(Fault::Crashed);
+        }
+        if let Some(ops) = &mut self.ops_until_crash {
+            if *ops == 0 {
+                self.crashed = true;
+                return Err(Fault::Crashed);
+            }
+            *ops -= 1;
+        }
+        match self.rng.fraction() < self.error_probability {
+            true => Err(Fault::Injected),
+            false => Ok(()),
+        }
+    }
+
+    fn check(&mut self) -> io::Result<()> {
+        self.fault().map_err(io::Error::from)
+    }
+
+    /// Whether the directory entry of `path` survives a power loss: the
+    /// root always does, anything else if its parent does too.
+    fn is_durable_entry(&self, path: &Path) -> bool {
+        path.parent()
+            .is_none_or(|parent| parent.parent().is_none() || self.durable_dirs.contains(parent))
+    }
+}
+
+enum Fault {
+    Crashed,
+    Injected,
+}
+
+impl From<Fault> for io::Error {
+    fn from(fault: Fault) -> Self {
+        match fault {
+            Fault::Crashed => io::Error::other("simulated crash"),
+            Fault::Injected => io::Error::other("injected error"),
+        }
+    }
+}
+
+/// An in-memory file system that misbehaves on purpose, for testing that the
+/// engines survive crashes. It tracks which bytes of every file have been
+/// synced, and can
+///
+/// - fail operations with injected errors, tearing the data of a failed
+///   append partway,
+/// - crash after a given number of operations, failing every later one, and
+/// - lose power, dropping what was appended to each file since it was last
+///   synced except for a random prefix, as a disk that wrote part of its
+///   cache would.
+///
+/// Creating, renaming and removing files and directories take effect at once,
+/// but a power loss undoes every such change to a directory since it was
+/// last synced. The faults are drawn from a seeded generator, so a failing
+/// seed can be replayed.
+#[derive(Debug, Clone)]
+pub struct FaultInjectionFileSystem {
+    state: Arc<Mutex<State>>,
+}
+
+impl FaultInjectionFileSystem {
+    pub fn new(seed: u64) -> Self {
+        let state = State {
+            files: BTreeMap::new(),
+            dirs: BTreeSet::new(),
+            durable_files: BTreeMap::new(),
+            durable_dirs: BTreeSet::new(),
+            rng: Rng::new(seed),
+            ops_until_crash: None,
+            crashed: false,
+            error_probability: 0.0,
+            dir_syncs: 0,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Lets `ops` more operations succeed, then crashes.
+    pub fn crash_after(&self, ops: u64) {
+        self.state.lock().unwrap().ops_until_crash = Some(ops);
+    }
+
+    pub fn is_crashed(&self) -> bool {
+        self.state.lock().unwrap().crashed
+    }
+
+    /// Makes every operation fail with the given probability.
+    pub fn inject_errors(&self, probability: f64) {
+        self.state.lock().unwrap().error_probability = probability;
+    }
+
+    /// The files holding data a power loss would drop.
+    pub fn unsynced(&self) -> Vec<PathBuf> {
+        let state = self.state.lock().unwrap();
+        state
+            .files
+            .iter()
+            .filter(|(_, file)| {
+                let file = file.lock().unwrap();
+                file.synced < file.data.len()
+            })
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Directory syncs so far.
+    pub fn dir_syncs(&self) -> u64 {
+        self.state.lock().unwrap().dir_syncs
+    }
+
+    /// Undoes the changes to every directory since it was last synced and
+    /// drops the unsynced data of every file, keeping a random prefix of it,
+    /// then brings a crashed file system back up.
+    pub fn power_loss(&self) {
+        let mut state = self.state.lock().unwrap();
+        let dirs: BTreeSet<PathBuf> = state
+            .durable_dirs
+            .iter()
+            .filter(|dir| state.is_durable_entry(dir))
+            .cloned()
+            .collect();
+        let files: BTreeMap<PathBuf, Contents> = state
+            .durable_files
+            .iter()
+            .filter(|(path, _)| state.is_durable_entry(path))
+            .map(|(path, contents)| (path.clone(), Arc::clone(contents)))
+            .collect();
+        state.dirs = dirs;
+        state.files = files;
+        let files: Vec<Contents> = state.files.values().cloned().collect();
+        for file in files {
+            let mut file = file.lock().unwrap();
+            let unsynced = file.data.len() - file.synced;
+            let kept = file.synced + state.rng.up_to(unsynced);
+            file.data.truncate(kept);
+            file.synced = kept;
+        }
+        state.crashed = false;
+        state.ops_until_crash = None;
+    }
+
+    fn contents(&self, path: &Path) -> io::Result<Contents> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        match state.files.get(path) {
+            Some(contents) => Ok(Arc::clone(contents)),
+            None => Err(io::Error::new(
+                ErrorKind::NotFound,
+                path.display().to_string(),
+            )),
+        }
+    }
+
+    fn file(&self, contents: Contents) -> Box<FaultInjectionFile> {
+        Box::new(FaultInjectionFile {
+            state: Arc::clone(&self.state),
+            contents,
+        })
+    }
+}
+
+struct FaultInjectionFile {
+    state: Arc<Mutex<State>>,
+    contents: Contents,
+}
+
+impl WritableFile for FaultInjectionFile {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match state.fault() {
+            Ok(()) => {
+                self.contents.lock().unwrap().data.extend_from_slice(data);
+                Ok(())
+            }
+            Err(Fault::Injected) => {
+                let torn = state.rng.up_to(data.len());
+                let mut contents = self.contents.lock().unwrap();
+                contents.data.extend_from_slice(&data[..torn]);
+                Err(Fault::Injected.into())
+            }
+            Err(fault) => Err(fault.into()),
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.state.lock().unwrap().check()?;
+        let mut contents = self.contents.lock().unwrap();
+        contents.synced = contents.data.len();
+        Ok(())
+    }
+}
+
+impl RandomAccessFile for FaultInjectionFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self.state.lock().unwrap().check()?;
+        let contents = self.contents.lock().unwrap();
+        let bytes = usize::try_from(offset)
+            .ok()
+            .and_then(|start| contents.data.get(start..start.checked_add(buf.len())?))
+            .ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        self.state.lock().unwrap().check()?;
+        Ok(self.contents.lock().unwrap().data.len() as u64)
+    }
+}
+
+impl FileSystem for FaultInjectionFileSystem {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        for dir in path.ancestors() {
+            state.dirs.insert(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        // An existing file is truncated in place, which its directory does
+        // not need to be synced for.
+        let contents = state.files.entry(path.to_path_buf()).or_default().clone();
+        drop(state);
+        *contents.lock().unwrap() = FileState::default();
+        Ok(self.file(contents))
+    }
+
+    fn append(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        let contents = state.files.entry(path.to_path_buf()).or_default().clone();
+        drop(state);
+        Ok(self.file(contents))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn RandomAccessFile>> {
+        let contents = self.contents(path)?;
+        Ok(self.file(contents))
+    }
+
+    fn map(&self, path: &Path) -> io::Result<MappedFile> {
+        let contents = self.contents(path)?;
+        let bytes = contents.lock().unwrap().data.clone();
+        Ok(Box::new(bytes))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        let contents = state
+            .files
+            .remove(from)
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, from.display().to_string()))?;
+        state.files.insert(to.to_path_buf(), contents);
+        Ok(())
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        state.dir_syncs += 1;
+        let in_dir = |entry: &Path| entry.parent() == Some(path);
+        let files: Vec<(PathBuf, Contents)> = state
+            .files
+            .iter()
+            .filter(|(file, _)| in_dir(file))
+            .map(|(file, contents)| (file.clone(), Arc::clone(contents)))
+            .collect();
+        let dirs: Vec<PathBuf> = state
+            .dirs
+            .iter()
+            .filter(|dir| in_dir(dir))
+            .cloned()
+            .collect();
+        state.durable_files.retain(|file, _| !in_dir(file));
+        state.durable_files.extend(files);
+        state.durable_dirs.retain(|dir| !in_dir(dir));
+        state.durable_dirs.extend(dirs);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        match state.files.remove(path) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(
+                ErrorKind::NotFound,
+                path.display().to_string(),
+            )),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        let mut children = state.files.keys().chain(state.dirs.iter());
+        if children.any(|p| p.parent() == Some(path)) {
+            return Err(io::Error::new(
+                ErrorKind::DirectoryNotEmpty,
+                path.display().to_string(),
+            ));
+        }
+        match state.dirs.remove(path) {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                ErrorKind::NotFound,
+                path.display().to_string(),
+            )),
+        }
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        if !state.dirs.contains(path) {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                path.display().to_string(),
+            ));
+        }
+        Ok(state
+            .files
+            .keys()
+            .chain(state.dirs.iter())
+            .filter(|p| p.parent() == Some(path))
+            .cloned()
+            .collect())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let state = self.state.lock().unwrap();
+        state.files.contains_key(path) || state.dirs.contains(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::ops::Range;
+    use std::time::Duration;
+
+    use crate::batch::WriteBatch;
+    use crate::driver::{Driver, SST_EXTENSION};
+    use crate::options::{ColumnFamilyOptions, Options, SyncPolicy, WriteOptions};
+    use crate::sst_file_writer::SstFileWriter;
+    use crate::Error;
+
+    use super::*;
+
+    type Model = BTreeMap<String, Vec<u8>>;
+
+    fn options(fs: &FaultInjectionFileSystem, sync: SyncPolicy) -> Options {
+        Options {
+            default_column_family: ColumnFamilyOptions {
+                memtable_capacity: 16,
+                min_value_log_size: Some(64),
+                ..ColumnFamilyOptions::default()
+            },
+            file_system: Arc::new(fs.clone()),
+            sync,
+            ..Options::default()
+        }
+    }
+
+    fn everything() -> Range<String> {
+        String::from("")..String::from("~")
+    }
+
+    /// Applies a pseudo-random operation to both the model and the driver,
+    /// returning whether the operation leaves everything acknowledged so far
+    /// durable. The model is updated first, as a failed write may still have
+    /// landed.
+    fn step(
+        driver: &mut Driver,
+        model: &mut Model,
+        rng: &mut Rng,
+        i: usize,
+    ) -> Result<bool, Error> {
+        let key = format!("key/{:02}", rng.next() % 32);
+        match rng.next() % 20 {
+            0 => {
+                driver.flush_table()?;
+                Ok(true)
+            }
+            1 => {
+                driver.compact()?;
+                Ok(false)
+            }
+            2 => {
+                let end = format!("key/{:02}", rng.next() % 32);
+                model.retain(|k, _| !(&key <= k && k < &end));
+                let mut batch = WriteBatch::new();
+                batch.delete_range(key..end);
+                driver.write_batch(batch)?;
+                Ok(false)
+            }
+            3 => {
+                model.remove(&key);
+                driver.delete(key)?;
+                Ok(false)
+            }
+            n => {
+                // Every fourth value is large enough for the value log.
+                let repeat = match n % 4 {
+                    0 => 40,
+                    _ => 1,
+                };
+                let value = i.to_string().repeat(repeat).into_bytes();
+                model.insert(key.clone(), value.clone());
+                driver.write(key, value)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Runs random workloads against a driver, crashing it through `fault`
+    /// and checking after each restart that the recovered database matches
+    /// the model at some point no earlier than the last durable operation.
+    /// A workload keeps going past failed operations as long as the driver
+    /// can be resumed.
+    /// Every write is durable with `SyncPolicy::Always`, and otherwise only
+    /// flushes are.
+    fn crash_repeatedly(
+        seed: u64,
+        sync: SyncPolicy,
+        fault: impl Fn(&FaultInjectionFileSystem, &mut Rng),
+    ) {
+        let fs = FaultInjectionFileSystem::new(seed);
+        let mut rng = Rng::new(!seed);
+        let dir = Path::new("/db");
+        let mut model = Model::new();
+
+        for round in 0..10 {
+            let mut driver = Driver::open_with_options(dir, options(&fs, sync)).unwrap();
+            fault(&fs, &mut rng);
+
+            // The models after each acknowledged operation, starting at the
+            // last one known to be durable.
+            let mut history = vec![model.clone()];
+            for i in 0..200 {
+                let mut next = history.last().unwrap().clone();
+                match step(&mut driver, &mut next, &mut rng, round * 1000 + i) {
+                    Ok(true) => history = vec![next],
+                    Ok(false) if sync == SyncPolicy::Always => history = vec![next],
+                    Ok(false) => history.push(next),
+                    // Once the WAL is whole again, the failed operation has
+                    // left no trace and the workload goes on.
+                    Err(_) if (0..3).any(|_| driver.resume().is_ok()) => {}
+                    Err(_) => {
+                        // The failed operation may or may not have landed.
+                        history.push(next);
+                        break;
+                    }
+                }
+            }
+
+            drop(driver);
+            fs.inject_errors(0.0);
+            fs.power_loss();
+            let driver = Driver::open_with_options(dir, options(&fs, sync)).unwrap();
+            let recovered: Model = driver.scan(everything()).unwrap().into_iter().collect();
+            model = history
+                .into_iter()
+                .find(|m| *m == recovered)
+                .unwrap_or_else(|| panic!("seed {} round {} lost writes", seed, round));
+        }
+    }
+
+    #[test]
+    fn unsynced_data_is_torn_on_power_loss() {
+        let fs = FaultInjectionFileSystem::new(7);
+        let path = Path::new("/file");
+        let mut file = fs.create(path).unwrap();
+        file.append(b"synced").unwrap();
+        file.sync().unwrap();
+        fs.sync_dir(Path::new("/")).unwrap();
+        file.append(&[0; 100]).unwrap();
+
+        fs.crash_after(1);
+        file.append(b"!").unwrap();
+        assert!(file.sync().is_err());
+        assert!(fs.is_crashed());
+        assert!(fs.open(path).is_err());
+
+        fs.power_loss();
+        let bytes = fs.read(path).unwrap();
+        assert!(bytes.len() >= 6 && bytes.len() <= 107);
+        assert_eq!(b"synced", &bytes[..6]);
+
+        fs.inject_errors(1.0);
+        assert!(fs.create(Path::new("/other")).is_err());
+        assert!(file.append(b"more").is_err());
+    }
+
+    #[test]
+    fn unsynced_directory_changes_are_undone_on_power_loss() {
+        let fs = FaultInjectionFileSystem::new(7);
+        let (a, b, c) = (Path::new("/d/a"), Path::new("/d/b"), Path::new("/d/c"));
+        fs.create_dir_all(Path::new("/d")).unwrap();
+        let mut file = fs.create(a).unwrap();
+        file.append(b"a").unwrap();
+        file.sync().unwrap();
+        fs.power_loss();
+        assert!(!fs.exists(Path::new("/d")));
+
+        fs.create_dir_all(Path::new("/d")).unwrap();
+        fs.sync_dir(Path::new("/")).unwrap();
+        let mut file = fs.create(a).unwrap();
+        file.append(b"a").unwrap();
+        file.sync().unwrap();
+        fs.sync_dir(Path::new("/d")).unwrap();
+        fs.rename(a, b).unwrap();
+        fs.create(c).unwrap();
+        fs.power_loss();
+        assert_eq!(b"a", &fs.read(a).unwrap()[..]);
+        assert!(!fs.exists(b) && !fs.exists(c));
+
+        fs.rename(a, b).unwrap();
+        fs.sync_dir(Path::new("/d")).unwrap();
+        fs.remove_file(b).unwrap();
+        fs.power_loss();
+        assert!(!fs.exists(a));
+        assert_eq!(b"a", &fs.read(b).unwrap()[..]);
+    }
+
+    #[test]
+    fn crash_during_writes_flushes_and_compactions() {
+        for seed in 0..20 {
+            let crash =
+                |fs: &FaultInjectionFileSystem, rng: &mut Rng| fs.crash_after(rng.next() % 400);
+            crash_repeatedly(seed, SyncPolicy::OnFlush, crash);
+            crash_repeatedly(seed, SyncPolicy::Always, crash);
+        }
+    }
+
+    #[test]
+    fn sync_policies() {
+        let dir = Path::new("/db");
+        let sync = WriteOptions { sync: true };
+        for (policy, wal_syncs) in [
+            (SyncPolicy::Always, 3),
+            (SyncPolicy::Interval(Duration::from_secs(3600)), 1),
+            (SyncPolicy::OnFlush, 1),
+            (SyncPolicy::Never, 1),
+        ] {
+            let fs = FaultInjectionFileSystem::new(0);
+            let mut driver = Driver::open_with_options(dir, options(&fs, policy)).unwrap();
+            for i in 0..2 {
+                driver.write(i.to_string(), vec![i]).unwrap();
+            }
+            let mut batch = WriteBatch::new();
+            batch.put(String::from("2"), vec![2; 100]);
+            driver.write_batch_with_options(batch, &sync).unwrap();
+            assert_eq!(wal_syncs, driver.stats().wal_syncs, "{:?}", policy);
+
+            let dir_syncs = fs.dir_syncs();
+            driver.flush_table().unwrap();
+            driver.compact().unwrap();
+            let unsynced = fs.unsynced();
+            match policy.syncs_files() {
+                true => {
+                    assert!(unsynced.is_empty(), "{:?}", unsynced);
+                    assert!(fs.dir_syncs() > dir_syncs);
+                }
+                false => {
+                    for extension in ["sst", "vlog"] {
+                        let found = unsynced.iter().any(|p| p.extension().unwrap() == extension);
+                        assert!(found, "{:?}", unsynced);
+                    }
+                    assert!(unsynced.contains(&dir.join("MANIFEST")));
+                    assert_eq!(0, fs.dir_syncs());
+                }
+            }
+
+            // Without a power loss, nothing is lost either way.
+            drop(driver);
+            let driver = Driver::open_with_options(dir, options(&fs, policy)).unwrap();
+            assert_eq!(3, driver.scan(everything()).unwrap().len());
+        }
+    }
+
+    #[test]
+    fn interval_syncs_fall_due() {
+        let fs = FaultInjectionFileSystem::new(0);
+        let interval = Duration::from_millis(50);
+        let options = options(&fs, SyncPolicy::Interval(interval));
+        let mut driver = Driver::open_with_options(Path::new("/db"), options).unwrap();
+        assert_eq!(None, driver.sync_if_due().unwrap());
+
+        driver.write(String::from("a"), vec![1]).unwrap();
+        driver.write(String::from("b"), vec![2]).unwrap();
+        assert_eq!(0, driver.stats().wal_syncs);
+        let due = driver.sync_if_due().unwrap().unwrap();
+        assert!(due <= interval);
+
+        // An idle driver syncs once the writes fall due.
+        std::thread::sleep(due);
+        assert_eq!(None, driver.sync_if_due().unwrap());
+        assert_eq!(1, driver.stats().wal_syncs);
+        assert!(fs.unsynced().is_empty());
+
+        // A write after a quiet interval is synced at once.
+        std::thread::sleep(interval);
+        driver.write(String::from("c"), vec![3]).unwrap();
+        assert_eq!(2, driver.stats().wal_syncs);
+        assert_eq!(None, driver.sync_if_due().unwrap());
+    }
+
+    #[test]
+    fn writes_after_a_failed_append_survive() {
+        let dir = Path::new("/db");
+        let sync = WriteOptions { sync: true };
+        let put = |key: &str, value: u8| {
+            let mut batch = WriteBatch::new();
+            batch.put(String::from(key), vec![value]);
+            batch
+        };
+        for seed in 0..50 {
+            let fs = FaultInjectionFileSystem::new(seed);
+            let options = options(&fs, SyncPolicy::OnFlush);
+            let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+            driver.write_batch_with_options(put("a", 1), &sync).unwrap();
+
+            // The failed append may leave part of its record behind, which
+            // would take the records appended after it down with it.
+            fs.inject_errors(1.0);
+            assert!(driver.write(String::from("b"), vec![2]).is_err());
+            fs.inject_errors(0.0);
+            assert!(driver.write_batch_with_options(put("c", 3), &sync).is_err());
+            driver.resume().unwrap();
+            driver.write_batch_with_options(put("c", 3), &sync).unwrap();
+            drop(driver);
+
+            fs.power_loss();
+            let driver = Driver::open_with_options(dir, options).unwrap();
+            let recovered = driver.scan(everything()).unwrap();
+            let expected = vec![(String::from("a"), vec![1]), (String::from("c"), vec![3])];
+            assert_eq!(expected, recovered, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn failed_flush_keeps_the_memtable() {
+        let dir = Path::new("/db");
+        let mut failures = 0;
+        for seed in 0..50 {
+            let fs = FaultInjectionFileSystem::new(seed);
+            let options = options(&fs, SyncPolicy::Always);
+            let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+            driver.write(String::from("a"), vec![1]).unwrap();
+
+            // Fails the flush at a different step for each seed.
+            fs.inject_errors(0.1);
+            let flushed = driver.flush_table();
+            fs.inject_errors(0.0);
+            if flushed.is_err() {
+                failures += 1;
+            }
+            assert_eq!(Some(vec![1]), driver.read("a").unwrap(), "seed {}", seed);
+
+            // The next flush writes what the failed one left, before the
+            // segments holding it go.
+            driver.write(String::from("b"), vec![2]).unwrap();
+            driver.flush_table().unwrap();
+            drop(driver);
+
+            fs.power_loss();
+            let driver = Driver::open_with_options(dir, options).unwrap();
+            let recovered = driver.scan(everything()).unwrap();
+            let expected = vec![(String::from("a"), vec![1]), (String::from("b"), vec![2])];
+            assert_eq!(expected, recovered, "seed {} ({:?})", seed, flushed.err());
+        }
+        assert!(failures > 0 && failures < 50, "{}", failures);
+    }
+
+    #[test]
+    fn failed_compaction_keeps_tables() {
+        let dir = Path::new("/db");
+        for seed in 0..32 {
+            let fs = FaultInjectionFileSystem::new(seed);
+            let mut driver =
+                Driver::open_with_options(dir, options(&fs, SyncPolicy::OnFlush)).unwrap();
+            let mut model = Model::new();
+            for i in 0..40 {
+                let (key, value) = (format!("key/{:02}", i % 24), vec![i as u8; i % 3 * 40]);
+                model.insert(key.clone(), value.clone());
+                driver.write(key, value).unwrap();
+            }
+            driver.flush_table().unwrap();
+
+            // Fails the compaction at a different step for each seed, reading
+            // the tables, writing the merged one or saving the manifest.
+            fs.inject_errors(0.3);
+            let compacted = driver.compact();
+            fs.inject_errors(0.0);
+            model.insert(String::from("key/99"), Vec::new());
+            driver.write(String::from("key/99"), Vec::new()).unwrap();
+            driver.flush_table().unwrap();
+            drop(driver);
+
+            let driver = Driver::open_with_options(dir, options(&fs, SyncPolicy::OnFlush)).unwrap();
+            let recovered: Model = driver.scan(everything()).unwrap().into_iter().collect();
+            assert_eq!(model, recovered, "seed {} ({:?})", seed, compacted.err());
+        }
+    }
+
+    #[test]
+    fn failed_ingestion_adds_nothing() {
+        let dir = Path::new("/db");
+        let mut failures = 0;
+        for ops in 0..64 {
+            let fs = FaultInjectionFileSystem::new(ops);
+            let mut paths = Vec::new();
+            for prefix in ["x", "y"] {
+                let mut writer = SstFileWriter::new(ColumnFamilyOptions::default());
+                for i in 0..10 {
+                    writer.put(format!("{}/{}", prefix, i), vec![i]).unwrap();
+                }
+                let path = Path::new("/external").join(prefix);
+                writer.finish(&fs, &path).unwrap();
+                paths.push(path);
+            }
+            let options = options(&fs, SyncPolicy::OnFlush);
+            let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+            driver.write(String::from("a"), vec![1]).unwrap();
+            driver.flush_table().unwrap();
+
+            // Fails the ingestion at a later step each time round, and
+            // brings the file system back before the driver goes on.
+            fs.crash_after(ops);
+            let ingested = driver.ingest_external_files(&paths);
+            fs.power_loss();
+            let expected = match ingested {
+                Ok(()) => 22,
+                Err(_) => {
+                    failures += 1;
+                    2
+                }
+            };
+            // A flush publishes whatever tables the column family lists.
+            driver.write(String::from("b"), vec![1]).unwrap();
+            driver.flush_table().unwrap();
+            assert_eq!(
+                expected,
+                driver.scan(everything()).unwrap().len(),
+                "ops {}",
+                ops
+            );
+            drop(driver);
+
+            // Links the rollback failed to remove go when the database opens.
+            let driver = Driver::open_with_options(dir, options).unwrap();
+            assert_eq!(
+                expected,
+                driver.scan(everything()).unwrap().len(),
+                "ops {}",
+                ops
+            );
+            let tables = fs
+                .list_dir(dir)
+                .unwrap()
+                .into_iter()
+                .filter(|path| path.extension().is_some_and(|e| e == SST_EXTENSION))
+                .count();
+            assert_eq!(
+                driver.stats().tables["default"] as usize,
+                tables,
+                "ops {}",
+                ops
+            );
+        }
+        assert!(failures > 0 && failures < 64);
+    }
+
+    #[test]
+    fn injected_errors() {
+        for seed in 0..20 {
+            crash_repeatedly(seed, SyncPolicy::Always, |fs, _| fs.inject_errors(0.01));
+        }
+    }
+}
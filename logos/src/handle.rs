@@ -0,0 +1,825 @@
+use std::ops::{Range, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+
+use crate::batch::WriteBatch;
+use crate::cache::BlockCache;
+use crate::driver::{Driver, Version, DEFAULT_COLUMN_FAMILY};
+use crate::options::{ColumnFamilyOptions, Options, WriteOptions};
+use crate::stats::Statistics;
+use crate::write_queue::WriteQueue;
+use crate::Error;
+
+/// A handle to a database that can be cloned and used from many threads at
+/// once, without an async runtime. Reads go to the version the driver last
+/// published, which they load without locking, and never wait for writes,
+/// flushes or compactions. They see each write batch whole or not at all.
+/// Only the sharded block cache is locked, briefly, on their way. Writes are
+/// group committed through a `WriteQueue`, and only they and the maintenance
+/// operations take turns with the driver.
+///
+/// A background thread syncs writes left unsynced under
+/// `SyncPolicy::Interval` and compacts column families that reach
+/// `Options::compaction_trigger` tables. It stops once every handle is
+/// dropped. After it fails, every write returns its error until
+/// `Db::resume` retries the work and it succeeds. Writes are refused the
+/// same way once appending to or syncing the WAL fails. Statistics are read
+/// without waiting for the driver either.
+#[derive(Clone)]
+pub struct Db {
+    shared: Arc<Shared>,
+    _background: Arc<Background>,
+}
+
+struct Shared {
+    driver: Mutex<Driver>,
+    queue: WriteQueue,
+    current: Arc<ArcSwap<Version>>,
+    block_cache: Arc<BlockCache>,
+    compaction_trigger: Option<usize>,
+    signal: Mutex<Signal>,
+    wake: Condvar,
+    error: Mutex<Option<Error>>,
+}
+
+#[derive(Default)]
+struct Signal {
+    pending: bool,
+    closed: bool,
+}
+
+/// Stops and joins the background thread when the last handle goes away.
+struct Background {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        self.shared.signal.lock().unwrap().closed = true;
+        self.shared.wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Shared {
+    fn wake(&self) {
+        self.signal.lock().unwrap().pending = true;
+        self.wake.notify_one();
+    }
+
+    /// Waits for work, or for unsynced writes to fall due, and does it until
+    /// the database is closed.
+    fn run(&self) {
+        let mut signal = self.signal.lock().unwrap();
+        let mut due = None;
+        loop {
+            if !signal.pending && !signal.closed {
+                signal = match due {
+                    Some(due) => self.wake.wait_timeout(signal, due).unwrap().0,
+                    None => self.wake.wait(signal).unwrap(),
+                };
+            }
+            if signal.closed {
+                return;
+            }
+            signal.pending = false;
+            drop(signal);
+
+            due = match self.maintain() {
+                Ok(due) => due,
+                Err(e) => {
+                    self.error.lock().unwrap().get_or_insert(e);
+                    None
+                }
+            };
+            signal = self.signal.lock().unwrap();
+        }
+    }
+
+    fn maintain(&self) -> Result<Option<Duration>, Error> {
+        let mut driver = self.driver.lock().unwrap();
+        driver.run_background_work(self.compaction_trigger)
+    }
+}
+
+impl Db {
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, Options::default())
+    }
+
+    pub fn open_with_options<P: Into<PathBuf>>(path: P, options: Options) -> Result<Self, Error> {
+        let block_cache = Arc::clone(&options.block_cache);
+        let compaction_trigger = options.compaction_trigger;
+        let driver = Driver::load(path.into(), options)?;
+        let shared = Arc::new(Shared {
+            current: driver.current(),
+            block_cache,
+            compaction_trigger,
+            driver: Mutex::new(driver),
+            queue: WriteQueue::new(),
+            signal: Mutex::new(Signal::default()),
+            wake: Condvar::new(),
+            error: Mutex::new(None),
+        });
+
+        let worker = Arc::clone(&shared);
+        let thread = thread::Builder::new()
+            .name(String::from("logos-background"))
+            .spawn(move || worker.run())?;
+        Ok(Self {
+            _background: Arc::new(Background {
+                shared: Arc::clone(&shared),
+                thread: Some(thread),
+            }),
+            shared,
+        })
+    }
+
+    fn version(&self) -> Arc<Version> {
+        self.shared.current.load_full()
+    }
+
+    /// Runs an operation with exclusive use of the driver, then lets the
+    /// background thread look for work it left behind.
+    fn with_driver<T>(&self, f: impl FnOnce(&mut Driver) -> Result<T, Error>) -> Result<T, Error> {
+        let output = f(&mut self.shared.driver.lock().unwrap());
+        self.shared.wake();
+        output
+    }
+
+    fn check_background(&self) -> Result<(), Error> {
+        match &*self.shared.error.lock().unwrap() {
+            Some(e) => Err(e.duplicate()),
+            None => Ok(()),
+        }
+    }
+
+    /// The statistics as of the last write, flush or compaction, even while
+    /// another one is under way.
+    pub fn stats(&self) -> Statistics {
+        self.version().stats(&self.shared.block_cache)
+    }
+
+    /// Makes the WAL whole again if writing it failed and retries the
+    /// background work that failed, letting writes through again once both
+    /// succeed. Does nothing if neither failed.
+    pub fn resume(&self) -> Result<(), Error> {
+        self.shared.driver.lock().unwrap().resume()?;
+        let mut error = self.shared.error.lock().unwrap();
+        if error.is_some() {
+            self.shared.maintain()?;
+            *error = None;
+        }
+        drop(error);
+        self.shared.wake();
+        Ok(())
+    }
+
+    pub fn column_families(&self) -> Vec<String> {
+        self.version().column_families()
+    }
+
+    pub fn create_column_family<S: Into<String>>(
+        &self,
+        name: S,
+        options: ColumnFamilyOptions,
+    ) -> Result<(), Error> {
+        self.with_driver(|driver| driver.add_column_family(name.into(), options))
+    }
+
+    pub fn drop_column_family<S: AsRef<str>>(&self, name: S) -> Result<(), Error> {
+        self.with_driver(|driver| driver.remove_column_family(name.as_ref()))
+    }
+
+    pub fn write(&self, key: String, value: Vec<u8>) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write_batch(batch)
+    }
+
+    pub fn delete(&self, key: String) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write_batch(batch)
+    }
+
+    pub fn delete_range(&self, range: Range<String>) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.delete_range(range);
+        self.write_batch(batch)
+    }
+
+    pub fn write_batch(&self, batch: WriteBatch) -> Result<(), Error> {
+        self.write_batch_with_options(batch, &WriteOptions::default())
+    }
+
+    pub fn write_batch_with_options(
+        &self,
+        batch: WriteBatch,
+        options: &WriteOptions,
+    ) -> Result<(), Error> {
+        self.check_background()?;
+        let shared = &*self.shared;
+        let result = shared.queue.write(&shared.driver, batch, options);
+        shared.wake();
+        result
+    }
+
+    /// Makes every write so far durable, whatever the `SyncPolicy`.
+    pub fn sync_wal(&self) -> Result<(), Error> {
+        self.with_driver(Driver::sync)
+    }
+
+    pub fn read<S: AsRef<str>>(&self, key: S) -> Result<Option<Vec<u8>>, Error> {
+        self.read_cf(DEFAULT_COLUMN_FAMILY, key)
+    }
+
+    pub fn read_cf<S: AsRef<str>>(&self, cf: &str, key: S) -> Result<Option<Vec<u8>>, Error> {
+        self.version().read_cf(cf, key.as_ref())
+    }
+
+    pub fn scan(&self, range: Range<String>) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        self.scan_cf(DEFAULT_COLUMN_FAMILY, range)
+    }
+
+    pub fn scan_cf(&self, cf: &str, range: Range<String>) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        self.version().scan_cf(cf, range)
+    }
+
+    /// Flushes the memtable of every column family.
+    pub fn flush_table(&self) -> Result<(), Error> {
+        self.with_driver(Driver::flush_all)
+    }
+
+    pub fn flush_cf(&self, cf: &str) -> Result<(), Error> {
+        self.with_driver(|driver| driver.flush(&[driver.index(cf)?]))
+    }
+
+    pub fn compact(&self) -> Result<(), Error> {
+        self.with_driver(|driver| driver.compact_column_family(0))
+    }
+
+    pub fn compact_cf(&self, cf: &str) -> Result<(), Error> {
+        self.with_driver(|driver| driver.compact_column_family(driver.index(cf)?))
+    }
+
+    /// Reclaims space from the value logs of the default column family.
+    pub fn collect_garbage(&self) -> Result<(), Error> {
+        self.with_driver(|driver| driver.collect_value_log_garbage(0))
+    }
+
+    pub fn collect_garbage_cf(&self, cf: &str) -> Result<(), Error> {
+        self.with_driver(|driver| driver.collect_value_log_garbage(driver.index(cf)?))
+    }
+
+    pub fn ingest_external_files<P: AsRef<Path>>(&self, paths: &[P]) -> Result<(), Error> {
+        self.ingest_external_files_cf(DEFAULT_COLUMN_FAMILY, paths)
+    }
+
+    /// Adds SSTables built by `SstFileWriter` to a column family, as
+    /// `Driver::ingest_external_files_cf` does.
+    pub fn ingest_external_files_cf<P: AsRef<Path>>(
+        &self,
+        cf: &str,
+        paths: &[P],
+    ) -> Result<(), Error> {
+        let paths: Vec<PathBuf> = paths.iter().map(|p| p.as_ref().to_path_buf()).collect();
+        self.with_driver(|driver| driver.ingest(driver.index(cf)?, &paths))
+    }
+
+    /// Writes a consistent copy of the database to `dir`, holding off writes
+    /// only while the files are linked and copied.
+    pub fn checkpoint<P: AsRef<Path>>(&self, dir: P) -> Result<(), Error> {
+        self.with_driver(|driver| driver.create_checkpoint(dir.as_ref()))
+    }
+}
+
+/// A database opened only for reading, as it was when opened. Opening it
+/// changes nothing on disk, so it is safe on a directory another process has
+/// open or one that may be damaged, and it fails on a directory holding no
+/// database instead of creating one.
+pub struct ReadOnlyDb {
+    version: Version,
+}
+
+impl ReadOnlyDb {
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, Options::default())
+    }
+
+    pub fn open_with_options<P: Into<PathBuf>>(path: P, options: Options) -> Result<Self, Error> {
+        let version = Driver::load_read_only(path.into(), &options)?;
+        Ok(Self { version })
+    }
+
+    pub fn column_families(&self) -> Vec<String> {
+        self.version.column_families()
+    }
+
+    pub fn read<S: AsRef<str>>(&self, key: S) -> Result<Option<Vec<u8>>, Error> {
+        self.read_cf(DEFAULT_COLUMN_FAMILY, key)
+    }
+
+    pub fn read_cf<S: AsRef<str>>(&self, cf: &str, key: S) -> Result<Option<Vec<u8>>, Error> {
+        self.version.read_cf(cf, key.as_ref())
+    }
+
+    pub fn scan(&self, range: Range<String>) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        self.scan_cf(DEFAULT_COLUMN_FAMILY, range)
+    }
+
+    pub fn scan_cf(&self, cf: &str, range: Range<String>) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        self.version.scan_cf(cf, range)
+    }
+
+    /// The keys and values in `range`, in order. Tables are read a block at
+    /// a time as the iterator advances, so stopping early skips the rest.
+    pub fn iter_cf<R: RangeBounds<String> + Clone>(
+        &self,
+        cf: &str,
+        range: R,
+    ) -> Result<impl Iterator<Item = Result<(String, Vec<u8>), Error>> + '_, Error> {
+        self.version.iter_cf(cf, range)
+    }
+}
+
+/// The `Db` API for async code. Every call runs on tokio's blocking thread
+/// pool, so disk I/O and waiting for a group commit never stall the
+/// runtime's workers.
+#[derive(Clone)]
+pub struct AsyncDb {
+    db: Db,
+}
+
+impl From<Db> for AsyncDb {
+    fn from(db: Db) -> Self {
+        Self { db }
+    }
+}
+
+/// Runs blocking work off the runtime, passing on any panic.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(output) => output,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+impl AsyncDb {
+    pub async fn open<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, Options::default()).await
+    }
+
+    pub async fn open_with_options<P: Into<PathBuf>>(
+        path: P,
+        options: Options,
+    ) -> Result<Self, Error> {
+        let path = path.into();
+        Ok(blocking(move || Db::open_with_options(path, options))
+            .await?
+            .into())
+    }
+
+    /// The synchronous handle this one wraps.
+    pub fn blocking(&self) -> &Db {
+        &self.db
+    }
+
+    async fn run<T: Send + 'static>(&self, f: impl FnOnce(&Db) -> T + Send + 'static) -> T {
+        let db = self.db.clone();
+        blocking(move || f(&db)).await
+    }
+
+    pub async fn stats(&self) -> Statistics {
+        self.db.stats()
+    }
+
+    /// Retries the background work that failed, as `Db::resume` does.
+    pub async fn resume(&self) -> Result<(), Error> {
+        self.run(Db::resume).await
+    }
+
+    pub fn column_families(&self) -> Vec<String> {
+        self.db.column_families()
+    }
+
+    pub async fn create_column_family<S: Into<String>>(
+        &self,
+        name: S,
+        options: ColumnFamilyOptions,
+    ) -> Result<(), Error> {
+        let name = name.into();
+        self.run(move |db| db.create_column_family(name, options))
+            .await
+    }
+
+    pub async fn drop_column_family<S: Into<String>>(&self, name: S) -> Result<(), Error> {
+        let name = name.into();
+        self.run(move |db| db.drop_column_family(name)).await
+    }
+
+    pub async fn write(&self, key: String, value: Vec<u8>) -> Result<(), Error> {
+        self.run(move |db| db.write(key, value)).await
+    }
+
+    pub async fn delete(&self, key: String) -> Result<(), Error> {
+        self.run(move |db| db.delete(key)).await
+    }
+
+    pub async fn delete_range(&self, range: Range<String>) -> Result<(), Error> {
+        self.run(move |db| db.delete_range(range)).await
+    }
+
+    pub async fn write_batch(&self, batch: WriteBatch) -> Result<(), Error> {
+        self.run(move |db| db.write_batch(batch)).await
+    }
+
+    pub async fn write_batch_with_options(
+        &self,
+        batch: WriteBatch,
+        options: WriteOptions,
+    ) -> Result<(), Error> {
+        self.run(move |db| db.write_batch_with_options(batch, &options))
+            .await
+    }
+
+    /// Makes every write so far durable, whatever the `SyncPolicy`.
+    pub async fn sync_wal(&self) -> Result<(), Error> {
+        self.run(Db::sync_wal).await
+    }
+
+    pub async fn read<S: Into<String>>(&self, key: S) -> Result<Option<Vec<u8>>, Error> {
+        let key = key.into();
+        self.run(move |db| db.read(key)).await
+    }
+
+    pub async fn read_cf<S: Into<String>>(
+        &self,
+        cf: &str,
+        key: S,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let (cf, key) = (cf.to_owned(), key.into());
+        self.run(move |db| db.read_cf(&cf, key)).await
+    }
+
+    pub async fn scan(&self, range: Range<String>) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        self.run(move |db| db.scan(range)).await
+    }
+
+    pub async fn scan_cf(
+        &self,
+        cf: &str,
+        range: Range<String>,
+    ) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let cf = cf.to_owned();
+        self.run(move |db| db.scan_cf(&cf, range)).await
+    }
+
+    /// Flushes the memtable of every column family.
+    pub async fn flush_table(&self) -> Result<(), Error> {
+        self.run(Db::flush_table).await
+    }
+
+    pub async fn flush_cf(&self, cf: &str) -> Result<(), Error> {
+        let cf = cf.to_owned();
+        self.run(move |db| db.flush_cf(&cf)).await
+    }
+
+    pub async fn compact(&self) -> Result<(), Error> {
+        self.run(Db::compact).await
+    }
+
+    pub async fn compact_cf(&self, cf: &str) -> Result<(), Error> {
+        let cf = cf.to_owned();
+        self.run(move |db| db.compact_cf(&cf)).await
+    }
+
+    /// Reclaims space from the value logs of the default column family.
+    pub async fn collect_garbage(&self) -> Result<(), Error> {
+        self.run(Db::collect_garbage).await
+    }
+
+    pub async fn collect_garbage_cf(&self, cf: &str) -> Result<(), Error> {
+        let cf = cf.to_owned();
+        self.run(move |db| db.collect_garbage_cf(&cf)).await
+    }
+
+    pub async fn ingest_external_files<P: AsRef<Path>>(&self, paths: &[P]) -> Result<(), Error> {
+        self.ingest_external_files_cf(DEFAULT_COLUMN_FAMILY, paths)
+            .await
+    }
+
+    pub async fn ingest_external_files_cf<P: AsRef<Path>>(
+        &self,
+        cf: &str,
+        paths: &[P],
+    ) -> Result<(), Error> {
+        let cf = cf.to_owned();
+        let paths: Vec<PathBuf> = paths.iter().map(|p| p.as_ref().to_path_buf()).collect();
+        self.run(move |db| db.ingest_external_files_cf(&cf, &paths))
+            .await
+    }
+
+    pub async fn checkpoint<P: Into<PathBuf>>(&self, dir: P) -> Result<(), Error> {
+        let dir = dir.into();
+        self.run(move |db| db.checkpoint(dir)).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    use crate::cache::BlockCache;
+    use crate::env::{FileSystem, MemoryFileSystem};
+    use crate::fault_injection::FaultInjectionFileSystem;
+    use crate::options::SyncPolicy;
+
+    use super::*;
+
+    fn options() -> Options {
+        Options {
+            default_column_family: ColumnFamilyOptions {
+                memtable_capacity: 16,
+                min_value_log_size: Some(64),
+                ..ColumnFamilyOptions::default()
+            },
+            file_system: Arc::new(MemoryFileSystem::new()),
+            ..Options::default()
+        }
+    }
+
+    /// Polls until `done` holds, failing after a few seconds.
+    fn eventually(mut done: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !done() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn concurrent_readers_and_writers() {
+        let db = Db::open_with_options(Path::new("/db"), options()).unwrap();
+
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for n in 0..100 {
+                        let value = vec![n as u8; 1 + n % 2 * 100];
+                        db.write(format!("key/{}/{:03}", i, n), value).unwrap();
+                        if n % 25 == 0 {
+                            db.compact().unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..4)
+            .map(|i| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    // A writer's keys appear in order, so the first missing
+                    // key is never followed by a present one.
+                    for _ in 0..100 {
+                        let prefix = format!("key/{}/", i);
+                        let range = prefix.clone()..format!("{}~", prefix);
+                        for (n, (key, value)) in db.scan(range).unwrap().iter().enumerate() {
+                            assert_eq!(&format!("{}{:03}", prefix, n), key);
+                            assert_eq!(n as u8, value[0]);
+                        }
+                        thread::yield_now();
+                    }
+                })
+            })
+            .collect();
+        for thread in writers.into_iter().chain(readers) {
+            thread.join().unwrap();
+        }
+
+        for i in 0..4 {
+            let key = format!("key/{}/099", i);
+            assert_eq!(Some(vec![99; 101]), db.read(key).unwrap());
+        }
+    }
+
+    #[test]
+    fn readers_see_whole_batches() {
+        let db = Db::open_with_options(Path::new("/db"), options()).unwrap();
+        db.create_column_family("other", ColumnFamilyOptions::default())
+            .unwrap();
+
+        let writer = {
+            let db = db.clone();
+            thread::spawn(move || {
+                for n in 1..=500u32 {
+                    let mut batch = WriteBatch::new();
+                    batch.put(String::from("a"), n.to_le_bytes().to_vec());
+                    batch.put(String::from("b"), n.to_le_bytes().to_vec());
+                    batch.put_cf("other", String::from("a"), n.to_le_bytes().to_vec());
+                    db.write_batch(batch).unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    let decode = |value: Vec<u8>| u32::from_le_bytes(value.try_into().unwrap());
+                    let mut seen = 0;
+                    while seen < 500 {
+                        let range = String::from("a")..String::from("c");
+                        let values: Vec<u32> = db
+                            .scan(range)
+                            .unwrap()
+                            .into_iter()
+                            .map(|(_, v)| decode(v))
+                            .collect();
+                        assert!(values.is_empty() || values == [values[0]; 2]);
+
+                        // A batch seen in one column family is already
+                        // applied to the other.
+                        seen = db.read("a").unwrap().map_or(0, decode);
+                        let other = db.read_cf("other", "a").unwrap().map_or(0, decode);
+                        assert!(other >= seen, "{} before {}", other, seen);
+                    }
+                })
+            })
+            .collect();
+        for thread in readers.into_iter().chain([writer]) {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn reads_do_not_wait_for_the_driver() {
+        let db = Db::open_with_options(Path::new("/db"), options()).unwrap();
+        db.write(String::from("a"), vec![1]).unwrap();
+        db.create_column_family("other", ColumnFamilyOptions::default())
+            .unwrap();
+        db.flush_table().unwrap();
+        db.write(String::from("b"), vec![2]).unwrap();
+
+        let _writer = db.shared.driver.lock().unwrap();
+        assert_eq!(Some(vec![1]), db.read("a").unwrap());
+        let stats = db.stats();
+        assert_eq!((2, 1), (stats.writes, stats.tables["default"]));
+        assert_eq!(Some(vec![2]), db.read("b").unwrap());
+        assert_eq!(vec!["default", "other"], db.column_families());
+        assert!(matches!(
+            db.read_cf("missing", "a"),
+            Err(Error::ColumnFamilyNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn read_only_databases() {
+        let file_system = Arc::new(MemoryFileSystem::new());
+        let cache = Arc::new(BlockCache::new(1 << 20));
+        let options = Options {
+            default_column_family: ColumnFamilyOptions {
+                block_size: 64,
+                ..ColumnFamilyOptions::default()
+            },
+            block_cache: Arc::clone(&cache),
+            file_system: file_system.clone(),
+            ..Options::default()
+        };
+        assert!(matches!(
+            ReadOnlyDb::open_with_options("/db", options.clone()),
+            Err(Error::DatabaseNotFound(_))
+        ));
+        assert!(!file_system.exists(Path::new("/db")));
+
+        let db = Db::open_with_options("/db", options.clone()).unwrap();
+        for i in 0..100 {
+            db.write(format!("{:03}", i), vec![i; 16]).unwrap();
+        }
+        db.flush_table().unwrap();
+        db.write(String::from("050"), vec![]).unwrap();
+        drop(db);
+
+        let contents = || {
+            let mut files = file_system.list_dir(Path::new("/db")).unwrap();
+            files.sort();
+            files
+                .into_iter()
+                .map(|file| (file_system.read(&file).unwrap(), file))
+                .collect::<Vec<_>>()
+        };
+        let before = contents();
+        let db = ReadOnlyDb::open_with_options("/db", options).unwrap();
+        assert_eq!(Some(vec![]), db.read("050").unwrap());
+        assert_eq!(Some(vec![7; 16]), db.read("007").unwrap());
+        let misses = cache.misses();
+        let first: Vec<_> = db
+            .iter_cf("default", String::from("010")..)
+            .unwrap()
+            .take(2)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            vec![("010".into(), vec![10; 16]), ("011".into(), vec![11; 16])],
+            first
+        );
+        assert!(
+            cache.misses() - misses <= 2,
+            "{} blocks read",
+            cache.misses() - misses
+        );
+        drop(db);
+        assert!(before == contents());
+    }
+
+    #[test]
+    fn background_syncs_and_compactions() {
+        let options = Options {
+            sync: SyncPolicy::Interval(Duration::from_millis(10)),
+            compaction_trigger: Some(3),
+            ..options()
+        };
+        let db = Db::open_with_options(Path::new("/db"), options).unwrap();
+
+        db.write(String::from("a"), vec![1]).unwrap();
+        eventually(|| db.stats().wal_syncs == 1);
+        for i in 0..3 {
+            db.write(i.to_string(), vec![i]).unwrap();
+            db.flush_table().unwrap();
+        }
+        eventually(|| db.stats().compactions == 1);
+        assert_eq!(Some(vec![1]), db.read("a").unwrap());
+        assert_eq!(Some(vec![2]), db.read("2").unwrap());
+    }
+
+    #[test]
+    fn resume_after_background_errors() {
+        let fs = FaultInjectionFileSystem::new(0);
+        let options = Options {
+            file_system: Arc::new(fs.clone()),
+            compaction_trigger: Some(2),
+            ..options()
+        };
+        let db = Db::open_with_options(Path::new("/db"), options).unwrap();
+        db.write(String::from("a"), vec![1]).unwrap();
+        db.flush_table().unwrap();
+
+        // The second table makes the background thread compact, and fail.
+        db.write(String::from("b"), vec![2]).unwrap();
+        {
+            let mut driver = db.shared.driver.lock().unwrap();
+            driver.flush_all().unwrap();
+            fs.inject_errors(1.0);
+        }
+        db.shared.wake();
+        eventually(|| db.shared.error.lock().unwrap().is_some());
+        assert!(db.write(String::from("c"), vec![3]).is_err());
+        assert!(db.resume().is_err());
+
+        fs.inject_errors(0.0);
+        db.resume().unwrap();
+        db.write(String::from("c"), vec![3]).unwrap();
+        assert_eq!(1, db.stats().compactions);
+        assert_eq!(Some(vec![2]), db.read("b").unwrap());
+    }
+
+    #[tokio::test]
+    async fn async_calls_do_not_block_the_runtime() {
+        let db = AsyncDb::open_with_options(Path::new("/db"), options())
+            .await
+            .unwrap();
+        db.write(String::from("a"), vec![1]).await.unwrap();
+
+        // Hold the driver on another thread so the next write waits for it.
+        let (locked, release) = (mpsc::channel(), mpsc::channel::<()>());
+        let driver = db.blocking().clone();
+        let holder = thread::spawn(move || {
+            let _driver = driver.shared.driver.lock().unwrap();
+            locked.0.send(()).unwrap();
+            release.1.recv().unwrap();
+        });
+        locked.1.recv().unwrap();
+
+        let write = tokio::spawn({
+            let db = db.clone();
+            async move { db.write(String::from("b"), vec![2]).await }
+        });
+        // The single-threaded runtime still serves reads meanwhile.
+        assert_eq!(Some(vec![1]), db.read("a").await.unwrap());
+        assert!(!write.is_finished());
+
+        release.0.send(()).unwrap();
+        holder.join().unwrap();
+        write.await.unwrap().unwrap();
+        assert_eq!(Some(vec![2]), db.read("b").await.unwrap());
+    }
+}
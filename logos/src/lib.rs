@@ -1,7 +1,34 @@
+use std::path::{Path, PathBuf};
+
 use thiserror::Error;
 
+#[macro_use]
+mod trace;
+
+pub mod backup;
+pub mod batch;
+pub mod bitcask;
+pub mod block;
+pub mod cache;
+pub mod compression;
+pub mod data_block;
 pub mod db;
 pub mod driver;
+pub mod engine;
+pub mod env;
+pub mod fault_injection;
+pub mod handle;
+pub mod listener;
+pub mod manifest;
+pub mod options;
+pub mod repair;
+pub mod simulation;
+pub mod sst_file_writer;
+pub mod stats;
+pub mod table;
+pub mod value_log;
+pub mod wal;
+pub mod write_queue;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -11,4 +38,61 @@ pub enum Error {
     IoError(#[from] std::io::Error),
     #[error("memtable full")]
     MemTableFull,
+    #[error("compression error")]
+    CompressionError,
+    #[error("corruption in {} at offset {offset}", file.display())]
+    Corruption { file: PathBuf, offset: u64 },
+    #[error("column family not found: {0}")]
+    ColumnFamilyNotFound(String),
+    #[error("column family already exists: {0}")]
+    ColumnFamilyExists(String),
+    #[error("the default column family cannot be dropped")]
+    DropDefaultColumnFamily,
+    #[error("backup not found: {0}")]
+    BackupNotFound(u64),
+    #[error("key added out of order: {0}")]
+    KeyOutOfOrder(String),
+    #[error("cannot ingest {}: {reason}", file.display())]
+    InvalidExternalFile { file: PathBuf, reason: String },
+    #[error("no database in {}", .0.display())]
+    DatabaseNotFound(PathBuf),
+}
+
+impl Error {
+    /// Attributes a corruption found while decoding bytes to the file they
+    /// were read from.
+    pub fn in_file(self, path: &Path) -> Self {
+        match self {
+            Error::Corruption { offset, .. } => Error::Corruption {
+                file: path.to_path_buf(),
+                offset,
+            },
+            e => e,
+        }
+    }
+
+    /// A copy of the error for each writer of a group commit that failed.
+    /// I/O errors keep their kind and message.
+    pub(crate) fn duplicate(&self) -> Self {
+        match self {
+            Error::BincodeError => Error::BincodeError,
+            Error::IoError(e) => Error::IoError(std::io::Error::new(e.kind(), e.to_string())),
+            Error::MemTableFull => Error::MemTableFull,
+            Error::CompressionError => Error::CompressionError,
+            Error::Corruption { file, offset } => Error::Corruption {
+                file: file.clone(),
+                offset: *offset,
+            },
+            Error::ColumnFamilyNotFound(name) => Error::ColumnFamilyNotFound(name.clone()),
+            Error::ColumnFamilyExists(name) => Error::ColumnFamilyExists(name.clone()),
+            Error::DropDefaultColumnFamily => Error::DropDefaultColumnFamily,
+            Error::BackupNotFound(id) => Error::BackupNotFound(*id),
+            Error::KeyOutOfOrder(key) => Error::KeyOutOfOrder(key.clone()),
+            Error::InvalidExternalFile { file, reason } => Error::InvalidExternalFile {
+                file: file.clone(),
+                reason: reason.clone(),
+            },
+            Error::DatabaseNotFound(path) => Error::DatabaseNotFound(path.clone()),
+        }
+    }
 }
@@ -0,0 +1,150 @@
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::Error;
+
+/// Callbacks for what the engine does on its own, registered through
+/// `Options::listeners`. Every method does nothing unless overridden.
+///
+/// They are called on the thread doing the work while it holds the driver,
+/// so they should return quickly and must not call back into the database.
+pub trait EventListener: Debug + Send + Sync {
+    /// Called before a non-empty memtable is written out.
+    fn on_flush_begin(&self, _info: &FlushJobInfo) {}
+
+    /// Called once the files of a flush are written and in the manifest.
+    fn on_flush_completed(&self, _info: &FlushJobInfo) {}
+
+    /// Called once the merged table of a compaction is in the manifest, before
+    /// its input tables are deleted.
+    fn on_compaction_completed(&self, _info: &CompactionJobInfo) {}
+
+    /// Called after an SSTable no longer in the manifest is deleted.
+    fn on_table_file_deleted(&self, _path: &Path) {}
+
+    /// Called when writes to a column family stop to wait for its full
+    /// memtable to be flushed, and again when they resume.
+    fn on_stall_conditions_changed(&self, _info: &WriteStallInfo) {}
+
+    /// Called when maintenance on the background thread of a `Db` fails. The
+    /// error is also returned by the next write.
+    fn on_background_error(&self, _error: &Error) {}
+}
+
+#[derive(Debug, Clone)]
+pub struct FlushJobInfo {
+    pub column_family: String,
+    /// Number of operations in the memtable.
+    pub entries: usize,
+    /// The SSTable and value log the flush wrote, empty until it completes.
+    pub output_files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompactionJobInfo {
+    pub column_family: String,
+    pub input_files: Vec<PathBuf>,
+    /// The merged table, unless nothing in the inputs was live.
+    pub output_files: Vec<PathBuf>,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStallCondition {
+    Normal,
+    Stopped,
+}
+
+#[derive(Debug, Clone)]
+pub struct WriteStallInfo {
+    pub column_family: String,
+    pub previous: WriteStallCondition,
+    pub current: WriteStallCondition,
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::driver::Driver;
+    use crate::env::MemoryFileSystem;
+    use crate::options::{ColumnFamilyOptions, Options};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl Recorder {
+        fn record(&self, event: String) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    impl EventListener for Recorder {
+        fn on_flush_begin(&self, info: &FlushJobInfo) {
+            self.record(format!(
+                "flush begin {} {}",
+                info.column_family, info.entries
+            ));
+        }
+
+        fn on_flush_completed(&self, info: &FlushJobInfo) {
+            self.record(format!("flush completed {:?}", info.output_files));
+        }
+
+        fn on_compaction_completed(&self, info: &CompactionJobInfo) {
+            self.record(format!(
+                "compaction {:?} -> {:?}",
+                info.input_files, info.output_files
+            ));
+        }
+
+        fn on_table_file_deleted(&self, path: &Path) {
+            self.record(format!("deleted {:?}", path));
+        }
+
+        fn on_stall_conditions_changed(&self, info: &WriteStallInfo) {
+            self.record(format!("stall {:?}", info.current));
+        }
+    }
+
+    #[test]
+    fn events() {
+        let recorder = Arc::new(Recorder::default());
+        let options = Options {
+            default_column_family: ColumnFamilyOptions {
+                memtable_capacity: 4,
+                ..ColumnFamilyOptions::default()
+            },
+            file_system: Arc::new(MemoryFileSystem::new()),
+            listeners: vec![recorder.clone()],
+            ..Options::default()
+        };
+        let mut driver = Driver::open_with_options("/db", options).unwrap();
+        for i in 0..5 {
+            driver.write(format!("{}", i), vec![i]).unwrap();
+        }
+        driver.flush_table().unwrap();
+        driver.compact().unwrap();
+
+        let events = recorder.events.lock().unwrap();
+        assert_eq!(
+            *events,
+            [
+                "stall Stopped",
+                "flush begin default 4",
+                "flush completed [\"/db/3.sst\"]",
+                "stall Normal",
+                "flush begin default 1",
+                "flush completed [\"/db/5.sst\"]",
+                "compaction [\"/db/3.sst\", \"/db/5.sst\"] -> [\"/db/6.sst\"]",
+                "deleted \"/db/3.sst\"",
+                "deleted \"/db/5.sst\"",
+            ]
+        );
+    }
+}
@@ -0,0 +1,476 @@
+use std::io::Write;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use clap::{Parser, Subcommand};
+use logos::batch::WriteBatch;
+use logos::cache::BlockCache;
+use logos::db::Value;
+use logos::driver::{sst_path, DEFAULT_COLUMN_FAMILY};
+use logos::env::OsFileSystem;
+use logos::handle::{Db, ReadOnlyDb};
+use logos::manifest::Manifest;
+use logos::options::{Options, WriteOptions};
+use logos::repair::{self, FileReport, Report};
+use logos::table::Table;
+use logos::value_log::value_log_path;
+use logos::Error;
+
+#[derive(Parser)]
+#[command(
+    name = "logos",
+    about = "Inspect and maintain a logos database directory"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check every table, log segment and value log against its checksums.
+    Verify { path: PathBuf },
+    /// Salvage what survives in a damaged directory and rebuild its manifest.
+    Repair { path: PathBuf },
+    /// Print an SSTable's properties, range tombstones and entries.
+    SstDump {
+        file: PathBuf,
+        /// Also list the data blocks with their offsets, sizes and last keys.
+        #[arg(long)]
+        layout: bool,
+        /// Leave out the entries.
+        #[arg(long)]
+        no_entries: bool,
+    },
+    /// Print the manifest and the live files of every column family.
+    Manifest { path: PathBuf },
+    /// Print the value of a key.
+    Get {
+        path: PathBuf,
+        key: String,
+        #[arg(long, default_value = DEFAULT_COLUMN_FAMILY)]
+        cf: String,
+    },
+    /// Write a key, syncing the WAL before returning.
+    Put {
+        path: PathBuf,
+        key: String,
+        value: String,
+        #[arg(long, default_value = DEFAULT_COLUMN_FAMILY)]
+        cf: String,
+    },
+    /// Print the keys from `start` up to, but excluding, `end`.
+    Scan {
+        path: PathBuf,
+        #[arg(default_value = "")]
+        start: String,
+        /// Defaults to past every key.
+        end: Option<String>,
+        #[arg(long, default_value = DEFAULT_COLUMN_FAMILY)]
+        cf: String,
+        /// Print at most this many entries.
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Flush a column family and merge all of its tables into one.
+    Compact {
+        path: PathBuf,
+        #[arg(long, default_value = DEFAULT_COLUMN_FAMILY)]
+        cf: String,
+    },
+    /// Write a copy of the database that opens on its own into a new directory.
+    Checkpoint { path: PathBuf, dir: PathBuf },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli.command, &mut std::io::stdout().lock()) {
+        Ok(code) => code,
+        Err(e) => {
+            let mut message = e.to_string();
+            let mut source = std::error::Error::source(&e);
+            while let Some(e) = source {
+                message = format!("{}: {}", message, e);
+                source = e.source();
+            }
+            eprintln!("error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs a command, writing what it prints to `out`. No command creates a
+/// database where there is none, and `get`, `scan` and the inspection
+/// commands leave the directory untouched.
+fn run(command: Command, out: &mut dyn Write) -> Result<ExitCode, Error> {
+    match command {
+        Command::Verify { path } => report(out, &repair::verify(path)?),
+        Command::Repair { path } => report(out, &repair::repair(path)?),
+        Command::SstDump {
+            file,
+            layout,
+            no_entries,
+        } => {
+            sst_dump(out, &file, layout, !no_entries)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Manifest { path } => {
+            print_manifest(out, &path)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Get { path, key, cf } => match ReadOnlyDb::open(path)?.read_cf(&cf, &key)? {
+            Some(value) => {
+                writeln!(out, "{}", escape(&value))?;
+                Ok(ExitCode::SUCCESS)
+            }
+            None => {
+                eprintln!("{}: not found", key);
+                Ok(ExitCode::FAILURE)
+            }
+        },
+        Command::Put {
+            path,
+            key,
+            value,
+            cf,
+        } => {
+            let mut batch = WriteBatch::new();
+            batch.put_cf(cf, key, value.into_bytes());
+            open_existing(path)?.write_batch_with_options(batch, &WriteOptions { sync: true })?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Scan {
+            path,
+            start,
+            end,
+            cf,
+            limit,
+        } => {
+            let db = ReadOnlyDb::open(path)?;
+            let end = end.map_or(Bound::Unbounded, Bound::Excluded);
+            let entries = db.iter_cf(&cf, (Bound::Included(start), end))?;
+            for entry in entries.take(limit.unwrap_or(usize::MAX)) {
+                let (key, value) = entry?;
+                writeln!(out, "{} => {}", key, escape(&value))?;
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Compact { path, cf } => {
+            let db = open_existing(path)?;
+            db.flush_cf(&cf)?;
+            db.compact_cf(&cf)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Checkpoint { path, dir } => {
+            open_existing(path)?.checkpoint(dir)?;
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+/// Opens a database for writing, refusing a directory that holds none.
+fn open_existing(path: PathBuf) -> Result<Db, Error> {
+    let options = Options {
+        create_if_missing: false,
+        ..Options::default()
+    };
+    Db::open_with_options(path, options)
+}
+
+fn report(out: &mut dyn Write, report: &Report) -> Result<ExitCode, Error> {
+    print_report(out, report)?;
+    match report.is_clean() {
+        true => Ok(ExitCode::SUCCESS),
+        false => Ok(ExitCode::FAILURE),
+    }
+}
+
+fn open_table(path: &Path) -> Result<Table, Error> {
+    let cache = Arc::new(BlockCache::default());
+    Table::open(&OsFileSystem, path, cache, true, false)
+}
+
+fn sst_dump(out: &mut dyn Write, path: &Path, layout: bool, entries: bool) -> Result<(), Error> {
+    let table = open_table(path)?;
+    let properties = table.properties();
+    writeln!(out, "entries: {}", properties.entries)?;
+    writeln!(out, "range tombstones: {}", properties.tombstones)?;
+    writeln!(out, "data blocks: {}", properties.data_blocks)?;
+    writeln!(
+        out,
+        "data size: {} bytes, {} uncompressed ({:.2}x)",
+        properties.data_size,
+        properties.raw_data_size,
+        properties.compression_ratio()
+    )?;
+    if let (Some(smallest), Some(largest)) = (&properties.smallest_key, &properties.largest_key) {
+        writeln!(out, "keys: {} ..= {}", smallest, largest)?;
+    }
+
+    for tombstone in table.tombstones() {
+        writeln!(out, "tombstone: {} .. {}", tombstone.start, tombstone.end)?;
+    }
+    if layout {
+        for (i, entry) in table.index()?.iter().enumerate() {
+            writeln!(
+                out,
+                "block {}: offset {}, {} bytes, last key {}",
+                i, entry.handle.offset, entry.handle.size, entry.last_key
+            )?;
+        }
+    }
+    if entries {
+        for entry in table.iter(..)? {
+            let entry = entry?;
+            match entry.value {
+                Value::Inline(value) => writeln!(out, "{} => {}", entry.key, escape(&value))?,
+                Value::Pointer(pointer) => writeln!(
+                    out,
+                    "{} => value log {} at offset {}, {} bytes",
+                    entry.key, pointer.file, pointer.offset, pointer.size
+                )?,
+                Value::Tombstone => writeln!(out, "{} deleted", entry.key)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_manifest(out: &mut dyn Write, path: &Path) -> Result<(), Error> {
+    let Some(manifest) = Manifest::load(&OsFileSystem, path)? else {
+        writeln!(out, "no manifest in {}", path.display())?;
+        return Ok(());
+    };
+    writeln!(out, "next file: {}", manifest.next_file)?;
+    writeln!(out, "next column family: {}", manifest.next_column_family)?;
+    for cf in &manifest.column_families {
+        writeln!(
+            out,
+            "column family {} ({}): WAL from segment {}",
+            cf.name, cf.id, cf.log_number
+        )?;
+        for &file in &cf.files {
+            let table_path = sst_path(path, file);
+            match open_table(&table_path) {
+                Ok(table) => {
+                    let properties = table.properties();
+                    let keys = match (&properties.smallest_key, &properties.largest_key) {
+                        (Some(smallest), Some(largest)) => format!("{} ..= {}", smallest, largest),
+                        _ => String::from("no keys"),
+                    };
+                    writeln!(
+                        out,
+                        "  table {}: {} entries, {} bytes, {}",
+                        file, properties.entries, properties.data_size, keys
+                    )?;
+                }
+                Err(e) => writeln!(out, "  table {}: {}", file, e)?,
+            }
+        }
+        for &number in &cf.value_logs {
+            match std::fs::metadata(value_log_path(path, number)) {
+                Ok(metadata) => writeln!(out, "  value log {}: {} bytes", number, metadata.len())?,
+                Err(e) => writeln!(out, "  value log {}: {}", number, e)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Shows printable ASCII as is and escapes every other byte.
+fn escape(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|&b| std::ascii::escape_default(b))
+        .map(char::from)
+        .collect()
+}
+
+fn print_report(out: &mut dyn Write, report: &Report) -> Result<(), Error> {
+    if let Some(e) = &report.manifest {
+        writeln!(
+            out,
+            "manifest: unusable ({}), only the default column family rebuilt from the logs",
+            e
+        )?;
+    }
+    for path in &report.unrecoverable {
+        writeln!(
+            out,
+            "unrecoverable without the manifest: {}",
+            path.display()
+        )?;
+    }
+    let kinds = [
+        ("table", &report.tables),
+        ("log", &report.logs),
+        ("value log", &report.value_logs),
+    ];
+    for (kind, files) in kinds {
+        for file in files {
+            print_file(out, kind, file)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_file(out: &mut dyn Write, kind: &str, file: &FileReport) -> Result<(), Error> {
+    let status = match file.is_intact() {
+        true => "ok",
+        false => "damaged",
+    };
+    writeln!(
+        out,
+        "{} {}: {}, {} recovered",
+        kind,
+        file.path.display(),
+        status,
+        file.recovered
+    )?;
+    for e in &file.corruptions {
+        writeln!(out, "  {}", e)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    /// Runs the command line `args`, returning its exit code and output.
+    fn logos(args: &[&str]) -> Result<(ExitCode, String), Error> {
+        let cli =
+            Cli::try_parse_from(std::iter::once("logos").chain(args.iter().copied())).unwrap();
+        let mut out = Vec::new();
+        let code = run(cli.command, &mut out)?;
+        Ok((code, String::from_utf8(out).unwrap()))
+    }
+
+    fn ok(args: &[&str]) -> String {
+        let (code, out) = logos(args).unwrap();
+        assert_eq!(ExitCode::SUCCESS, code, "{:?}", args);
+        out
+    }
+
+    /// The name and size of every file under `dir`.
+    fn files(dir: &Path) -> BTreeMap<PathBuf, u64> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.path(), entry.metadata().unwrap().len())
+            })
+            .collect()
+    }
+
+    fn create(path: &str) {
+        std::fs::create_dir_all(path).unwrap();
+        Db::open(path).unwrap();
+    }
+
+    #[test]
+    fn put_get_and_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let path = path.to_str().unwrap();
+        create(path);
+
+        for (key, value) in [("a", "1"), ("b", "2"), ("c", "3\n")] {
+            ok(&["put", path, key, value]);
+        }
+        assert_eq!("2\n", ok(&["get", path, "b"]));
+        assert_eq!(ExitCode::FAILURE, logos(&["get", path, "d"]).unwrap().0);
+        assert_eq!("a => 1\nb => 2\nc => 3\\n\n", ok(&["scan", path]));
+        assert_eq!("b => 2\nc => 3\\n\n", ok(&["scan", path, "b"]));
+        assert_eq!("a => 1\n", ok(&["scan", path, "", "b"]));
+        assert_eq!("a => 1\nb => 2\n", ok(&["scan", path, "--limit", "2"]));
+        assert_eq!("", ok(&["scan", path, "c", "a"]));
+        assert!(matches!(
+            logos(&["get", path, "a", "--cf", "missing"]),
+            Err(Error::ColumnFamilyNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn reads_leave_the_directory_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let path = path.to_str().unwrap();
+        create(path);
+        ok(&["put", path, "a", "1"]);
+        // An orphaned table, which opening the database for writing removes.
+        std::fs::write(Path::new(path).join("1000.sst"), b"orphan").unwrap();
+
+        let before = files(Path::new(path));
+        ok(&["get", path, "a"]);
+        ok(&["scan", path]);
+        ok(&["manifest", path]);
+        ok(&["verify", path]);
+        assert_eq!(before, files(Path::new(path)));
+    }
+
+    #[test]
+    fn missing_databases_are_not_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("typo");
+        let path = path.to_str().unwrap();
+
+        for args in [
+            &["get", path, "a"][..],
+            &["scan", path],
+            &["put", path, "a", "1"],
+            &["compact", path],
+        ] {
+            assert!(
+                matches!(logos(args), Err(Error::DatabaseNotFound(_))),
+                "{:?}",
+                args
+            );
+        }
+        let checkpoint = dir.path().join("checkpoint");
+        let args = ["checkpoint", path, checkpoint.to_str().unwrap()];
+        assert!(matches!(logos(&args), Err(Error::DatabaseNotFound(_))));
+        assert!(!Path::new(path).exists());
+        assert!(!checkpoint.exists());
+    }
+
+    #[test]
+    fn compact_checkpoint_and_inspect() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let path = path.to_str().unwrap();
+        create(path);
+        for key in ["a", "b", "c"] {
+            ok(&["put", path, key, "value"]);
+        }
+        ok(&["compact", path]);
+
+        let checkpoint = dir.path().join("checkpoint");
+        let checkpoint = checkpoint.to_str().unwrap();
+        ok(&["checkpoint", path, checkpoint]);
+        assert_eq!("value\n", ok(&["get", checkpoint, "c"]));
+
+        let manifest = ok(&["manifest", checkpoint]);
+        assert!(
+            manifest.contains("column family default (0)"),
+            "{}",
+            manifest
+        );
+        let table = files(Path::new(checkpoint))
+            .into_keys()
+            .find(|p| p.extension().is_some_and(|e| e == "sst"))
+            .unwrap();
+        let dump = ok(&["sst-dump", table.to_str().unwrap(), "--layout"]);
+        assert!(dump.starts_with("entries: 3\n"), "{}", dump);
+        assert!(dump.contains("block 0: offset 0"), "{}", dump);
+        assert!(
+            dump.ends_with("a => value\nb => value\nc => value\n"),
+            "{}",
+            dump
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use std::io::ErrorKind;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::env::FileSystem;
+use crate::options::ColumnFamilyOptions;
+use crate::Error;
+
+pub(crate) const MANIFEST: &str = "MANIFEST";
+const MANIFEST_TMP: &str = "MANIFEST.tmp";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ColumnFamilyMeta {
+    pub id: u32,
+    pub name: String,
+    pub options: ColumnFamilyOptions,
+    /// The oldest WAL segment that may still hold writes not yet in `files`.
+    pub log_number: usize,
+    pub files: Vec<usize>,
+    /// Value logs holding the values separated from keys in `files`.
+    pub value_logs: Vec<usize>,
+}
+
+/// The persisted layout of a database directory: which column families exist
+/// and which SSTable and value log files belong to each of them.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Manifest {
+    pub next_file: usize,
+    pub next_column_family: u32,
+    pub column_families: Vec<ColumnFamilyMeta>,
+}
+
+impl Manifest {
+    pub fn load(fs: &dyn FileSystem, path: &Path) -> Result<Option<Self>, Error> {
+        let bytes = match fs.read(&path.join(MANIFEST)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let corruption = || Error::Corruption {
+            file: path.join(MANIFEST),
+            offset: 0,
+        };
+        let split = bytes.len().checked_sub(4).ok_or_else(corruption)?;
+        let (bytes, checksum) = bytes.split_at(split);
+        if crc32c::crc32c(bytes).to_le_bytes() != checksum {
+            return Err(corruption());
+        }
+        bincode::deserialize(bytes)
+            .map(Some)
+            .map_err(|_| Error::BincodeError)
+    }
+
+    /// Replaces the manifest on disk by writing a temporary file and renaming
+    /// it over the old one, so neither readers nor a crash ever observe a
+    /// partial manifest. With `sync` set, the file and the directory are
+    /// synced as well, which makes the same hold after a power loss.
+    pub fn save(&self, fs: &dyn FileSystem, path: &Path, sync: bool) -> Result<(), Error> {
+        let mut bytes = bincode::serialize(self).map_err(|_| Error::BincodeError)?;
+        bytes.extend_from_slice(&crc32c::crc32c(&bytes).to_le_bytes());
+        let tmp = path.join(MANIFEST_TMP);
+        let mut file = fs.create(&tmp)?;
+        file.append(&bytes)?;
+        if !sync {
+            fs.rename(&tmp, &path.join(MANIFEST))?;
+            return Ok(());
+        }
+        file.sync()?;
+        // Persist the entries of the files it lists before the manifest
+        // itself, then the rename.
+        fs.sync_dir(path)?;
+        fs.rename(&tmp, &path.join(MANIFEST))?;
+        fs.sync_dir(path)?;
+        Ok(())
+    }
+}
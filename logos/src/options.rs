@@ -0,0 +1,176 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::BlockCache;
+use crate::compression::Compression;
+use crate::env::{Clock, FileSystem, OsFileSystem, SystemClock};
+use crate::listener::EventListener;
+
+const DEFAULT_MEMTABLE_CAPACITY: usize = 10_000;
+const DEFAULT_BLOCK_SIZE: usize = 4096;
+const DEFAULT_BLOCK_RESTART_INTERVAL: usize = 16;
+const DEFAULT_VALUE_LOG_GC_THRESHOLD: f64 = 0.5;
+const DEFAULT_MAX_SEGMENT_SIZE: u64 = 64 << 20;
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub default_column_family: ColumnFamilyOptions,
+    /// Verify the checksum of every SSTable block as it is read. Footers are
+    /// verified regardless.
+    pub paranoid_checks: bool,
+    /// Cache of decoded SSTable blocks. Clone the same cache into the
+    /// options of several drivers to share it between them.
+    pub block_cache: Arc<BlockCache>,
+    /// Read SSTables through memory maps instead of positioned reads.
+    pub mmap_reads: bool,
+    /// Where every file of the database is stored.
+    pub file_system: Arc<dyn FileSystem>,
+    /// When writes, and the files flushes and compactions write, are synced
+    /// to disk.
+    pub sync: SyncPolicy,
+    /// What `SyncPolicy::Interval` is timed by. Latencies and durations in
+    /// the statistics are measured in real time regardless.
+    pub clock: Arc<dyn Clock>,
+    /// Number of SSTables at which the background thread of a `Db` compacts
+    /// a column family. `None` leaves compaction to the caller.
+    pub compaction_trigger: Option<usize>,
+    /// Told about flushes, compactions, deleted tables, stalls and
+    /// background errors, in order.
+    pub listeners: Vec<Arc<dyn EventListener>>,
+    /// Create an empty database when the directory holds none. Otherwise
+    /// opening a directory without a manifest fails with
+    /// `Error::DatabaseNotFound`.
+    pub create_if_missing: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            default_column_family: ColumnFamilyOptions::default(),
+            paranoid_checks: true,
+            block_cache: Arc::new(BlockCache::default()),
+            mmap_reads: false,
+            file_system: Arc::new(OsFileSystem),
+            sync: SyncPolicy::default(),
+            clock: Arc::new(SystemClock::new()),
+            compaction_trigger: None,
+            listeners: Vec::new(),
+            create_if_missing: true,
+        }
+    }
+}
+
+/// What is synced to disk and when, which bounds what a power loss can take
+/// away. Unless the policy is `Never`, every table, value log and manifest is
+/// synced as it is written, along with the directory entries of new files,
+/// since the WAL segments they replace are deleted afterwards. Whatever the
+/// policy, a write asking for it through `WriteOptions`, or
+/// `Driver::sync_wal`, syncs the WAL.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync the WAL after every write, so that every acknowledged write is
+    /// durable.
+    Always,
+    /// Sync the WAL after a write once the interval has passed since the last
+    /// sync, committing the writes in between as a group. Writes left
+    /// unsynced are synced by the background thread of a `Db` once the
+    /// interval is up, or by `Driver::sync_if_due` for a driver used on its
+    /// own.
+    Interval(Duration),
+    /// Never sync the WAL on its own, so a power loss takes away the writes
+    /// since the last flush.
+    #[default]
+    OnFlush,
+    /// Leave every file to the operating system. A power loss can then leave
+    /// the database unreadable, while a crash of the process alone loses
+    /// nothing.
+    Never,
+}
+
+impl SyncPolicy {
+    /// Whether tables, value logs, the manifest and directory entries are
+    /// synced as they are written.
+    pub fn syncs_files(self) -> bool {
+        self != SyncPolicy::Never
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Sync the WAL before the write returns, whatever the `SyncPolicy`.
+    pub sync: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ColumnFamilyOptions {
+    pub memtable_capacity: usize,
+    pub compression: Compression,
+    /// Target size of an uncompressed SSTable data block, in bytes.
+    pub block_size: usize,
+    /// Number of keys between restart points in a data block. Other keys
+    /// only store the part they do not share with the key before them, so
+    /// larger intervals make blocks smaller but lookups scan further.
+    pub block_restart_interval: usize,
+    /// Values of at least this many bytes are moved into a value log when
+    /// their memtable is flushed, leaving SSTables with a pointer to them so
+    /// compaction no longer rewrites them. `None` keeps every value inline.
+    pub min_value_log_size: Option<usize>,
+    /// Fraction of a value log that must be garbage before garbage collection
+    /// rewrites its live values and deletes it.
+    pub value_log_gc_threshold: f64,
+}
+
+impl Default for ColumnFamilyOptions {
+    fn default() -> Self {
+        Self {
+            memtable_capacity: DEFAULT_MEMTABLE_CAPACITY,
+            compression: Compression::default(),
+            block_size: DEFAULT_BLOCK_SIZE,
+            block_restart_interval: DEFAULT_BLOCK_RESTART_INTERVAL,
+            min_value_log_size: None,
+            value_log_gc_threshold: DEFAULT_VALUE_LOG_GC_THRESHOLD,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BitcaskOptions {
+    /// Size at which the active data segment is closed and a new one started.
+    pub max_segment_size: u64,
+    /// Where the data and hint files are stored.
+    pub file_system: Arc<dyn FileSystem>,
+    /// When writes are synced to disk. Besides what the policy says for
+    /// each write, `Bitcask::flush` and `Bitcask::close` sync those left
+    /// unsynced, as does starting a new segment, and merged segments are
+    /// synced before the ones they replace are deleted. Under `Never`,
+    /// nothing is.
+    pub sync: SyncPolicy,
+}
+
+impl Default for BitcaskOptions {
+    fn default() -> Self {
+        Self {
+            max_segment_size: DEFAULT_MAX_SEGMENT_SIZE,
+            file_system: Arc::new(OsFileSystem),
+            sync: SyncPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BackupOptions {
+    /// Where the backup repository is stored. Backups are checkpoints of a
+    /// database written into the repository, so this must also be the file
+    /// system of the databases backed up.
+    pub file_system: Arc<dyn FileSystem>,
+}
+
+impl Default for BackupOptions {
+    fn default() -> Self {
+        Self {
+            file_system: Arc::new(OsFileSystem),
+        }
+    }
+}
@@ -0,0 +1,447 @@
+use std::path::{Path, PathBuf};
+
+use crate::batch::Operation;
+use crate::db::{MemTable, SSTable};
+use crate::driver::{self, DEFAULT_COLUMN_FAMILY, SST_EXTENSION, WAL_EXTENSION};
+use crate::env::FileSystem;
+use crate::manifest::{ColumnFamilyMeta, Manifest};
+use crate::options::{ColumnFamilyOptions, Options};
+use crate::value_log::{self, ValueLog, VALUE_LOG_EXTENSION};
+use crate::wal;
+use crate::Error;
+
+const LOST_DIR: &str = "lost";
+
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// Entries of a table, or records of a log segment or value log, that
+    /// could be read.
+    pub recovered: usize,
+    /// The corruption found in each block or record that could not be read.
+    pub corruptions: Vec<Error>,
+}
+
+impl FileReport {
+    pub fn is_intact(&self) -> bool {
+        self.corruptions.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Why the manifest could not be used, in which case only the default
+    /// column family can be rebuilt, from the log segments.
+    pub manifest: Option<Error>,
+    pub tables: Vec<FileReport>,
+    pub logs: Vec<FileReport>,
+    pub value_logs: Vec<FileReport>,
+    /// Files that may be intact but whose data cannot be put back without
+    /// the manifest: tables and value logs, which do not record the column
+    /// family they belong to, and log segments holding writes to column
+    /// families other than the default one. Repair moves them into `lost`.
+    pub unrecoverable: Vec<PathBuf>,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.manifest.is_none()
+            && self.tables.iter().all(FileReport::is_intact)
+            && self.logs.iter().all(FileReport::is_intact)
+            && self.value_logs.iter().all(FileReport::is_intact)
+            && self.unrecoverable.is_empty()
+    }
+}
+
+/// A table that survived the scan, either untouched or salvaged from a
+/// damaged file and still to be rewritten.
+enum Table {
+    Intact(usize),
+    Salvaged(usize, SSTable),
+}
+
+struct ColumnFamily {
+    meta: ColumnFamilyMeta,
+    tables: Vec<Table>,
+    master: MemTable,
+}
+
+struct Scan {
+    report: Report,
+    column_families: Vec<ColumnFamily>,
+    logs: Vec<usize>,
+    next_file: usize,
+    next_column_family: u32,
+}
+
+/// Checks every table, log segment and value log of a database directory
+/// against its checksums without modifying anything.
+pub fn verify<P: AsRef<Path>>(path: P) -> Result<Report, Error> {
+    verify_with_options(path, &Options::default())
+}
+
+/// Like `verify`, reading the directory through `options.file_system`.
+pub fn verify_with_options<P: AsRef<Path>>(path: P, options: &Options) -> Result<Report, Error> {
+    Ok(scan(&*options.file_system, path.as_ref())?.report)
+}
+
+/// Rebuilds a consistent database from whatever survives in a damaged
+/// directory. Readable blocks of damaged tables and readable records of
+/// damaged log segments are rewritten into new tables, a new manifest is
+/// written, and the damaged files are moved into a `lost` subdirectory.
+/// Damaged value logs are left in place, since tables still point at the
+/// values that survive in them; reading a lost value reports corruption.
+pub fn repair<P: AsRef<Path>>(path: P) -> Result<Report, Error> {
+    repair_with_options(path, &Options::default())
+}
+
+/// Like `repair`, working on the directory through `options.file_system`.
+pub fn repair_with_options<P: AsRef<Path>>(path: P, options: &Options) -> Result<Report, Error> {
+    let fs = &*options.file_system;
+    let path = path.as_ref();
+    let Scan {
+        report,
+        column_families,
+        logs,
+        mut next_file,
+        next_column_family,
+    } = scan(fs, path)?;
+
+    let mut damaged = Vec::new();
+    let mut metas = Vec::new();
+    for cf in column_families {
+        let mut meta = cf.meta;
+        meta.files.clear();
+        for table in cf.tables {
+            match table {
+                Table::Intact(file) => meta.files.push(file),
+                Table::Salvaged(file, sst) => {
+                    damaged.push(driver::sst_path(path, file));
+                    if !sst.entries().is_empty() || !sst.tombstones().is_empty() {
+                        meta.files
+                            .push(write_table(fs, path, next_file, &meta.options, &sst)?);
+                        next_file += 1;
+                    }
+                }
+            }
+        }
+        if !cf.master.is_empty() {
+            let sst = SSTable::from(&cf.master);
+            meta.files
+                .push(write_table(fs, path, next_file, &meta.options, &sst)?);
+            next_file += 1;
+        }
+        metas.push(meta);
+    }
+
+    for meta in metas.iter_mut() {
+        meta.log_number = next_file;
+    }
+    Manifest {
+        next_file: next_file + 1,
+        next_column_family,
+        column_families: metas,
+    }
+    .save(fs, path, true)?;
+
+    for log in &report.logs {
+        if !log.is_intact() {
+            damaged.push(log.path.clone());
+        }
+    }
+    for file in &report.unrecoverable {
+        if !damaged.contains(file) {
+            damaged.push(file.clone());
+        }
+    }
+    if !damaged.is_empty() {
+        let lost = path.join(LOST_DIR);
+        driver::create_dir(fs, &lost, true)?;
+        for file in damaged {
+            if let Some(name) = file.file_name() {
+                fs.rename(&file, &lost.join(name))?;
+            }
+        }
+        fs.sync_dir(&lost)?;
+    }
+    // Segments moved into `lost` are already gone.
+    for number in logs {
+        driver::remove_file(fs, &wal::wal_path(path, number))?;
+    }
+    Ok(report)
+}
+
+fn write_table(
+    fs: &dyn FileSystem,
+    path: &Path,
+    file: usize,
+    options: &ColumnFamilyOptions,
+    sst: &SSTable,
+) -> Result<usize, Error> {
+    let (bytes, _) = sst.into_bytes(options)?;
+    driver::write_sst(fs, path, file, bytes, true)?;
+    Ok(file)
+}
+
+fn scan(fs: &dyn FileSystem, path: &Path) -> Result<Scan, Error> {
+    let mut report = Report::default();
+    let tables = driver::list_files(fs, path, SST_EXTENSION)?;
+    let logs = driver::list_files(fs, path, WAL_EXTENSION)?;
+    let value_logs = driver::list_files(fs, path, VALUE_LOG_EXTENSION)?;
+
+    let manifest = match Manifest::load(fs, path) {
+        Ok(manifest) => manifest,
+        Err(e @ (Error::Corruption { .. } | Error::BincodeError)) => {
+            report.manifest = Some(e);
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    let fallback = ColumnFamilyMeta {
+        id: 0,
+        name: String::from(DEFAULT_COLUMN_FAMILY),
+        options: ColumnFamilyOptions::default(),
+        log_number: 0,
+        files: Vec::new(),
+        value_logs: Vec::new(),
+    };
+    let (metas, next_file, next_column_family) = match manifest {
+        Some(m) => (m.column_families, m.next_file, m.next_column_family),
+        None => {
+            report.unrecoverable.extend(
+                tables
+                    .iter()
+                    .map(|&file| driver::sst_path(path, file))
+                    .chain(
+                        value_logs
+                            .iter()
+                            .map(|&number| value_log::value_log_path(path, number)),
+                    ),
+            );
+            (vec![fallback], 1, 1)
+        }
+    };
+    let next_file = tables
+        .iter()
+        .chain(logs.iter())
+        .chain(value_logs.iter())
+        .map(|n| n + 1)
+        .fold(next_file, usize::max);
+
+    let mut column_families = Vec::new();
+    for meta in metas {
+        let mut cf = ColumnFamily {
+            master: MemTable::with_capacity(meta.options.memtable_capacity),
+            tables: Vec::new(),
+            meta,
+        };
+        for &file in &cf.meta.files {
+            let path = driver::sst_path(path, file);
+            let bytes = match fs.read(&path) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    report.tables.push(FileReport {
+                        path,
+                        recovered: 0,
+                        corruptions: vec![e.into()],
+                    });
+                    continue;
+                }
+            };
+
+            let (sst, corruptions) = SSTable::salvage(&bytes);
+            report.tables.push(FileReport {
+                path: path.clone(),
+                recovered: sst.entries().len(),
+                corruptions: corruptions.into_iter().map(|e| e.in_file(&path)).collect(),
+            });
+            match report.tables.last().is_some_and(FileReport::is_intact) {
+                true => cf.tables.push(Table::Intact(file)),
+                false => cf.tables.push(Table::Salvaged(file, sst)),
+            }
+        }
+        for &number in &cf.meta.value_logs {
+            let salvaged = ValueLog::open(fs, path, number).and_then(|log| log.salvage());
+            let path = value_log::value_log_path(path, number);
+            report.value_logs.push(match salvaged {
+                Ok((records, corruption)) => FileReport {
+                    path,
+                    recovered: records.len(),
+                    corruptions: corruption.into_iter().collect(),
+                },
+                Err(e) => FileReport {
+                    path,
+                    recovered: 0,
+                    corruptions: vec![e],
+                },
+            });
+        }
+        column_families.push(cf);
+    }
+
+    let min_log = column_families.iter().map(|cf| cf.meta.log_number).min();
+    let logs: Vec<usize> = logs.into_iter().filter(|n| Some(*n) >= min_log).collect();
+    for &number in &logs {
+        let (records, corruption) = wal::salvage_segment(fs, path, number)?;
+        let mut file = FileReport {
+            path: wal::wal_path(path, number),
+            recovered: 0,
+            corruptions: corruption.into_iter().collect(),
+        };
+        let mut orphaned = false;
+        for record in records {
+            let operations: Vec<(u32, Operation)> = match bincode::deserialize(&record) {
+                Ok(operations) => operations,
+                Err(_) => {
+                    file.corruptions.push(Error::BincodeError);
+                    continue;
+                }
+            };
+            file.recovered += 1;
+            for (id, operation) in operations {
+                let cf = column_families.iter_mut().find(|cf| cf.meta.id == id);
+                match cf {
+                    Some(cf) if number >= cf.meta.log_number => cf.master.apply(operation),
+                    Some(_) => {}
+                    // A column family the lost manifest listed, rather than
+                    // one that was dropped.
+                    None => orphaned |= report.manifest.is_some(),
+                }
+            }
+        }
+        if orphaned {
+            report.unrecoverable.push(file.path.clone());
+        }
+        report.logs.push(file);
+    }
+
+    Ok(Scan {
+        report,
+        column_families,
+        logs,
+        next_file,
+        next_column_family,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::batch::WriteBatch;
+    use crate::block;
+    use crate::driver::Driver;
+    use crate::env::MemoryFileSystem;
+
+    use super::*;
+
+    fn value(value: u32) -> Vec<u8> {
+        value.to_string().into_bytes()
+    }
+
+    fn options() -> Options {
+        Options {
+            file_system: Arc::new(MemoryFileSystem::new()),
+            ..Options::default()
+        }
+    }
+
+    fn populate(path: &Path, options: &Options) -> Vec<usize> {
+        let mut driver = Driver::open_with_options(path, options.clone()).unwrap();
+        for i in 0..300 {
+            driver.write(format!("key/{:03}", i), value(i)).unwrap();
+            if i % 100 == 99 {
+                driver.flush_table().unwrap();
+            }
+        }
+        driver.write(String::from("unflushed"), value(1)).unwrap();
+        driver::list_files(&*options.file_system, path, SST_EXTENSION).unwrap()
+    }
+
+    #[test]
+    fn clean_database() {
+        let (dir, options) = (Path::new("/db"), options());
+        populate(dir, &options);
+
+        let report = verify_with_options(dir, &options).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(3, report.tables.len());
+        assert_eq!(
+            300,
+            report.tables.iter().map(|t| t.recovered).sum::<usize>()
+        );
+
+        assert!(repair_with_options(dir, &options).unwrap().is_clean());
+        let driver = Driver::open_with_options(dir, options).unwrap();
+        assert_eq!(Some(value(150)), driver.read("key/150").unwrap());
+        assert_eq!(Some(value(1)), driver.read("unflushed").unwrap());
+    }
+
+    #[test]
+    fn damaged_table() {
+        let (dir, options) = (Path::new("/db"), options());
+        let fs = &*options.file_system;
+        let tables = populate(dir, &options);
+
+        let damaged = driver::sst_path(dir, tables[1]);
+        let mut bytes = fs.read(&damaged).unwrap();
+        bytes[block::HEADER_SIZE] ^= 0xff;
+        fs.create(&damaged).unwrap().append(&bytes).unwrap();
+
+        let report = repair_with_options(dir, &options).unwrap();
+        assert!(!report.is_clean());
+        let lost: Vec<&FileReport> = report.tables.iter().filter(|t| !t.is_intact()).collect();
+        assert_eq!(1, lost.len());
+        assert_eq!(damaged, lost[0].path);
+        assert!(fs.exists(&dir.join(LOST_DIR).join(damaged.file_name().unwrap())));
+
+        let driver = Driver::open_with_options(dir, options.clone()).unwrap();
+        assert_eq!(Some(value(50)), driver.read("key/050").unwrap());
+        assert_eq!(Some(value(250)), driver.read("key/250").unwrap());
+        assert_eq!(Some(value(1)), driver.read("unflushed").unwrap());
+        let recovered = driver
+            .scan(String::from("key/")..String::from("key0"))
+            .unwrap()
+            .len();
+        assert_eq!(200 + lost[0].recovered, recovered);
+        assert!(verify_with_options(dir, &options).unwrap().is_clean());
+    }
+
+    #[test]
+    fn lost_manifest() {
+        let (dir, options) = (Path::new("/db"), options());
+        let fs = &*options.file_system;
+        let tables = populate(dir, &options);
+        {
+            let mut driver = Driver::open_with_options(dir, options.clone()).unwrap();
+            driver
+                .create_column_family("index", ColumnFamilyOptions::default())
+                .unwrap();
+            let mut batch = WriteBatch::new();
+            batch.put_cf("index", String::from("key/000"), value(7));
+            driver.write_batch(batch).unwrap();
+            driver.write(String::from("unflushed"), value(2)).unwrap();
+        }
+        fs.create(&dir.join("MANIFEST"))
+            .unwrap()
+            .append(b"garbage")
+            .unwrap();
+        assert!(Driver::open_with_options(dir, options.clone()).is_err());
+
+        let report = repair_with_options(dir, &options).unwrap();
+        assert!(report.manifest.is_some());
+        for file in &tables {
+            let table = driver::sst_path(dir, *file);
+            assert!(report.unrecoverable.contains(&table));
+            assert!(fs.exists(&dir.join(LOST_DIR).join(table.file_name().unwrap())));
+        }
+        // The segment holding the write to `index` is kept for inspection.
+        assert_eq!(tables.len() + 1, report.unrecoverable.len());
+
+        // The tables' keys are not taken into the default column family.
+        let driver = Driver::open_with_options(dir, options.clone()).unwrap();
+        assert_eq!(None, driver.read("key/000").unwrap());
+        assert_eq!(Some(value(2)), driver.read("unflushed").unwrap());
+        assert!(verify_with_options(dir, &options).unwrap().is_clean());
+    }
+}
@@ -0,0 +1,473 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::batch::WriteBatch;
+use crate::driver::Driver;
+use crate::env::Clock;
+use crate::fault_injection::{FaultInjectionFileSystem, Rng};
+use crate::options::{ColumnFamilyOptions, Options, SyncPolicy, WriteOptions};
+use crate::Error;
+
+type Model = BTreeMap<String, Vec<u8>>;
+
+#[derive(Debug, Clone)]
+pub struct SimulationOptions {
+    /// Number of clients issuing operations concurrently.
+    pub clients: usize,
+    /// Number of events to run, counting client operations and background
+    /// jobs.
+    pub steps: usize,
+    /// Number of distinct keys the clients write.
+    pub keys: usize,
+    /// Chance of a crash, followed by power loss, striking during each event.
+    pub crash_probability: f64,
+    pub memtable_capacity: usize,
+    pub min_value_log_size: Option<usize>,
+    /// `SyncPolicy::Interval` in virtual microseconds, or `None` for the
+    /// default policy.
+    pub sync_interval: Option<u64>,
+    /// `Options::compaction_trigger` for the background work.
+    pub compaction_trigger: Option<usize>,
+}
+
+impl Default for SimulationOptions {
+    fn default() -> Self {
+        Self {
+            clients: 4,
+            steps: 2000,
+            keys: 64,
+            crash_probability: 0.005,
+            memtable_capacity: 32,
+            min_value_log_size: Some(64),
+            sync_interval: Some(2_000),
+            compaction_trigger: Some(4),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationReport {
+    pub seed: u64,
+    pub operations: usize,
+    pub flushes: usize,
+    pub compactions: usize,
+    /// WAL syncs made by the background work.
+    pub background_syncs: usize,
+    pub crashes: usize,
+    /// Virtual time at the end of the run, in microseconds.
+    pub elapsed: u64,
+    /// A digest of every event and its outcome, equal between two runs
+    /// exactly when they behaved identically.
+    pub trace: u64,
+}
+
+/// A run that went wrong, with everything needed to replay it.
+#[derive(Debug)]
+pub struct SimulationFailure {
+    pub seed: u64,
+    pub step: usize,
+    /// Virtual time of the failing event, in microseconds.
+    pub time: u64,
+    pub message: String,
+}
+
+impl fmt::Display for SimulationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "seed {} failed at step {} ({}us): {}",
+            self.seed, self.step, self.time, self.message
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Event {
+    Client(usize),
+    Flush,
+    Compact,
+    CollectGarbage,
+    /// The work the background thread of a `Db` does.
+    Background,
+}
+
+/// A clock showing the simulation's virtual time.
+#[derive(Debug, Default)]
+struct VirtualClock(AtomicU64);
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        Duration::from_micros(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Runs a randomized workload against a `Driver` entirely determined by
+/// `seed`, checking every result against a `BTreeMap` model.
+///
+/// The driver runs flushes, compactions and value log garbage collection
+/// inline, so the simulation schedules them itself: they are events on a
+/// virtual clock alongside the clients' operations, and a seeded scheduler
+/// decides when each happens. So is the work a `Db` leaves to its background
+/// thread, which runs when it says it is next due, and the driver reads the
+/// virtual clock to decide when writes fall due. The driver stores its files on a
+/// `FaultInjectionFileSystem` and its futures are polled on the calling
+/// thread, so nothing depends on the real clock, the disk or the tokio
+/// scheduler, and a failing seed replays exactly.
+///
+/// Crashes drop the driver and lose power. The recovered database must then
+/// match the model as of some operation no earlier than the last flush or
+/// synced write.
+pub fn simulate(
+    seed: u64,
+    options: &SimulationOptions,
+) -> Result<SimulationReport, SimulationFailure> {
+    Simulation::new(seed, options).run()
+}
+
+struct Simulation<'a> {
+    options: &'a SimulationOptions,
+    seed: u64,
+    rng: Rng,
+    fs: FaultInjectionFileSystem,
+    clock: Arc<VirtualClock>,
+    /// Pending events by virtual time, ties broken by scheduling order.
+    queue: BinaryHeap<Reverse<(u64, u64, Event)>>,
+    scheduled: u64,
+    now: u64,
+    step: usize,
+    /// The model after each acknowledged write since the last flush or synced
+    /// write. The last entry is the current state.
+    history: Vec<Model>,
+    report: SimulationReport,
+}
+
+impl<'a> Simulation<'a> {
+    fn new(seed: u64, options: &'a SimulationOptions) -> Self {
+        Self {
+            options,
+            seed,
+            rng: Rng::new(seed),
+            fs: FaultInjectionFileSystem::new(seed),
+            clock: Arc::default(),
+            queue: BinaryHeap::new(),
+            scheduled: 0,
+            now: 0,
+            step: 0,
+            history: vec![Model::new()],
+            report: SimulationReport {
+                seed,
+                operations: 0,
+                flushes: 0,
+                compactions: 0,
+                background_syncs: 0,
+                crashes: 0,
+                elapsed: 0,
+                trace: 0xcbf2_9ce4_8422_2325,
+            },
+        }
+    }
+
+    fn run(mut self) -> Result<SimulationReport, SimulationFailure> {
+        let mut driver = self.open()?;
+        for client in 0..self.options.clients {
+            self.schedule(Event::Client(client), 100);
+        }
+        self.schedule(Event::Flush, 5_000);
+        self.schedule(Event::Compact, 20_000);
+        self.schedule(Event::CollectGarbage, 50_000);
+        self.schedule(Event::Background, 1_000);
+
+        for step in 0..self.options.steps {
+            self.step = step;
+            let Some(Reverse((time, _, event))) = self.queue.pop() else {
+                break;
+            };
+            self.now = time;
+            self.clock.0.store(time, Ordering::Relaxed);
+
+            // A crash strikes within the next few file operations, possibly
+            // in the middle of this event.
+            let crash = self.rng.fraction() < self.options.crash_probability;
+            if crash {
+                self.fs.crash_after(self.rng.next() % 16);
+            }
+            let outcome = self.execute(event, &mut driver);
+            self.record(&(event, &outcome));
+            match outcome {
+                Err(message) if !self.fs.is_crashed() => return Err(self.failure(message)),
+                _ if crash => {
+                    self.report.crashes += 1;
+                    drop(driver);
+                    driver = self.recover()?;
+                }
+                _ => {}
+            }
+        }
+        self.report.elapsed = self.now;
+        Ok(self.report)
+    }
+
+    fn execute(&mut self, event: Event, driver: &mut Driver) -> Result<(), String> {
+        match event {
+            Event::Client(client) => {
+                self.schedule(Event::Client(client), 100);
+                self.report.operations += 1;
+                self.client_operation(driver)
+            }
+            Event::Flush => {
+                self.schedule(Event::Flush, 5_000);
+                check(driver.flush_table())?;
+                self.history = vec![self.model().clone()];
+                self.report.flushes += 1;
+                Ok(())
+            }
+            Event::Compact => {
+                self.schedule(Event::Compact, 20_000);
+                check(driver.compact())?;
+                self.report.compactions += 1;
+                Ok(())
+            }
+            Event::CollectGarbage => {
+                self.schedule(Event::CollectGarbage, 50_000);
+                check(driver.collect_garbage())
+            }
+            Event::Background => {
+                let before = driver.stats();
+                let due = driver.run_background_work(self.options.compaction_trigger);
+                // Until writes fall due, the next run waits for the wakes
+                // that writes and flushes give a `Db`.
+                match due {
+                    Ok(Some(due)) => self.schedule_at(Event::Background, due.as_micros() as u64),
+                    _ => self.schedule(Event::Background, 1_000),
+                }
+                check(due)?;
+                let after = driver.stats();
+                if after.wal_syncs > before.wal_syncs {
+                    self.history = vec![self.model().clone()];
+                    self.report.background_syncs += 1;
+                }
+                self.report.compactions += (after.compactions - before.compactions) as usize;
+                Ok(())
+            }
+        }
+    }
+
+    fn options(&self) -> Options {
+        Options {
+            default_column_family: ColumnFamilyOptions {
+                memtable_capacity: self.options.memtable_capacity,
+                min_value_log_size: self.options.min_value_log_size,
+                ..ColumnFamilyOptions::default()
+            },
+            file_system: Arc::new(self.fs.clone()),
+            sync: match self.options.sync_interval {
+                Some(interval) => SyncPolicy::Interval(Duration::from_micros(interval)),
+                None => SyncPolicy::default(),
+            },
+            clock: Arc::clone(&self.clock) as Arc<dyn Clock>,
+            compaction_trigger: self.options.compaction_trigger,
+            ..Options::default()
+        }
+    }
+
+    fn open(&self) -> Result<Driver, SimulationFailure> {
+        Driver::open_with_options(Path::new("/db"), self.options())
+            .map_err(|e| self.failure(format!("open failed: {}", e)))
+    }
+
+    /// Loses power and reopens the driver, checking that it recovered the
+    /// model as of some operation since the last flush.
+    fn recover(&mut self) -> Result<Driver, SimulationFailure> {
+        self.fs.power_loss();
+        let driver = self.open()?;
+        let recovered: Model = driver
+            .scan(String::new()..String::from("\u{10ffff}"))
+            .map_err(|e| self.failure(format!("scan after recovery failed: {}", e)))?
+            .into_iter()
+            .collect();
+        match self.history.contains(&recovered) {
+            true => {
+                self.record(&recovered.len());
+                self.history = vec![recovered];
+                Ok(driver)
+            }
+            false => Err(self.failure(format!(
+                "recovered {} keys matching no state since the last durable write",
+                recovered.len()
+            ))),
+        }
+    }
+
+    /// Runs a random client operation. Writes update the model first, since
+    /// a write interrupted by a crash may or may not survive it.
+    fn client_operation(&mut self, driver: &mut Driver) -> Result<(), String> {
+        let key = self.key();
+        match self.rng.next() % 10 {
+            0..=3 => {
+                let value = self.value();
+                self.update(|m| {
+                    m.insert(key.clone(), value.clone());
+                });
+                check(driver.write(key, value))
+            }
+            4 => {
+                self.update(|m| {
+                    m.remove(&key);
+                });
+                check(driver.delete(key))
+            }
+            5 => {
+                let end = self.key();
+                self.update(|m| m.retain(|k, _| !(&key <= k && k < &end)));
+                check(driver.delete_range(key..end))
+            }
+            6 => {
+                let mut batch = WriteBatch::new();
+                let mut puts = Vec::new();
+                for _ in 0..1 + self.rng.next() % 4 {
+                    puts.push((self.key(), self.value()));
+                }
+                for (key, value) in &puts {
+                    batch.put(key.clone(), value.clone());
+                }
+                self.update(|m| m.extend(puts));
+                // Synced batches make every write up to them durable.
+                let options = WriteOptions {
+                    sync: self.rng.next() & 1 == 0,
+                };
+                check(driver.write_batch_with_options(batch, &options))?;
+                if options.sync {
+                    self.history = vec![self.model().clone()];
+                }
+                Ok(())
+            }
+            7 | 8 => {
+                let value = check(driver.read(&key))?;
+                match value.as_ref() == self.model().get(&key) {
+                    true => Ok(()),
+                    false => Err(format!("get {} disagrees with the model", key)),
+                }
+            }
+            _ => {
+                let end = self.key();
+                let entries = check(driver.scan(key.clone()..end.clone()))?;
+                let expected: Vec<(String, Vec<u8>)> = match key <= end {
+                    true => self
+                        .model()
+                        .range(key.clone()..end.clone())
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect(),
+                    false => Vec::new(),
+                };
+                match entries == expected {
+                    true => Ok(()),
+                    false => Err(format!("scan {}..{} disagrees with the model", key, end)),
+                }
+            }
+        }
+    }
+
+    fn model(&self) -> &Model {
+        self.history.last().unwrap()
+    }
+
+    fn update(&mut self, f: impl FnOnce(&mut Model)) {
+        let mut next = self.model().clone();
+        f(&mut next);
+        self.history.push(next);
+    }
+
+    fn key(&mut self) -> String {
+        format!("key/{:04}", self.rng.next() as usize % self.options.keys)
+    }
+
+    /// A value of random size, sometimes large enough for the value log.
+    fn value(&mut self) -> Vec<u8> {
+        let len = match self.rng.next() % 4 {
+            0 => 64 + self.rng.up_to(256),
+            _ => 1 + self.rng.up_to(16),
+        };
+        let byte = self.rng.next() as u8;
+        vec![byte; len]
+    }
+
+    /// Schedules `event` at a random time within `interval` from now.
+    fn schedule(&mut self, event: Event, interval: u64) {
+        let delay = 1 + self.rng.next() % interval;
+        self.schedule_at(event, delay);
+    }
+
+    /// Schedules `event` exactly `delay` from now, or at the next tick.
+    fn schedule_at(&mut self, event: Event, delay: u64) {
+        let time = self.now + delay.max(1);
+        self.queue.push(Reverse((time, self.scheduled, event)));
+        self.scheduled += 1;
+    }
+
+    /// Folds an event into the trace digest, FNV-1a over its debug form.
+    fn record<T: fmt::Debug>(&mut self, event: &T) {
+        for byte in format!("{}:{:?};", self.now, event).bytes() {
+            self.report.trace ^= byte as u64;
+            self.report.trace = self.report.trace.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+
+    fn failure(&self, message: String) -> SimulationFailure {
+        SimulationFailure {
+            seed: self.seed,
+            step: self.step,
+            time: self.now,
+            message,
+        }
+    }
+}
+
+fn check<T>(result: Result<T, Error>) -> Result<T, String> {
+    result.map_err(|e| format!("{:?}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn random_workloads() {
+        for seed in 0..10 {
+            if let Err(failure) = simulate(seed, &SimulationOptions::default()) {
+                panic!("{}", failure);
+            }
+        }
+    }
+
+    #[test]
+    fn runs_replay_from_the_seed() {
+        let options = SimulationOptions {
+            crash_probability: 0.02,
+            ..SimulationOptions::default()
+        };
+        let first = simulate(42, &options).unwrap();
+        assert!(first.crashes > 0 && first.flushes > 0 && first.compactions > 0);
+        assert_eq!(first, simulate(42, &options).unwrap());
+        assert_ne!(first.trace, simulate(43, &options).unwrap().trace);
+    }
+
+    #[test]
+    fn background_work_runs_on_virtual_time() {
+        // A single client leaves the WAL idle long enough for unsynced
+        // writes to fall due between its operations.
+        let options = SimulationOptions {
+            clients: 1,
+            sync_interval: Some(50),
+            crash_probability: 0.0,
+            ..SimulationOptions::default()
+        };
+        let report = simulate(7, &options).unwrap();
+        assert!(report.background_syncs > 0);
+        assert_eq!(report, simulate(7, &options).unwrap());
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::Path;
+
+use crate::db::{Entry, SSTable, TableProperties, Value};
+use crate::env::FileSystem;
+use crate::options::ColumnFamilyOptions;
+use crate::Error;
+
+/// Builds an SSTable outside of any database, in the format flushes write,
+/// for `Driver::ingest_external_files` to add to a column family. Keys must
+/// be added in strictly ascending order.
+pub struct SstFileWriter {
+    options: ColumnFamilyOptions,
+    entries: Vec<Entry>,
+}
+
+impl SstFileWriter {
+    /// Creates a writer encoding blocks with the compression, block size and
+    /// restart interval of `options`.
+    pub fn new(options: ColumnFamilyOptions) -> Self {
+        Self {
+            options,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn put(&mut self, key: String, value: Vec<u8>) -> Result<(), Error> {
+        if self.entries.last().is_some_and(|last| last.key >= key) {
+            return Err(Error::KeyOutOfOrder(key));
+        }
+        self.entries.push(Entry {
+            key,
+            value: Value::Inline(value),
+        });
+        Ok(())
+    }
+
+    /// Number of keys added so far.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes and syncs the table at `path`, returning its properties.
+    pub fn finish(self, fs: &dyn FileSystem, path: &Path) -> Result<TableProperties, Error> {
+        let (bytes, properties) =
+            SSTable::new(self.entries, Vec::new()).into_bytes(&self.options)?;
+        let mut file = fs.create(path)?;
+        file.append(&bytes)?;
+        file.sync()?;
+        event!(
+            DEBUG,
+            path = %path.display(),
+            entries = properties.entries,
+            "wrote external SSTable"
+        );
+        Ok(properties)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use crate::driver::Driver;
+    use crate::env::MemoryFileSystem;
+    use crate::options::Options;
+
+    use super::*;
+
+    fn write(fs: &dyn FileSystem, name: &str, keys: &[&str], value: u8) -> PathBuf {
+        let mut writer = SstFileWriter::new(ColumnFamilyOptions::default());
+        for key in keys {
+            writer.put(key.to_string(), vec![value]).unwrap();
+        }
+        let path = Path::new("/external").join(name);
+        writer.finish(fs, &path).unwrap();
+        path
+    }
+
+    #[test]
+    fn keys_must_ascend() {
+        let mut writer = SstFileWriter::new(ColumnFamilyOptions::default());
+        writer.put(String::from("b"), Vec::new()).unwrap();
+        let error = writer.put(String::from("a"), Vec::new()).unwrap_err();
+        assert!(matches!(error, Error::KeyOutOfOrder(key) if key == "a"));
+        assert!(writer.put(String::from("b"), Vec::new()).is_err());
+        assert_eq!(1, writer.len());
+    }
+
+    #[test]
+    fn ingest_external_files() {
+        let fs = Arc::new(MemoryFileSystem::new());
+        let options = Options {
+            file_system: fs.clone(),
+            ..Options::default()
+        };
+        let mut driver = Driver::open_with_options("/db", options.clone()).unwrap();
+        driver.write(String::from("a/1"), vec![0]).unwrap();
+        driver.write(String::from("z"), vec![0]).unwrap();
+        driver.flush_table().unwrap();
+        driver.write(String::from("b/1"), vec![0]).unwrap();
+
+        let a = write(&*fs, "a.sst", &["a/1", "a/2"], 1);
+        let b = write(&*fs, "b.sst", &["b/1", "b/2"], 2);
+        let overlapping = write(&*fs, "c.sst", &["a/2", "c"], 3);
+        let error = driver
+            .ingest_external_files(&[&a, &overlapping])
+            .unwrap_err();
+        assert!(matches!(error, Error::InvalidExternalFile { file, .. } if file == overlapping));
+        assert_eq!(Some(vec![0]), driver.read("a/1").unwrap());
+
+        driver.ingest_external_files(&[&b, &a]).unwrap();
+        // "b/1" was in the memtable, which had to be flushed first.
+        assert_eq!(2, driver.stats().flushes);
+        assert_eq!(Some(vec![1]), driver.read("a/1").unwrap());
+        assert_eq!(Some(vec![2]), driver.read("b/1").unwrap());
+        assert_eq!(Some(vec![0]), driver.read("z").unwrap());
+        assert_eq!(
+            5,
+            driver
+                .scan(String::from("a")..String::from("zz"))
+                .unwrap()
+                .len()
+        );
+
+        drop(driver);
+        let mut driver = Driver::open_with_options("/db", options).unwrap();
+        driver.compact().unwrap();
+        assert_eq!(Some(vec![1]), driver.read("a/2").unwrap());
+        assert_eq!(Some(vec![2]), driver.read("b/1").unwrap());
+    }
+}
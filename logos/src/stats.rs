@@ -0,0 +1,422 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the histogram buckets, in microseconds. A last bucket
+/// holds everything slower.
+pub const BUCKET_BOUNDS: [u64; 7] = [10, 100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+
+/// Counters describing the work a `Driver` has done since it was opened.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Statistics {
+    /// Operations written by callers: puts and deletions.
+    pub writes: u64,
+    /// Bytes of keys and values written by callers.
+    pub user_bytes_written: u64,
+    /// Point reads and range scans.
+    pub reads: u64,
+    pub scans: u64,
+    /// Bytes of values returned by reads and scans.
+    pub bytes_read: u64,
+    pub write_latency: Histogram,
+    pub read_latency: Histogram,
+    /// Operations held in memtables, the unit of `memtable_capacity`.
+    pub memtable_size: u64,
+    /// SSTables of each column family. Tables are not arranged in levels:
+    /// each flush adds one and each compaction merges them all.
+    pub tables: BTreeMap<String, u64>,
+    /// Bytes of records appended to the WAL, headers included.
+    pub wal_bytes_written: u64,
+    /// Bytes of SSTable data blocks before compression.
+    pub raw_bytes_written: u64,
+    /// Bytes of SSTable data blocks as written to disk.
+    pub bytes_written: u64,
+    /// Bytes of values moved into value logs, including those relocated by
+    /// garbage collection.
+    pub value_log_bytes_written: u64,
+    /// Bytes of value logs deleted by garbage collection.
+    pub value_log_bytes_reclaimed: u64,
+    /// Lookups served from the block cache. A cache shared between drivers
+    /// reports the lookups of all of them.
+    pub block_cache_hits: u64,
+    pub block_cache_misses: u64,
+    /// Tables consulted by point reads, and those of them skipped without
+    /// reading a block because the key lies outside their key range. Tables
+    /// have no Bloom filters, so keys within the range are always read.
+    pub key_range_checks: u64,
+    pub key_range_skips: u64,
+    /// Records appended to the WAL. Group commit logs many writes as one.
+    pub wal_records: u64,
+    pub wal_syncs: u64,
+    pub flushes: u64,
+    pub flush_duration: Histogram,
+    pub compactions: u64,
+    pub compaction_duration: Histogram,
+}
+
+impl Statistics {
+    pub fn compression_ratio(&self) -> f64 {
+        match self.bytes_written {
+            0 => 1.0,
+            written => self.raw_bytes_written as f64 / written as f64,
+        }
+    }
+
+    pub fn block_cache_hit_rate(&self) -> f64 {
+        match self.block_cache_hits + self.block_cache_misses {
+            0 => 0.0,
+            lookups => self.block_cache_hits as f64 / lookups as f64,
+        }
+    }
+
+    /// Bytes written to the WAL, SSTables and value logs for every byte
+    /// written by callers.
+    pub fn write_amplification(&self) -> f64 {
+        let written = self.wal_bytes_written + self.bytes_written + self.value_log_bytes_written;
+        match self.user_bytes_written {
+            0 => 0.0,
+            user => written as f64 / user as f64,
+        }
+    }
+
+    pub fn key_range_skip_rate(&self) -> f64 {
+        match self.key_range_checks {
+            0 => 0.0,
+            checks => self.key_range_skips as f64 / checks as f64,
+        }
+    }
+
+    /// Renders the statistics in the Prometheus text exposition format, with
+    /// every metric name prefixed by `logos_`.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "writes_total",
+                "Operations written by callers.",
+                self.writes,
+            ),
+            (
+                "user_bytes_written_total",
+                "Bytes of keys and values written by callers.",
+                self.user_bytes_written,
+            ),
+            ("reads_total", "Point reads.", self.reads),
+            ("scans_total", "Range scans.", self.scans),
+            (
+                "bytes_read_total",
+                "Bytes of values returned by reads and scans.",
+                self.bytes_read,
+            ),
+            (
+                "wal_bytes_written_total",
+                "Bytes appended to the WAL.",
+                self.wal_bytes_written,
+            ),
+            (
+                "raw_bytes_written_total",
+                "Bytes of SSTable data blocks before compression.",
+                self.raw_bytes_written,
+            ),
+            (
+                "bytes_written_total",
+                "Bytes of SSTable data blocks written to disk.",
+                self.bytes_written,
+            ),
+            (
+                "value_log_bytes_written_total",
+                "Bytes of values written to value logs.",
+                self.value_log_bytes_written,
+            ),
+            (
+                "value_log_bytes_reclaimed_total",
+                "Bytes of value logs deleted by garbage collection.",
+                self.value_log_bytes_reclaimed,
+            ),
+            (
+                "block_cache_hits_total",
+                "Block cache hits.",
+                self.block_cache_hits,
+            ),
+            (
+                "block_cache_misses_total",
+                "Block cache misses.",
+                self.block_cache_misses,
+            ),
+            (
+                "key_range_checks_total",
+                "Tables consulted by point reads.",
+                self.key_range_checks,
+            ),
+            (
+                "key_range_skips_total",
+                "Tables skipped by point reads as the key lies outside their key range.",
+                self.key_range_skips,
+            ),
+            (
+                "wal_records_total",
+                "Records appended to the WAL.",
+                self.wal_records,
+            ),
+            ("wal_syncs_total", "Syncs of the WAL.", self.wal_syncs),
+            ("flushes_total", "Memtable flushes.", self.flushes),
+            ("compactions_total", "Compactions.", self.compactions),
+        ];
+        for (name, help, value) in counters {
+            metric(&mut out, name, "counter", help);
+            writeln!(out, "logos_{} {}", name, value).unwrap();
+        }
+
+        metric(
+            &mut out,
+            "memtable_size",
+            "gauge",
+            "Operations held in memtables.",
+        );
+        writeln!(out, "logos_memtable_size {}", self.memtable_size).unwrap();
+        metric(&mut out, "tables", "gauge", "SSTables per column family.");
+        for (cf, count) in &self.tables {
+            writeln!(
+                out,
+                "logos_tables{{column_family=\"{}\"}} {}",
+                escape(cf),
+                count
+            )
+            .unwrap();
+        }
+        let gauges = [
+            (
+                "write_amplification",
+                "Bytes written to disk per byte written by callers.",
+                self.write_amplification(),
+            ),
+            (
+                "compression_ratio",
+                "Uncompressed over compressed SSTable data size.",
+                self.compression_ratio(),
+            ),
+            (
+                "block_cache_hit_rate",
+                "Share of block cache lookups that hit.",
+                self.block_cache_hit_rate(),
+            ),
+            (
+                "key_range_skip_rate",
+                "Share of tables skipped by point reads.",
+                self.key_range_skip_rate(),
+            ),
+        ];
+        for (name, help, value) in gauges {
+            metric(&mut out, name, "gauge", help);
+            writeln!(out, "logos_{} {}", name, value).unwrap();
+        }
+
+        let histograms = [
+            (
+                "write_latency_seconds",
+                "Latency of writes.",
+                &self.write_latency,
+            ),
+            (
+                "read_latency_seconds",
+                "Latency of point reads.",
+                &self.read_latency,
+            ),
+            (
+                "flush_duration_seconds",
+                "Duration of memtable flushes.",
+                &self.flush_duration,
+            ),
+            (
+                "compaction_duration_seconds",
+                "Duration of compactions.",
+                &self.compaction_duration,
+            ),
+        ];
+        for (name, help, histogram) in histograms {
+            metric(&mut out, name, "histogram", help);
+            histogram.write_prometheus(&mut out, name);
+        }
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP logos_{} {}", name, help).unwrap();
+    writeln!(out, "# TYPE logos_{} {}", name, kind).unwrap();
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// A distribution of durations over exponential buckets.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    /// Durations falling in each bucket of `BUCKET_BOUNDS`, then slower ones.
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    /// Sum of every duration, in microseconds.
+    pub sum: u64,
+}
+
+impl Histogram {
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => Duration::from_micros(self.sum / count),
+        }
+    }
+
+    /// The upper bound of the bucket holding the `quantile` of durations, or
+    /// `None` if it lies past the last bound or nothing was recorded.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let target = (quantile * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (count, bound) in self.buckets.iter().zip(BUCKET_BOUNDS) {
+            seen += count;
+            if seen >= target {
+                return Some(Duration::from_micros(bound));
+            }
+        }
+        None
+    }
+
+    fn write_prometheus(&self, out: &mut String, name: &str) {
+        let mut cumulative = 0;
+        for (count, bound) in self.buckets.iter().zip(BUCKET_BOUNDS) {
+            cumulative += count;
+            let le = bound as f64 / 1e6;
+            writeln!(out, "logos_{}_bucket{{le=\"{}\"}} {}", name, le, cumulative).unwrap();
+        }
+        writeln!(out, "logos_{}_bucket{{le=\"+Inf\"}} {}", name, self.count).unwrap();
+        writeln!(out, "logos_{}_sum {}", name, self.sum as f64 / 1e6).unwrap();
+        writeln!(out, "logos_{}_count {}", name, self.count).unwrap();
+    }
+}
+
+/// A histogram that can be recorded into from many threads.
+#[derive(Debug, Default)]
+pub(crate) struct AtomicHistogram {
+    buckets: [AtomicU64; BUCKET_BOUNDS.len() + 1],
+    count: AtomicU64,
+    sum: AtomicU64,
+}
+
+impl AtomicHistogram {
+    pub(crate) fn record(&self, duration: Duration) {
+        let micros = duration.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = BUCKET_BOUNDS.partition_point(|&bound| bound < micros);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> Histogram {
+        Histogram {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|b| b.load(Ordering::Relaxed))
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The counters and histograms behind `Statistics`, updated through shared
+/// references: by reads, which go through versions shared between threads,
+/// and by the driver, so that a `Db` reports them without waiting for it.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    pub(crate) writes: AtomicU64,
+    pub(crate) user_bytes_written: AtomicU64,
+    pub(crate) wal_bytes_written: AtomicU64,
+    pub(crate) raw_bytes_written: AtomicU64,
+    pub(crate) bytes_written: AtomicU64,
+    pub(crate) value_log_bytes_written: AtomicU64,
+    pub(crate) value_log_bytes_reclaimed: AtomicU64,
+    pub(crate) wal_records: AtomicU64,
+    pub(crate) wal_syncs: AtomicU64,
+    pub(crate) flushes: AtomicU64,
+    pub(crate) compactions: AtomicU64,
+    pub(crate) reads: AtomicU64,
+    pub(crate) scans: AtomicU64,
+    pub(crate) bytes_read: AtomicU64,
+    pub(crate) key_range_checks: AtomicU64,
+    pub(crate) key_range_skips: AtomicU64,
+    pub(crate) read_latency: AtomicHistogram,
+    pub(crate) write_latency: AtomicHistogram,
+    pub(crate) flush_duration: AtomicHistogram,
+    pub(crate) compaction_duration: AtomicHistogram,
+}
+
+impl Metrics {
+    /// Fills in the fields of `stats` kept here.
+    pub(crate) fn report(&self, stats: &mut Statistics) {
+        stats.writes = self.writes.load(Ordering::Relaxed);
+        stats.user_bytes_written = self.user_bytes_written.load(Ordering::Relaxed);
+        stats.wal_bytes_written = self.wal_bytes_written.load(Ordering::Relaxed);
+        stats.raw_bytes_written = self.raw_bytes_written.load(Ordering::Relaxed);
+        stats.bytes_written = self.bytes_written.load(Ordering::Relaxed);
+        stats.value_log_bytes_written = self.value_log_bytes_written.load(Ordering::Relaxed);
+        stats.value_log_bytes_reclaimed = self.value_log_bytes_reclaimed.load(Ordering::Relaxed);
+        stats.wal_records = self.wal_records.load(Ordering::Relaxed);
+        stats.wal_syncs = self.wal_syncs.load(Ordering::Relaxed);
+        stats.flushes = self.flushes.load(Ordering::Relaxed);
+        stats.compactions = self.compactions.load(Ordering::Relaxed);
+        stats.reads = self.reads.load(Ordering::Relaxed);
+        stats.scans = self.scans.load(Ordering::Relaxed);
+        stats.bytes_read = self.bytes_read.load(Ordering::Relaxed);
+        stats.key_range_checks = self.key_range_checks.load(Ordering::Relaxed);
+        stats.key_range_skips = self.key_range_skips.load(Ordering::Relaxed);
+        stats.read_latency = self.read_latency.snapshot();
+        stats.write_latency = self.write_latency.snapshot();
+        stats.flush_duration = self.flush_duration.snapshot();
+        stats.compaction_duration = self.compaction_duration.snapshot();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_and_quantiles() {
+        let histogram = AtomicHistogram::default();
+        for micros in [5, 50, 50, 5_000, 20_000_000] {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(vec![1, 2, 0, 1, 0, 0, 0, 1], snapshot.buckets);
+        assert_eq!(5, snapshot.count);
+        assert_eq!(Some(Duration::from_micros(100)), snapshot.quantile(0.5));
+        assert_eq!(Some(Duration::from_micros(10_000)), snapshot.quantile(0.8));
+        assert_eq!(None, snapshot.quantile(1.0));
+        assert_eq!(Duration::from_micros(4_001_021), snapshot.mean());
+    }
+
+    #[test]
+    fn prometheus_text() {
+        let histogram = AtomicHistogram::default();
+        histogram.record(Duration::from_micros(50));
+        let stats = Statistics {
+            writes: 3,
+            tables: BTreeMap::from([(String::from("default"), 2)]),
+            flush_duration: histogram.snapshot(),
+            ..Statistics::default()
+        };
+        let text = stats.to_prometheus();
+        assert!(text.contains("# TYPE logos_writes_total counter\nlogos_writes_total 3\n"));
+        assert!(text.contains("logos_tables{column_family=\"default\"} 2\n"));
+        assert!(text.contains("logos_flush_duration_seconds_bucket{le=\"0.00001\"} 0\n"));
+        assert!(text.contains("logos_flush_duration_seconds_bucket{le=\"0.0001\"} 1\n"));
+        assert!(text.contains("logos_flush_duration_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("logos_flush_duration_seconds_count 1\n"));
+    }
+}
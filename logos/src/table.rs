@@ -0,0 +1,430 @@
+use std::borrow::Cow;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::block::{self, BlockHandle, Footer, FOOTER_SIZE};
+use crate::cache::{Block, BlockCache};
+use crate::data_block::{BlockData, DataBlock};
+use crate::db::{self, Entry, IndexEntry, Lookup, RangeTombstone, TableProperties, Value};
+use crate::env::{FileSystem, MappedFile, RandomAccessFile};
+use crate::Error;
+
+enum Source {
+    File(Box<dyn RandomAccessFile>),
+    /// The whole file mapped into memory. The mapping keeps the file's
+    /// contents alive even once compaction has unlinked it.
+    Mmap(Arc<MappedFile>),
+}
+
+/// An SSTable file opened for reading. Only the footer, range tombstones and
+/// properties are held in memory; index and data blocks are read on demand
+/// through the block cache.
+pub struct Table {
+    source: Source,
+    path: PathBuf,
+    id: u64,
+    cache: Arc<BlockCache>,
+    verify: bool,
+    index: BlockHandle,
+    tombstones: Vec<RangeTombstone>,
+    properties: TableProperties,
+}
+
+impl Table {
+    /// Opens the table at `path`, either reading blocks with positioned reads
+    /// or, with `mmap` set, slicing them out of a memory map of the file.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(fs, cache), fields(path = %path.display()))
+    )]
+    pub fn open(
+        fs: &dyn FileSystem,
+        path: &Path,
+        cache: Arc<BlockCache>,
+        verify: bool,
+        mmap: bool,
+    ) -> Result<Self, Error> {
+        let source = match mmap {
+            true => Source::Mmap(Arc::new(fs.map(path)?)),
+            false => Source::File(fs.open(path)?),
+        };
+        let footer = match &source {
+            Source::File(file) => {
+                let offset = file.len()?.saturating_sub(FOOTER_SIZE as u64);
+                read_at(file.as_ref(), offset, FOOTER_SIZE).and_then(|bytes| Footer::read(&bytes))
+            }
+            Source::Mmap(map) => Footer::read((***map).as_ref()),
+        }
+        .map_err(|e| e.in_file(path))?;
+
+        let mut table = Self {
+            source,
+            path: path.to_path_buf(),
+            id: cache.new_id(),
+            cache,
+            verify,
+            index: footer.index,
+            tombstones: Vec::new(),
+            properties: TableProperties::default(),
+        };
+        table.tombstones = db::deserialize(&table.read_block(footer.tombstones)?)?;
+        table.properties = db::deserialize(&table.read_block(footer.properties)?)?;
+        event!(
+            DEBUG,
+            entries = table.properties.entries,
+            data_blocks = table.properties.data_blocks,
+            tombstones = table.tombstones.len(),
+            "opened table"
+        );
+        Ok(table)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn tombstones(&self) -> &[RangeTombstone] {
+        &self.tombstones
+    }
+
+    pub fn properties(&self) -> &TableProperties {
+        &self.properties
+    }
+
+    /// The table's index: the last key of each data block and where the block
+    /// is stored.
+    pub fn index(&self) -> Result<Vec<IndexEntry>, Error> {
+        Ok(self.index_block()?.to_vec())
+    }
+
+    /// Whether `key` lies within the table's key range, short of which no
+    /// block needs reading to look it up.
+    pub fn may_contain(&self, key: &str) -> bool {
+        match (&self.properties.smallest_key, &self.properties.largest_key) {
+            (Some(smallest), Some(largest)) => smallest.as_str() <= key && key <= largest.as_str(),
+            _ => false,
+        }
+    }
+
+    pub fn lookup<S: AsRef<str>>(&self, key: S) -> Result<Lookup, Error> {
+        let key = key.as_ref();
+        match self.may_contain(key) {
+            true => self.lookup_in_range(key),
+            false => Ok(self.lookup_tombstones(key)),
+        }
+    }
+
+    /// Like `lookup`, for a key `may_contain` already placed in the table's
+    /// key range.
+    pub(crate) fn lookup_in_range(&self, key: &str) -> Result<Lookup, Error> {
+        let index = self.index_block()?;
+        let i = index.partition_point(|e| e.last_key.as_str() < key);
+        if let Some(entry) = index.get(i) {
+            match self.data_block(entry.handle)?.get(key)? {
+                Some(Value::Tombstone) => return Ok(Lookup::Deleted),
+                Some(value) => return Ok(Lookup::Found(value)),
+                None => {}
+            }
+        }
+        Ok(self.lookup_tombstones(key))
+    }
+
+    /// Whether the range tombstones of the table delete a key it holds no
+    /// entry for. They may reach past its key range.
+    pub(crate) fn lookup_tombstones(&self, key: &str) -> Lookup {
+        match self.tombstones.iter().any(|t| t.covers(key)) {
+            true => Lookup::Deleted,
+            false => Lookup::Absent,
+        }
+    }
+
+    /// Reads the entries in `range`, touching only the blocks that overlap it.
+    pub fn range<R: RangeBounds<String>>(&self, range: &R) -> Result<Vec<Entry>, Error> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        self.iter(range)?.collect()
+    }
+
+    /// Reads the entries in `range` lazily, a block at a time, so blocks
+    /// past the last entry taken are never read.
+    pub fn iter<R: RangeBounds<String>>(&self, range: R) -> Result<TableIter<'_>, Error> {
+        let index = self.index_block()?;
+        let first = match range.start_bound() {
+            _ if db::is_empty_range(&range) => index.len(),
+            Bound::Included(start) | Bound::Excluded(start) => {
+                index.partition_point(|e| &e.last_key < start)
+            }
+            Bound::Unbounded => 0,
+        };
+        Ok(TableIter {
+            table: self,
+            index,
+            range: (range.start_bound().cloned(), range.end_bound().cloned()),
+            first,
+            next_block: first,
+            entries: Vec::new().into_iter(),
+        })
+    }
+
+    pub fn entries(&self) -> Result<Vec<Entry>, Error> {
+        self.range(&..)
+    }
+
+    /// Whether every entry and tombstone in this table falls inside
+    /// `tombstone`, in which case a newer `tombstone` makes the whole table
+    /// obsolete.
+    pub fn covered_by(&self, tombstone: &RangeTombstone) -> bool {
+        let entries = match (&self.properties.smallest_key, &self.properties.largest_key) {
+            (Some(smallest), Some(largest)) => {
+                tombstone.covers(smallest) && tombstone.covers(largest)
+            }
+            _ => true,
+        };
+        entries && self.tombstones.iter().all(|t| tombstone.contains(t))
+    }
+
+    fn index_block(&self) -> Result<Arc<Vec<IndexEntry>>, Error> {
+        if let Some(Block::Index(index)) = self.cache.get(&(self.id, self.index.offset)) {
+            return Ok(index);
+        }
+        let bytes = self.read_block(self.index)?;
+        let index = Arc::new(db::deserialize(&bytes)?);
+        let block = Block::Index(Arc::clone(&index));
+        self.cache
+            .insert((self.id, self.index.offset), block, bytes.len());
+        Ok(index)
+    }
+
+    fn data_block(&self, handle: BlockHandle) -> Result<Arc<DataBlock>, Error> {
+        if let Some(Block::Data(block)) = self.cache.get(&(self.id, handle.offset)) {
+            return Ok(block);
+        }
+        let data = match (&self.source, self.read_block(handle)?) {
+            // Uncompressed blocks are read in place, right after their header.
+            (Source::Mmap(map), Cow::Borrowed(bytes)) => {
+                let start = handle.offset as usize + block::HEADER_SIZE;
+                BlockData::Mapped(Arc::clone(map), start..start + bytes.len())
+            }
+            (_, bytes) => BlockData::Owned(bytes.into_owned()),
+        };
+        let block =
+            Arc::new(DataBlock::new(data, handle.offset).map_err(|e| e.in_file(&self.path))?);
+        self.cache.insert(
+            (self.id, handle.offset),
+            Block::Data(Arc::clone(&block)),
+            block.size(),
+        );
+        Ok(block)
+    }
+
+    fn read_block(&self, handle: BlockHandle) -> Result<Cow<'_, [u8]>, Error> {
+        event!(
+            TRACE,
+            path = %self.path.display(),
+            offset = handle.offset,
+            size = handle.size,
+            "reading block"
+        );
+        let block = match &self.source {
+            Source::File(file) => read_at(file.as_ref(), handle.offset, handle.size as usize)
+                .and_then(|bytes| {
+                    block::decode_block(&bytes, handle.offset, self.verify)
+                        .map(|b| Cow::Owned(b.into_owned()))
+                }),
+            Source::Mmap(map) => block::read_block((***map).as_ref(), handle, self.verify),
+        };
+        block.map_err(|e| e.in_file(&self.path))
+    }
+}
+
+pub struct TableIter<'a> {
+    table: &'a Table,
+    index: Arc<Vec<IndexEntry>>,
+    range: (Bound<String>, Bound<String>),
+    first: usize,
+    next_block: usize,
+    /// What is left of the entries of the last block read.
+    entries: std::vec::IntoIter<Entry>,
+}
+
+impl TableIter<'_> {
+    fn read_block(&mut self, i: usize) -> Result<Vec<Entry>, Error> {
+        let entry = &self.index[i];
+        let block = self.table.data_block(entry.handle)?;
+        let iter = match (i == self.first, &self.range.0) {
+            (true, Bound::Included(start) | Bound::Excluded(start)) => block.seek(start)?,
+            _ => block.iter(),
+        };
+        let mut entries = Vec::new();
+        for entry in iter {
+            let entry = entry?;
+            if self.range.contains(&entry.key) {
+                entries.push(entry);
+            }
+        }
+
+        self.next_block = match &self.range.1 {
+            Bound::Included(end) | Bound::Excluded(end) if &entry.last_key >= end => {
+                self.index.len()
+            }
+            _ => i + 1,
+        };
+        Ok(entries)
+    }
+}
+
+impl Iterator for TableIter<'_> {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(Ok(entry));
+            }
+            let i = self.next_block;
+            if i >= self.index.len() {
+                return None;
+            }
+            match self.read_block(i) {
+                Ok(entries) => self.entries = entries.into_iter(),
+                Err(e) => {
+                    self.next_block = self.index.len();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+fn read_at(file: &dyn RandomAccessFile, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![0; len];
+    match file.read_at(&mut buf, offset) {
+        Ok(()) => Ok(buf),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Err(Error::Corruption {
+            file: PathBuf::new(),
+            offset,
+        }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression::Compression;
+    use crate::db::{MemTable, SSTable, Value};
+    use crate::env::OsFileSystem;
+    use crate::options::ColumnFamilyOptions;
+
+    use super::*;
+
+    fn open(dir: &Path, cache: &Arc<BlockCache>, compression: Compression, mmap: bool) -> Table {
+        let mut m = MemTable::new();
+        for i in 0..1000 {
+            m.write(format!("key/{:04}", i), i.to_string().into_bytes());
+        }
+        m.delete_range(String::from("key/0100")..String::from("key/0200"));
+
+        let path = dir.join("1.sst");
+        let (bytes, _) = SSTable::from(&m)
+            .into_bytes(&ColumnFamilyOptions {
+                compression,
+                block_size: 512,
+                ..ColumnFamilyOptions::default()
+            })
+            .unwrap();
+        std::fs::write(&path, bytes).unwrap();
+        Table::open(&OsFileSystem, &path, Arc::clone(cache), true, mmap).unwrap()
+    }
+
+    #[test]
+    fn lookup_and_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Arc::new(BlockCache::default());
+        let table = open(dir.path(), &cache, Compression::Lz4, false);
+
+        assert_eq!(
+            Lookup::Found(Value::Inline(b"7".to_vec())),
+            table.lookup("key/0007").unwrap()
+        );
+        assert_eq!(
+            Lookup::Found(Value::Inline(b"999".to_vec())),
+            table.lookup("key/0999").unwrap()
+        );
+        assert_eq!(Lookup::Deleted, table.lookup("key/0150").unwrap());
+        assert_eq!(Lookup::Absent, table.lookup("zzz").unwrap());
+
+        let range = table
+            .range(&(String::from("key/0495")..String::from("key/0505")))
+            .unwrap();
+        assert_eq!(10, range.len());
+        assert_eq!("key/0495", range[0].key);
+        assert_eq!(900, table.entries().unwrap().len());
+        assert_eq!(Some("key/0000"), table.properties().smallest_key.as_deref());
+
+        let tombstone = |start: &str, end: &str| RangeTombstone {
+            start: String::from(start),
+            end: String::from(end),
+        };
+        assert!(table.covered_by(&tombstone("key/", "key0")));
+        assert!(!table.covered_by(&tombstone("key/0001", "key0")));
+        assert!(!table.covered_by(&tombstone("key/0150", "key0")));
+    }
+
+    #[test]
+    fn blocks_are_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Arc::new(BlockCache::default());
+        let table = open(dir.path(), &cache, Compression::Lz4, false);
+
+        table.lookup("key/0500").unwrap();
+        let misses = cache.misses();
+        table.lookup("key/0500").unwrap();
+        assert_eq!(misses, cache.misses());
+        assert_eq!(2, cache.hits());
+        assert!(cache.usage() > 0);
+    }
+
+    #[test]
+    fn mmap_survives_unlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Arc::new(BlockCache::new(0));
+        let table = open(dir.path(), &cache, Compression::Lz4, true);
+        std::fs::remove_file(table.path()).unwrap();
+
+        assert_eq!(
+            Lookup::Found(Value::Inline(b"7".to_vec())),
+            table.lookup("key/0007").unwrap()
+        );
+        assert_eq!(900, table.entries().unwrap().len());
+        assert_eq!(0, cache.usage());
+    }
+
+    #[test]
+    fn mapped_blocks_are_not_copied() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Arc::new(BlockCache::default());
+        // Only compressed blocks need a buffer to be decompressed into.
+        for (compression, shared) in [(Compression::None, true), (Compression::Lz4, false)] {
+            let table = open(dir.path(), &cache, compression, true);
+            let Source::Mmap(map) = &table.source else {
+                unreachable!()
+            };
+            let blocks: Vec<_> = table
+                .index()
+                .unwrap()
+                .iter()
+                .map(|entry| table.data_block(entry.handle).unwrap())
+                .collect();
+            let references = match shared {
+                true => 1 + blocks.len(),
+                false => 1,
+            };
+            assert_eq!(references, Arc::strong_count(map));
+            assert_eq!(
+                Lookup::Found(Value::Inline(b"7".to_vec())),
+                table.lookup("key/0007").unwrap()
+            );
+            assert_eq!(900, table.entries().unwrap().len());
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Instrumentation that compiles away unless the `tracing` feature is enabled.
+//! Spans are added with
+//! `#[cfg_attr(feature = "tracing", tracing::instrument(...))]`, events with
+//! `event!`, which takes the level followed by the arguments of
+//! `tracing::event!`.
+
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($arg)+);
+    };
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::batch::Operation;
+    use crate::driver::Driver;
+    use crate::env::MemoryFileSystem;
+    use crate::options::Options;
+
+    /// Records the names of spans and the messages of events.
+    #[derive(Default)]
+    struct Recorder {
+        next_id: AtomicU64,
+        names: Arc<Mutex<Vec<String>>>,
+    }
+
+    struct Message<'a>(&'a mut Option<String>);
+
+    impl tracing::field::Visit for Message<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                *self.0 = Some(format!("{:?}", value));
+            }
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            self.names
+                .lock()
+                .unwrap()
+                .push(span.metadata().name().to_owned());
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut message = None;
+            event.record(&mut Message(&mut message));
+            self.names.lock().unwrap().extend(message);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn flush_and_compaction_are_traced() {
+        let recorder = Recorder::default();
+        let names = Arc::clone(&recorder.names);
+        tracing::subscriber::with_default(recorder, || {
+            let options = Options {
+                file_system: Arc::new(MemoryFileSystem::new()),
+                ..Options::default()
+            };
+            let mut driver = Driver::load("/db".into(), options).unwrap();
+            for i in 0..2 {
+                let operations = vec![(
+                    0,
+                    Operation::Put {
+                        key: i.to_string(),
+                        value: vec![i],
+                    },
+                )];
+                driver.commit(operations, false).unwrap();
+                driver.flush_all().unwrap();
+            }
+            driver.compact_column_family(0).unwrap();
+        });
+
+        let names = names.lock().unwrap();
+        for name in [
+            "load",
+            "commit",
+            "appended WAL record",
+            "flush",
+            "encoded table",
+            "wrote SSTable",
+            "flushed memtable",
+            "compact_column_family",
+            "merging tables",
+            "compaction completed",
+        ] {
+            assert!(names.iter().any(|n| n == name), "{} not traced", name);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::db::ValuePointer;
+use crate::env::{FileSystem, RandomAccessFile, WritableFile};
+use crate::Error;
+
+pub(crate) const VALUE_LOG_EXTENSION: &str = "vlog";
+
+/// Key length, value length and the CRC32C of both.
+const HEADER_SIZE: usize = 4 + 4 + 4;
+
+pub fn value_log_path(path: &Path, number: usize) -> PathBuf {
+    path.join(format!("{}.{}", number, VALUE_LOG_EXTENSION))
+}
+
+/// A record read back from a value log, along with the pointer locating it.
+#[derive(Debug)]
+pub struct Record {
+    pub pointer: ValuePointer,
+    pub key: String,
+    pub value: Vec<u8>,
+}
+
+/// A file of values separated from their keys. Values are appended once,
+/// when a memtable is flushed, and the file is only read afterwards. Each
+/// record keeps its key so the garbage collector can tell whether the tree
+/// still points at it.
+pub struct ValueLog {
+    file: Box<dyn RandomAccessFile>,
+    /// Present only while the log is being written by a flush.
+    writer: Option<Box<dyn WritableFile>>,
+    path: PathBuf,
+    number: usize,
+    size: u64,
+}
+
+impl ValueLog {
+    pub fn create(fs: &dyn FileSystem, path: &Path, number: usize) -> Result<Self, Error> {
+        let path = value_log_path(path, number);
+        if fs.exists(&path) {
+            return Err(std::io::Error::from(ErrorKind::AlreadyExists).into());
+        }
+        let writer = fs.create(&path)?;
+        Ok(Self {
+            file: fs.open(&path)?,
+            writer: Some(writer),
+            path,
+            number,
+            size: 0,
+        })
+    }
+
+    pub fn open(fs: &dyn FileSystem, path: &Path, number: usize) -> Result<Self, Error> {
+        let path = value_log_path(path, number);
+        let file = fs.open(&path)?;
+        let size = file.len()?;
+        Ok(Self {
+            file,
+            writer: None,
+            path,
+            number,
+            size,
+        })
+    }
+
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn append(&mut self, key: &str, value: &[u8]) -> Result<ValuePointer, Error> {
+        let mut buf = Vec::with_capacity(HEADER_SIZE + key.len() + value.len());
+        buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(key.as_bytes());
+        buf.extend_from_slice(value);
+        let checksum = crc32c::crc32c(&buf[HEADER_SIZE..]);
+        buf[8..HEADER_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        match &mut self.writer {
+            Some(writer) => writer.append(&buf)?,
+            None => return Err(std::io::Error::from(ErrorKind::PermissionDenied).into()),
+        }
+
+        let pointer = ValuePointer {
+            file: self.number,
+            offset: self.size,
+            size: buf.len() as u32,
+        };
+        self.size += buf.len() as u64;
+        Ok(pointer)
+    }
+
+    /// Makes the appended values durable. A value log must be synced before
+    /// any table pointing into it.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        if let Some(writer) = &mut self.writer {
+            writer.sync()?;
+        }
+        Ok(())
+    }
+
+    pub fn read(&self, pointer: &ValuePointer) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![0; pointer.size as usize];
+        match self.file.read_at(&mut buf, pointer.offset) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                return Err(self.corruption(pointer.offset))
+            }
+            Err(e) => return Err(e.into()),
+        }
+        match decode(&buf) {
+            Some((_, value, size)) if size == buf.len() => Ok(value.to_vec()),
+            _ => Err(self.corruption(pointer.offset)),
+        }
+    }
+
+    /// Reads every record of the log, failing at the first damaged one.
+    pub fn records(&self) -> Result<Vec<Record>, Error> {
+        match self.salvage()? {
+            (records, None) => Ok(records),
+            (_, Some(e)) => Err(e),
+        }
+    }
+
+    /// Reads the records of the log up to the first damaged one, returning
+    /// them along with the corruption that ended the read, if any.
+    pub fn salvage(&self) -> Result<(Vec<Record>, Option<Error>), Error> {
+        let mut bytes = vec![0; self.size as usize];
+        self.file.read_at(&mut bytes, 0)?;
+
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let Some((key, value, size)) = decode(&bytes[offset..]) else {
+                return Ok((records, Some(self.corruption(offset as u64))));
+            };
+            let Ok(key) = String::from_utf8(key.to_vec()) else {
+                return Ok((records, Some(self.corruption(offset as u64))));
+            };
+            records.push(Record {
+                pointer: ValuePointer {
+                    file: self.number,
+                    offset: offset as u64,
+                    size: size as u32,
+                },
+                key,
+                value: value.to_vec(),
+            });
+            offset += size;
+        }
+        Ok((records, None))
+    }
+
+    fn corruption(&self, offset: u64) -> Error {
+        Error::Corruption {
+            file: self.path.clone(),
+            offset,
+        }
+    }
+}
+
+/// Decodes the record at the start of `bytes` into its key, its value and
+/// its size, or `None` if it is truncated or fails its checksum.
+fn decode(bytes: &[u8]) -> Option<(&[u8], &[u8], usize)> {
+    let header = bytes.get(..HEADER_SIZE)?;
+    let key_len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let value_len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header[8..].try_into().unwrap());
+
+    let size = HEADER_SIZE + key_len + value_len;
+    let body = bytes.get(HEADER_SIZE..size)?;
+    match crc32c::crc32c(body) == checksum {
+        true => Some((&body[..key_len], &body[key_len..], size)),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::MemoryFileSystem;
+
+    use super::*;
+
+    #[test]
+    fn append_and_read() {
+        let fs = MemoryFileSystem::new();
+        let dir = Path::new("/db");
+        let mut log = ValueLog::create(&fs, dir, 3).unwrap();
+        let first = log.append("a", &[1; 5000]).unwrap();
+        let second = log.append("b", b"second").unwrap();
+        assert_eq!(first.offset + first.size as u64, second.offset);
+
+        assert!(ValueLog::create(&fs, dir, 3).is_err());
+        let log = ValueLog::open(&fs, dir, 3).unwrap();
+        assert_eq!(vec![1; 5000], log.read(&first).unwrap());
+        assert_eq!(b"second".to_vec(), log.read(&second).unwrap());
+
+        let records = log.records().unwrap();
+        assert_eq!(2, records.len());
+        assert_eq!("b", records[1].key);
+        assert_eq!(second, records[1].pointer);
+    }
+
+    #[test]
+    fn damaged_record() {
+        let fs = MemoryFileSystem::new();
+        let dir = Path::new("/db");
+        let mut log = ValueLog::create(&fs, dir, 1).unwrap();
+        let first = log.append("a", b"first").unwrap();
+        let second = log.append("b", b"second").unwrap();
+
+        let path = value_log_path(dir, 1);
+        let mut bytes = fs.read(&path).unwrap();
+        bytes[second.offset as usize + HEADER_SIZE] ^= 1;
+        fs.create(&path).unwrap().append(&bytes).unwrap();
+
+        let log = ValueLog::open(&fs, dir, 1).unwrap();
+        assert_eq!(b"first".to_vec(), log.read(&first).unwrap());
+        assert!(matches!(
+            log.read(&second),
+            Err(Error::Corruption { offset, .. }) if offset == second.offset
+        ));
+        let (records, corruption) = log.salvage().unwrap();
+        assert_eq!(1, records.len());
+        assert!(corruption.is_some());
+    }
+}
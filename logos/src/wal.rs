@@ -0,0 +1,249 @@
+use std::path::{Path, PathBuf};
+
+use crate::env::{FileSystem, WritableFile};
+use crate::Error;
+
+/// Record length followed by the record's CRC32C.
+pub(crate) const HEADER_SIZE: usize = 4 + 4;
+
+/// Records of a log along with the offset each was read from.
+pub(crate) type Records = Vec<(u64, Vec<u8>)>;
+
+const TMP_EXTENSION: &str = "tmp";
+
+pub fn wal_path(path: &Path, number: usize) -> PathBuf {
+    path.join(format!("{}.log", number))
+}
+
+/// An append-only log segment. Each record is stored as its length and
+/// checksum followed by its bytes, so a record torn by a crash is detected and
+/// dropped on replay, while a damaged record is reported as corruption.
+pub struct Wal {
+    file: Box<dyn WritableFile>,
+    number: usize,
+    size: u64,
+}
+
+impl Wal {
+    pub fn create(fs: &dyn FileSystem, path: &Path, number: usize) -> Result<Self, Error> {
+        Self::create_file(fs, &wal_path(path, number), number)
+    }
+
+    /// Opens a log with the same record format under any file name, appending
+    /// to it if it already exists.
+    pub(crate) fn create_file(
+        fs: &dyn FileSystem,
+        path: &Path,
+        number: usize,
+    ) -> Result<Self, Error> {
+        let size = match fs.exists(path) {
+            true => fs.open(path)?.len()?,
+            false => 0,
+        };
+        let file = fs.append(path)?;
+        Ok(Self { file, number, size })
+    }
+
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Makes every record appended so far durable.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync()?;
+        Ok(())
+    }
+
+    /// Appends a record, returning the offset it was written at.
+    pub fn append(&mut self, record: &[u8]) -> Result<u64, Error> {
+        let mut buf = Vec::with_capacity(HEADER_SIZE + record.len());
+        buf.extend_from_slice(&(record.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&crc32c::crc32c(record).to_le_bytes());
+        buf.extend_from_slice(record);
+        self.file.append(&buf)?;
+
+        let offset = self.size;
+        self.size += buf.len() as u64;
+        Ok(offset)
+    }
+}
+
+pub fn read_segment(
+    fs: &dyn FileSystem,
+    path: &Path,
+    number: usize,
+) -> Result<Vec<Vec<u8>>, Error> {
+    match salvage_segment(fs, path, number)? {
+        (records, None) => Ok(records),
+        (_, Some(e)) => Err(e),
+    }
+}
+
+/// Reads the records of a segment up to the first damaged one, returning
+/// them along with the corruption that ended the read, if any.
+pub fn salvage_segment(
+    fs: &dyn FileSystem,
+    path: &Path,
+    number: usize,
+) -> Result<(Vec<Vec<u8>>, Option<Error>), Error> {
+    let (records, corruption) = salvage_file(fs, &wal_path(path, number))?;
+    let records = records.into_iter().map(|(_, record)| record).collect();
+    Ok((records, corruption))
+}
+
+/// Reads the segment that was being written when the database was last
+/// closed or crashed. A record failing its checksum with no intact record
+/// after it is taken for a write the crash interrupted rather than for
+/// corruption: the records before it are returned along with the offset it
+/// starts at, where the segment should be cut.
+pub(crate) fn read_last_segment(
+    fs: &dyn FileSystem,
+    path: &Path,
+    number: usize,
+) -> Result<(Vec<Vec<u8>>, Option<u64>), Error> {
+    let path = wal_path(path, number);
+    let bytes = fs.read(&path)?;
+    let (records, corruption) = decode_file(&bytes);
+    let records = records.into_iter().map(|(_, record)| record).collect();
+    let Some(offset) = corruption else {
+        return Ok((records, None));
+    };
+    let rest = &bytes[offset as usize..];
+    let length = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+    let next = rest.get(HEADER_SIZE + length..).unwrap_or_default();
+    match decode_record(next) {
+        Some(Ok(record)) if !record.is_empty() => Err(Error::Corruption { file: path, offset }),
+        _ => Ok((records, Some(offset))),
+    }
+}
+
+/// Cuts segment `number` to its first `len` bytes, replacing it through a
+/// temporary file so that a crash leaves either segment whole.
+pub(crate) fn truncate_segment(
+    fs: &dyn FileSystem,
+    path: &Path,
+    number: usize,
+    len: u64,
+    sync: bool,
+) -> Result<(), Error> {
+    let segment = wal_path(path, number);
+    let bytes = fs.read(&segment)?;
+    let tmp = segment.with_extension(TMP_EXTENSION);
+    let mut file = fs.create(&tmp)?;
+    file.append(&bytes[..len as usize])?;
+    if sync {
+        file.sync()?;
+    }
+    fs.rename(&tmp, &segment)?;
+    if sync {
+        fs.sync_dir(path)?;
+    }
+    Ok(())
+}
+
+/// Like `read_segment` for a log under any file name.
+pub(crate) fn read_file(fs: &dyn FileSystem, path: &Path) -> Result<Records, Error> {
+    match salvage_file(fs, path)? {
+        (records, None) => Ok(records),
+        (_, Some(e)) => Err(e),
+    }
+}
+
+/// Like `salvage_segment` for a log under any file name.
+pub(crate) fn salvage_file(
+    fs: &dyn FileSystem,
+    path: &Path,
+) -> Result<(Records, Option<Error>), Error> {
+    let bytes = fs.read(path)?;
+    let (records, corruption) = decode_file(&bytes);
+    let corruption = corruption.map(|offset| Error::Corruption {
+        file: path.to_path_buf(),
+        offset,
+    });
+    Ok((records, corruption))
+}
+
+/// Decodes the records of a log up to the first damaged one, returning the
+/// offset of that one if there is any.
+fn decode_file(bytes: &[u8]) -> (Records, Option<u64>) {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while bytes.len() - offset >= HEADER_SIZE {
+        // Space a file system extended the file by but never wrote reads
+        // back as zeros, which would otherwise pass for empty records.
+        if bytes[offset..].iter().all(|&b| b == 0) {
+            break;
+        }
+        match decode_record(&bytes[offset..]) {
+            Some(Ok(record)) => {
+                records.push((offset as u64, record.to_vec()));
+                offset += HEADER_SIZE + record.len();
+            }
+            Some(Err(())) => return (records, Some(offset as u64)),
+            None => break,
+        }
+    }
+    (records, None)
+}
+
+/// Decodes the record at the start of `bytes`, returning `None` if it is torn
+/// and an error if it fails its checksum.
+pub(crate) fn decode_record(bytes: &[u8]) -> Option<Result<&[u8], ()>> {
+    let header = bytes.get(..HEADER_SIZE)?;
+    let length = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+    let record = bytes.get(HEADER_SIZE..HEADER_SIZE + length)?;
+    match crc32c::crc32c(record) == checksum {
+        true => Some(Ok(record)),
+        false => Some(Err(())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::MemoryFileSystem;
+
+    use super::*;
+
+    #[test]
+    fn torn_record_is_dropped() {
+        let fs = MemoryFileSystem::new();
+        let mut wal = Wal::create(&fs, Path::new("/db"), 1).unwrap();
+        wal.append(b"first").unwrap();
+        wal.append(b"second").unwrap();
+        wal.file.append(&10u32.to_le_bytes()).unwrap();
+        wal.file.append(b"thi").unwrap();
+
+        assert_eq!(
+            read_segment(&fs, Path::new("/db"), 1).unwrap(),
+            vec![b"first".to_vec(), b"second".to_vec()]
+        );
+    }
+
+    #[test]
+    fn damaged_record() {
+        let fs = MemoryFileSystem::new();
+        let dir = Path::new("/db");
+        let mut wal = Wal::create(&fs, dir, 1).unwrap();
+        wal.append(b"first").unwrap();
+        wal.append(b"second").unwrap();
+        wal.append(b"third").unwrap();
+
+        let path = wal_path(dir, 1);
+        let mut bytes = fs.read(&path).unwrap();
+        bytes[2 * HEADER_SIZE + 5 + 1] ^= 1;
+        fs.create(&path).unwrap().append(&bytes).unwrap();
+
+        assert!(matches!(
+            read_segment(&fs, dir, 1),
+            Err(Error::Corruption { offset: 13, .. })
+        ));
+        let (records, corruption) = salvage_segment(&fs, dir, 1).unwrap();
+        assert_eq!(vec![b"first".to_vec()], records);
+        assert!(corruption.is_some());
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Condvar, Mutex, PoisonError};
+
+use crate::batch::WriteBatch;
+use crate::driver::Driver;
+use crate::options::WriteOptions;
+use crate::Error;
+
+/// Group commit for writers sharing a driver. Each write is queued with the
+/// next sequence number. The first writer to find no group in progress
+/// becomes the leader: once it holds the driver it takes every queued write,
+/// logs them as a single WAL record with at most one sync, applies them in
+/// sequence order and wakes the writers it committed for. Writes queued
+/// meanwhile join the next group, so under load one sync serves many writes.
+///
+/// A write naming an unknown column family fails alone. Any other failure
+/// fails the whole group, as does a panic of the leader, after which the
+/// next writer to queue leads again.
+#[derive(Debug, Default)]
+pub struct WriteQueue {
+    state: Mutex<State>,
+    committed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_sequence: u64,
+    pending: VecDeque<(u64, WriteBatch, bool)>,
+    leading: bool,
+    results: HashMap<u64, Result<(), Error>>,
+}
+
+impl WriteQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes a batch through the queue, blocking until it is committed.
+    pub fn write(
+        &self,
+        driver: &Mutex<Driver>,
+        batch: WriteBatch,
+        options: &WriteOptions,
+    ) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let sequence = state.next_sequence;
+        state.next_sequence += 1;
+        state.pending.push_back((sequence, batch, options.sync));
+
+        loop {
+            if let Some(result) = state.results.remove(&sequence) {
+                return result;
+            }
+            if !state.leading {
+                state.leading = true;
+                drop(state);
+                return self.lead(driver, sequence);
+            }
+            state = self.committed.wait(state).unwrap();
+        }
+    }
+
+    fn lead(&self, driver: &Mutex<Driver>, sequence: u64) -> Result<(), Error> {
+        let mut leader = Leader {
+            queue: self,
+            group: Vec::new(),
+        };
+        let driver = driver.lock();
+        // Writes queued while waiting for the driver join the group. They,
+        // the leader's own included, leave the queue before a poisoned
+        // driver panics, so that the panic fails all of them rather than
+        // leaving them for the next leader to commit.
+        let group: Vec<_> = self.state.lock().unwrap().pending.drain(..).collect();
+        leader.group = group
+            .iter()
+            .map(|(other, _, _)| *other)
+            .filter(|&other| other != sequence)
+            .collect();
+        let mut driver = driver.unwrap();
+
+        let mut results = HashMap::with_capacity(group.len());
+        let mut committed = Vec::with_capacity(group.len());
+        let mut operations = Vec::new();
+        let mut sync = false;
+        for (sequence, batch, sync_batch) in group {
+            match driver.resolve(batch) {
+                Ok(resolved) => {
+                    operations.extend(resolved);
+                    sync |= sync_batch;
+                    committed.push(sequence);
+                }
+                Err(e) => {
+                    results.insert(sequence, Err(e));
+                }
+            }
+        }
+        if !committed.is_empty() {
+            let result = driver.commit(operations, sync);
+            for sequence in committed {
+                let result = result.as_ref().map_err(Error::duplicate).copied();
+                results.insert(sequence, result);
+            }
+        }
+        drop(driver);
+
+        let result = results.remove(&sequence).unwrap();
+        self.state.lock().unwrap().results.extend(results);
+        leader.group.clear();
+        result
+    }
+}
+
+/// Hands leadership back when the leader is done, even if it panics, in
+/// which case the other writers of its group fail instead of waiting
+/// forever.
+struct Leader<'a> {
+    queue: &'a WriteQueue,
+    group: Vec<u64>,
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        let queue = self.queue;
+        let mut state = queue.state.lock().unwrap_or_else(PoisonError::into_inner);
+        for &sequence in &self.group {
+            let e = io::Error::other("the leader of the group commit panicked");
+            state.results.insert(sequence, Err(e.into()));
+        }
+        state.leading = false;
+        queue.committed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::batch::Operation;
+    use crate::driver::{self, WAL_EXTENSION};
+    use crate::env::{FileSystem, MemoryFileSystem};
+    use crate::options::Options;
+    use crate::wal;
+
+    use super::*;
+
+    fn open_on(fs: Arc<dyn FileSystem>) -> Arc<Mutex<Driver>> {
+        let options = Options {
+            file_system: fs,
+            ..Options::default()
+        };
+        let driver = Driver::open_with_options(Path::new("/db"), options).unwrap();
+        Arc::new(Mutex::new(driver))
+    }
+
+    fn open() -> Arc<Mutex<Driver>> {
+        open_on(Arc::new(MemoryFileSystem::new()))
+    }
+
+    #[test]
+    fn queued_writes_share_a_sync() {
+        let driver = open();
+        let queue = Arc::new(WriteQueue::new());
+
+        // Hold the driver so that every write queues behind the first.
+        let guard = driver.lock().unwrap();
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let (driver, queue) = (Arc::clone(&driver), Arc::clone(&queue));
+                thread::spawn(move || {
+                    let mut batch = WriteBatch::new();
+                    batch.put(format!("key/{}", i), vec![i]);
+                    if i == 3 {
+                        batch.put_cf("missing", String::from("key"), Vec::new());
+                    }
+                    queue.write(&driver, batch, &WriteOptions { sync: true })
+                })
+            })
+            .collect();
+        while queue.state.lock().unwrap().pending.len() < 8 {
+            thread::yield_now();
+        }
+        drop(guard);
+
+        for (i, writer) in writers.into_iter().enumerate() {
+            let result = writer.join().unwrap();
+            match i {
+                3 => assert!(matches!(result, Err(Error::ColumnFamilyNotFound(_)))),
+                _ => result.unwrap(),
+            }
+        }
+        let driver = driver.lock().unwrap();
+        assert_eq!(1, driver.stats().wal_records);
+        assert_eq!(1, driver.stats().wal_syncs);
+        assert_eq!(None, driver.read("key/3").unwrap());
+        assert_eq!(Some(vec![7]), driver.read("key/7").unwrap());
+    }
+
+    #[test]
+    fn writes_apply_in_sequence_order() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let driver = open_on(Arc::clone(&fs));
+        let queue = Arc::new(WriteQueue::new());
+
+        // Holding the driver until every writer has queued makes the first
+        // group take a write of each.
+        let guard = driver.lock().unwrap();
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let (driver, queue) = (Arc::clone(&driver), Arc::clone(&queue));
+                thread::spawn(move || {
+                    for n in 0..100u32 {
+                        let mut batch = WriteBatch::new();
+                        batch.put(format!("key/{}", i), n.to_le_bytes().to_vec());
+                        let options = WriteOptions {
+                            sync: n.is_multiple_of(10),
+                        };
+                        queue.write(&driver, batch, &options).unwrap();
+                    }
+                })
+            })
+            .collect();
+        while queue.state.lock().unwrap().pending.len() < 4 {
+            thread::yield_now();
+        }
+        drop(guard);
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let driver = driver.lock().unwrap();
+        for i in 0..4 {
+            let value = driver.read(format!("key/{}", i)).unwrap();
+            assert_eq!(Some(99u32.to_le_bytes().to_vec()), value);
+        }
+        assert!(driver.stats().wal_records < 400);
+
+        // Each writer's values reach the WAL in the order it wrote them.
+        let mut logged: HashMap<String, Vec<u32>> = HashMap::new();
+        let dir = Path::new("/db");
+        for number in driver::list_files(&*fs, dir, WAL_EXTENSION).unwrap() {
+            for record in wal::read_segment(&*fs, dir, number).unwrap() {
+                let operations: Vec<(u32, Operation)> = bincode::deserialize(&record).unwrap();
+                for (_, operation) in operations {
+                    let Operation::Put { key, value } = operation else {
+                        panic!("unexpected {:?}", operation);
+                    };
+                    let value = u32::from_le_bytes(value.try_into().unwrap());
+                    logged.entry(key).or_default().push(value);
+                }
+            }
+        }
+        assert_eq!(4, logged.len());
+        for values in logged.values() {
+            assert_eq!((0..100).collect::<Vec<u32>>(), *values);
+        }
+    }
+
+    #[test]
+    fn a_panicking_leader_hands_back_leadership() {
+        let driver = open();
+        let queue = Arc::new(WriteQueue::new());
+        let write = |key: &str| {
+            let (driver, queue) = (Arc::clone(&driver), Arc::clone(&queue));
+            let key = String::from(key);
+            thread::spawn(move || {
+                let mut batch = WriteBatch::new();
+                batch.put(key, vec![1]);
+                queue.write(&driver, batch, &WriteOptions::default())
+            })
+            .join()
+        };
+
+        // A leader finding the driver poisoned panics while leading.
+        let poisoner = Arc::clone(&driver);
+        let _ = thread::spawn(move || {
+            let _driver = poisoner.lock().unwrap();
+            panic!("poisons the driver");
+        })
+        .join();
+        assert!(write("a").is_err());
+
+        driver.clear_poison();
+        write("b").unwrap().unwrap();
+        assert!(!queue.state.lock().unwrap().leading);
+        let driver = driver.lock().unwrap();
+        assert_eq!(None, driver.read("a").unwrap());
+        assert_eq!(Some(vec![1]), driver.read("b").unwrap());
+    }
+}
@@ -56,7 +56,7 @@ impl<T: Ord> BinaryTreeNode<T> {
                 Some(right) => right.find(item),
                 None => None,
             },
-            Ordering::Equal => return Some(self),
+            Ordering::Equal => Some(self),
         }
     }
 
@@ -67,9 +67,9 @@ impl<T: Ord> AVLNode<T> for BinaryTreeNode<T> {
     fn balance(&self) -> Balance {
         let (lh, rh) = self.child_heights();
         match lh - rh {
-            i32::MIN..=-2 => Balance::RightHeavy,
-            -1..=1 => Balance::Balanced,
-            2..=i32::MAX => Balance::LeftHeavy,
+            d if d < -Self::THRESHOLD => Balance::RightHeavy,
+            d if d > Self::THRESHOLD => Balance::LeftHeavy,
+            _ => Balance::Balanced,
         }
     }
 
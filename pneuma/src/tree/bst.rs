@@ -57,7 +57,7 @@ impl<T: Ord> BinarySearchTree<T> {
     pub(super) fn contains(&self, item: &T) -> bool {
         self.root
             .as_ref()
-            .map(|r| r.find(&item).is_some())
+            .map(|r| r.find(item).is_some())
             .unwrap_or_default()
     }
 
@@ -66,7 +66,7 @@ impl<T: Ord> BinarySearchTree<T> {
     }
 
     #[cfg(test)]
-    pub(super) fn level_iter(&self) -> LevelIter<T> {
+    pub(super) fn level_iter(&self) -> LevelIter<'_, T> {
         LevelIter {
             curr: self.root.as_ref(),
             queue: VecDeque::with_capacity(10),
@@ -74,7 +74,7 @@ impl<T: Ord> BinarySearchTree<T> {
     }
 
     #[cfg(test)]
-    pub(super) fn nodes_iter(&self) -> NodeIter<T> {
+    pub(super) fn nodes_iter(&self) -> NodeIter<'_, T> {
         NodeIter {
             curr: self.root.as_ref(),
             queue: VecDeque::with_capacity(10),
@@ -81,7 +81,7 @@ impl<'a, T: Ord> Iterator for NodeIter<'a, T> {
     type Item = &'a BoxedNode<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next = self.curr.take().map(|n| {
+        let next = self.curr.take().inspect(|n| {
             if let Some(left) = n.left.as_ref() {
                 self.queue.push_back(left);
             }
@@ -89,7 +89,6 @@ impl<'a, T: Ord> Iterator for NodeIter<'a, T> {
             if let Some(right) = n.right.as_ref() {
                 self.queue.push_back(right);
             }
-            n
         });
 
         self.curr = self.queue.pop_front();